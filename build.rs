@@ -20,4 +20,6 @@ use json::object;
     text.push_str(&gen_completion_stdlib(&stdlib_md));
 
     fs::write("src/gen/completion.rs", text).expect("Write failed");
+
+    fs::write("src/gen/signatures.rs", gen_stdlib_signatures(&stdlib_md)).expect("Write failed");
 }
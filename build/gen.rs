@@ -185,3 +185,328 @@ pub fn bpftrace_stdlib_functions(items: &mut json::JsonValue) {
 
     text
 }
+
+// Return type from signature like "uint32 pid([curr_ns|init])" or "uint32 pid"
+fn signature_return_type(signature: &str, name: &str) -> Option<String> {
+    let name_pos = signature
+        .match_indices(name)
+        .map(|(pos, _)| pos)
+        .find(|&pos| {
+            let after = &signature[pos + name.len()..];
+            let before = &signature[..pos];
+            (after.is_empty() || after.starts_with('('))
+                && (before.is_empty() || before.ends_with(' ') || before.ends_with('*'))
+        })?;
+
+    let ret_type = signature[..name_pos].trim();
+    if ret_type.is_empty() {
+        return None;
+    }
+
+    Some(ret_type.to_string())
+}
+
+#[derive(Default, Debug)]
+struct ParamSpec {
+    name: String,
+    type_name: String,
+    optional: bool,
+    literal: bool,
+    choices: Vec<String>,
+}
+
+#[derive(Default, Debug)]
+struct OverloadSpec {
+    ret: String,
+    params: Vec<ParamSpec>,
+    variadic: bool,
+}
+
+#[derive(Default, Debug)]
+struct SignatureSpec {
+    name: String,
+    overloads: Vec<OverloadSpec>,
+    allowed_probes: Vec<String>,
+}
+
+// Parameters which have to be literals, but are not documented as const
+const LITERAL_PARAMS: &[(&str, &[usize])] = &[("hist", &[1]), ("lhist", &[1, 2, 3]), ("tseries", &[1, 2, 3])];
+
+// Functions which can't be used in some probe types, not documented in stdlib.md
+const DISALLOWED_PROBES: &[(&str, &[&str])] = &[
+    ("kstack", &["begin", "end", "test", "bench"]),
+    ("ustack", &["begin", "end", "test", "bench"]),
+];
+
+// "Supported probes" names to provider names
+fn doc_probe_to_providers(doc_probe: &str) -> Vec<String> {
+    let name = doc_probe.trim().trim_end_matches('s').to_lowercase();
+    let providers: &[&str] = match name.as_str() {
+        "k(ret)probe" => &["kprobe", "kretprobe"],
+        "u(ret)probe" => &["uprobe", "uretprobe"],
+        "kprobe" => &["kprobe"],
+        "kretprobe" => &["kretprobe"],
+        "uprobe" => &["uprobe"],
+        "uretprobe" => &["uretprobe"],
+        "usdt" => &["usdt"],
+        "profile" => &["profile"],
+        "tracepoint" => &["tracepoint"],
+        _ => &[],
+    };
+    providers.iter().map(|p| p.to_string()).collect()
+}
+
+// Split parameters like "int64 n[, int k]" or "[StackMode mode, ][int limit]"
+fn parse_params(params_str: &str, overload: &mut OverloadSpec) {
+    if params_str.trim().is_empty() {
+        return;
+    }
+
+    let mut depth: i32 = 0;
+    for piece in params_str.split(',') {
+        let mut piece = piece.trim();
+        while let Some(rest) = piece.strip_prefix(']') {
+            depth -= 1;
+            piece = rest.trim_start();
+        }
+
+        let optional = depth > 0 || piece.starts_with('[');
+        depth += piece.matches('[').count() as i32 - piece.matches(']').count() as i32;
+
+        // Drop brackets of optional parameters, but keep arrays like "char addr[4]"
+        let mut clean = piece.trim_start_matches('[').trim();
+        while clean.ends_with(']') && clean.matches('[').count() < clean.matches(']').count() {
+            clean = clean[..clean.len() - 1].trim_end();
+        }
+        let clean = clean.split('=').next().unwrap_or_default();
+        let clean = clean.trim().trim_end_matches('[').trim();
+        if clean.is_empty() {
+            continue;
+        }
+        if clean.contains("...") {
+            overload.variadic = true;
+            continue;
+        }
+
+        let (mut type_name, mut name) = match clean.rsplit_once(' ') {
+            Some((type_name, name)) => (type_name.trim().to_string(), name.trim()),
+            None => (String::new(), clean),
+        };
+        // "char *arr[]"
+        while let Some(rest) = name.strip_prefix('*') {
+            type_name.push_str(" *");
+            name = rest;
+        }
+
+        // Keyword alternatives like "[curr_ns|init]", the first one is the default
+        let choices: Vec<String> = match name.contains('|') {
+            true => name.split('|').map(|c| c.trim().to_string()).collect(),
+            false => Vec::new(),
+        };
+        let name = choices.first().map_or(name, |c| c.as_str());
+
+        overload.params.push(ParamSpec {
+            name: name.split('[').next().unwrap_or(name).to_string(),
+            literal: type_name.starts_with("const "),
+            type_name,
+            optional,
+            choices,
+        });
+    }
+}
+
+fn parse_overload(signature: &str, name: &str) -> Option<OverloadSpec> {
+    let ret = signature_return_type(signature, name)?;
+
+    let after_name = &signature[signature.find(&format!("{}(", name))? + name.len() + 1..];
+    let params_str = after_name.rsplit_once(')').map_or(after_name, |(p, _)| p);
+
+    let mut overload = OverloadSpec {
+        ret,
+        ..Default::default()
+    };
+    parse_params(params_str, &mut overload);
+
+    if let Some((_, literal_idx)) = LITERAL_PARAMS.iter().find(|(n, _)| *n == name) {
+        for idx in literal_idx.iter() {
+            if let Some(param) = overload.params.get_mut(*idx) {
+                param.literal = true;
+            }
+        }
+    }
+
+    Some(overload)
+}
+
+fn parse_stdlib_signatures(stdlib_md: &str) -> Vec<SignatureSpec> {
+    let mut signatures: Vec<SignatureSpec> = Vec::new();
+    let mut after_label = false;
+    let mut in_supported_probes = false;
+
+    for line in stdlib_md.lines() {
+        let trimmed = line.trim();
+
+        if let Some(name) = trimmed.strip_prefix("### ") {
+            signatures.push(SignatureSpec {
+                name: name.trim().to_string(),
+                ..Default::default()
+            });
+            after_label = true;
+            in_supported_probes = false;
+            continue;
+        }
+        let Some(signature) = signatures.last_mut() else {
+            continue;
+        };
+
+        if after_label {
+            let item = trimmed
+                .strip_prefix("- ")
+                .or_else(|| trimmed.strip_prefix("* "))
+                .map(|item| item.trim_start_matches("deprecated ").trim());
+            match item {
+                Some(item) if item.starts_with('`') => {
+                    let item = item.trim_matches('`');
+                    if let Some(overload) = parse_overload(item, &signature.name) {
+                        signature.overloads.push(overload);
+                    }
+                    continue;
+                }
+                _ if trimmed.is_empty() => continue,
+                _ => after_label = false,
+            }
+        }
+
+        if trimmed == "**Supported probes**" {
+            in_supported_probes = true;
+            continue;
+        }
+        if in_supported_probes {
+            if let Some(probe) = trimmed.strip_prefix("* ") {
+                signature.allowed_probes.extend(doc_probe_to_providers(probe));
+                continue;
+            } else if !trimmed.is_empty() {
+                in_supported_probes = false;
+            }
+        }
+
+        if let Some(probes) = trimmed.strip_prefix("Probe types:") {
+            for probe in probes.split(',') {
+                signature.allowed_probes.extend(doc_probe_to_providers(probe));
+            }
+        }
+    }
+
+    signatures.retain(|s| !s.overloads.is_empty());
+    signatures
+}
+
+fn str_list(list: &[&str]) -> String {
+    let quoted: Vec<String> = list.iter().map(|s| format!("{:?}", s)).collect();
+    format!("&[{}]", quoted.join(", "))
+}
+
+fn gen_stdlib_signatures(stdlib_md: &str) -> String {
+    let mut text = r#"
+// This file is auto-generated by build.rs do not edit
+
+pub struct Param {
+    pub name: &'static str,
+    pub type_name: &'static str,
+    pub optional: bool,
+    // Has to be a literal, e.g. lhist() bounds
+    pub literal: bool,
+    // Keywords the argument is one of, e.g. curr_ns or init for pid()
+    pub choices: &'static [&'static str],
+}
+
+pub struct Overload {
+    pub ret: &'static str,
+    pub params: &'static [Param],
+    pub variadic: bool,
+}
+
+impl Overload {
+    pub fn min_args(&self) -> usize {
+        self.params.iter().filter(|p| !p.optional).count()
+    }
+
+    pub fn max_args(&self) -> Option<usize> {
+        if self.variadic {
+            None
+        } else {
+            Some(self.params.len())
+        }
+    }
+
+    pub fn accepts(&self, args_count: usize) -> bool {
+        args_count >= self.min_args() && self.max_args().is_none_or(|max| args_count <= max)
+    }
+}
+
+pub struct Signature {
+    pub name: &'static str,
+    pub overloads: &'static [Overload],
+    // Empty when the function can be used in any probe
+    pub allowed_probes: &'static [&'static str],
+    pub disallowed_probes: &'static [&'static str],
+}
+
+impl Signature {
+    // Return type when it doesn't depend on arguments
+    pub fn return_type(&self) -> Option<&'static str> {
+        let ret = self.overloads.first()?.ret;
+        self.overloads.iter().all(|o| o.ret == ret).then_some(ret)
+    }
+}
+
+pub fn stdlib_signature(name: &str) -> Option<&'static Signature> {
+    STDLIB_SIGNATURES.iter().find(|s| s.name == name)
+}
+
+pub static STDLIB_SIGNATURES: &[Signature] = &[
+"#
+    .to_string();
+
+    for signature in parse_stdlib_signatures(stdlib_md) {
+        text.push_str(&format!(
+            "    Signature {{\n        name: {:?},\n        overloads: &[\n",
+            signature.name
+        ));
+
+        for overload in signature.overloads.iter() {
+            text.push_str(&format!(
+                "            Overload {{\n                ret: {:?},\n                variadic: {},\n                params: &[\n",
+                overload.ret, overload.variadic
+            ));
+            for param in overload.params.iter() {
+                let choices: Vec<&str> = param.choices.iter().map(|c| c.as_str()).collect();
+                text.push_str(&format!(
+                    "                    Param {{ name: {:?}, type_name: {:?}, optional: {}, literal: {}, choices: {} }},\n",
+                    param.name,
+                    param.type_name,
+                    param.optional,
+                    param.literal,
+                    str_list(&choices)
+                ));
+            }
+            text.push_str("                ],\n            },\n");
+        }
+
+        let allowed: Vec<&str> = signature.allowed_probes.iter().map(|s| s.as_str()).collect();
+        let disallowed: &[&str] = DISALLOWED_PROBES
+            .iter()
+            .find(|(n, _)| *n == signature.name)
+            .map_or(&[], |(_, probes)| probes);
+
+        text.push_str(&format!(
+            "        ],\n        allowed_probes: {},\n        disallowed_probes: {},\n    }},\n",
+            str_list(&allowed),
+            str_list(disallowed)
+        ));
+    }
+
+    text.push_str("];\n");
+
+    text
+}
@@ -0,0 +1,550 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use btf_rs::{Btf, Type};
+use json::object;
+
+use crate::btf_mod::{
+    btf_base_checksum, btf_enum_values, btf_registry, btf_resolve_funcs, btf_resolve_struct_layout,
+    btf_setup_module, MemberLayout, ResolvedBtfItem, StructLayout,
+};
+use crate::btf_source_mod::{fnv1a_hash, BtfSource};
+use crate::log_mod::{self, BTFRE};
+use crate::{log_dbg, log_err};
+
+// On-disk index of BTF function prototypes, struct/union layouts and enum values, one file per
+// module in $XDG_CACHE_HOME/bpftrace-ls. Parsing vmlinux BTF takes a while, with the index the
+// first completion after start only needs to read and hash a text file.
+//
+// File format is a header with the key, followed by one entry per line:
+//   <kind>\t<name>\t<json>
+// where kind is F (function), S (struct or union), E (enum), C (enum constant) or R (raw
+// tracepoint, with empty value). Entries are decoded only when looked up.
+
+const INDEX_MAGIC: &str = "bpftrace-ls btf index 5";
+
+pub struct BtfIndex {
+    text: String,
+    entries: HashMap<(u8, String), Range<usize>>,
+}
+
+enum IndexState {
+    Ready(Arc<BtfIndex>),
+    Building,
+    // Since when, failed index is retried after UNAVAILABLE_RETRY
+    Unavailable(Instant),
+}
+
+const UNAVAILABLE_RETRY: Duration = Duration::from_secs(60);
+
+// Keyed by BTF source and module
+static INDEXES: LazyLock<Mutex<HashMap<(String, String), IndexState>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// First module type id, keyed by BTF source
+static FIRST_MODULE_TYPE_IDS: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = match std::env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(base.join("bpftrace-ls"))
+}
+
+// Indexes of alternate BTF sources are kept in separate directories
+fn index_dir(source: &BtfSource) -> Option<PathBuf> {
+    let dir = cache_dir()?;
+    match source {
+        BtfSource::Running => Some(dir),
+        _ => Some(dir.join(format!("{:016x}", fnv1a_hash(source.describe().as_bytes())))),
+    }
+}
+
+fn index_path(source: &BtfSource, module: &str) -> Option<PathBuf> {
+    Some(index_dir(source)?.join(format!("{}.idx", module)))
+}
+
+// Index is valid only for the same vmlinux BTF, kernel release and module BTF
+fn index_key(source: &BtfSource, module: &str) -> Option<String> {
+    let checksum = btf_base_checksum()?;
+    let release = match source {
+        BtfSource::Running => fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default(),
+        _ => source.describe(),
+    };
+    let mtime = fs::metadata(source.module_path(module)?)
+        .and_then(|m| m.modified())
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    Some(format!("{:016x} {} {}", checksum, release.trim(), mtime))
+}
+
+fn item_to_json(item: &ResolvedBtfItem) -> json::JsonValue {
+    let mut children = json::JsonValue::new_array();
+    for child in item.children_vec.iter() {
+        let _ = children.push(item_to_json(child));
+    }
+
+    let mut value = object! {
+        "name": item.name.clone(),
+        "type": item.type_vec.clone(),
+        "id": item.type_id,
+        "children": children,
+    };
+    if let Some(kind) = &item.from_anonymous {
+        value["anon"] = kind.clone().into();
+    }
+    value
+}
+
+fn item_from_json(value: &json::JsonValue) -> ResolvedBtfItem {
+    ResolvedBtfItem {
+        name: value["name"].to_string(),
+        type_vec: value["type"].members().map(|t| t.to_string()).collect(),
+        type_id: value["id"].as_u32().unwrap_or_default(),
+        children_vec: value["children"].members().map(item_from_json).collect(),
+        from_anonymous: value["anon"].as_str().map(str::to_string),
+    }
+}
+
+impl BtfIndex {
+    fn parse(text: String, key: &str) -> Option<BtfIndex> {
+        let mut lines = text.lines();
+        if lines.next()? != INDEX_MAGIC || lines.next()? != key {
+            return None;
+        }
+
+        let mut entries = HashMap::new();
+        let mut offset = INDEX_MAGIC.len() + key.len() + 2;
+        for line in text[offset..].split_inclusive('\n') {
+            let mut fields = line.trim_end_matches('\n').splitn(3, '\t');
+            if let (Some(kind), Some(name), Some(value)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                let start = offset + line.len() - value.len() - line.ends_with('\n') as usize;
+                entries
+                    .entry((kind.as_bytes()[0], name.to_string()))
+                    .or_insert(start..start + value.len());
+            }
+            offset += line.len();
+        }
+
+        Some(BtfIndex { text, entries })
+    }
+
+    fn entry(&self, kind: u8, name: &str) -> Option<json::JsonValue> {
+        let range = self.entries.get(&(kind, name.to_string()))?;
+        json::parse(&self.text[range.clone()]).ok()
+    }
+
+    pub fn has_func(&self, name: &str) -> bool {
+        self.entries.contains_key(&(b'F', name.to_string()))
+    }
+
+    // Same result as btf_resolve_funcs() on the indexed BTF
+    pub fn find_funcs(&self, name: &str, need_retval: bool) -> Vec<ResolvedBtfItem> {
+        let Some(value) = self.entry(b'F', name) else {
+            return Vec::new();
+        };
+
+        value["funcs"]
+            .members()
+            .map(|func| {
+                let mut item = item_from_json(&func["func"]);
+                if !need_retval && func["has_retval"].as_bool().unwrap_or_default() {
+                    item.children_vec.pop();
+                }
+                item
+            })
+            .collect()
+    }
+
+    fn names(&self, kind: u8) -> impl Iterator<Item = &str> {
+        self.entries
+            .keys()
+            .filter(move |(k, _)| *k == kind)
+            .map(|(_, name)| name.as_str())
+    }
+
+    pub fn func_names(&self) -> impl Iterator<Item = &str> {
+        self.names(b'F')
+    }
+
+    pub fn struct_names(&self) -> impl Iterator<Item = &str> {
+        self.names(b'S')
+    }
+
+    pub fn raw_tracepoint_names(&self) -> impl Iterator<Item = &str> {
+        self.names(b'R')
+    }
+
+    // Same result as btf_resolve_struct_layout() for the struct or union of the name
+    pub fn find_struct_layout(&self, name: &str) -> Option<StructLayout> {
+        let value = self.entry(b'S', name)?;
+        let members = value["members"]
+            .members()
+            .map(|member| MemberLayout {
+                name: member["name"].to_string(),
+                type_str: member["type"].to_string(),
+                bit_offset: member["bit_offset"].as_u32().unwrap_or_default(),
+                bitfield_size: member["bitfield_size"].as_u32(),
+                size: member["size"].as_usize().unwrap_or_default(),
+                from_anonymous: member["anon"].as_str().map(str::to_string),
+            })
+            .collect();
+
+        Some(StructLayout {
+            name: name.to_string(),
+            is_union: value["union"].as_bool().unwrap_or_default(),
+            size: value["size"].as_usize().unwrap_or_default(),
+            members,
+        })
+    }
+
+    pub fn find_enum(&self, name: &str) -> Option<Vec<(String, i128)>> {
+        let value = self.entry(b'E', name)?;
+        let values = value["values"]
+            .members()
+            .map(|v| {
+                (
+                    v[0].to_string(),
+                    v[1].as_i64()
+                        .map(i128::from)
+                        .or(v[1].as_u64().map(i128::from))
+                        .unwrap_or_default(),
+                )
+            })
+            .collect();
+        Some(values)
+    }
+
+    // Enum name and value of enum constant
+    pub fn find_enum_constant(&self, name: &str) -> Option<(String, i128)> {
+        let value = self.entry(b'C', name)?;
+        let number = value["value"]
+            .as_i64()
+            .map(i128::from)
+            .or(value["value"].as_u64().map(i128::from))?;
+        Some((value["enum"].to_string(), number))
+    }
+}
+
+fn value_to_json(value: i128) -> json::JsonValue {
+    match i64::try_from(value) {
+        Ok(v) => v.into(),
+        Err(_) => (value as u64).into(),
+    }
+}
+
+fn struct_layout_json(btf: &Btf, id: u32) -> Option<json::JsonValue> {
+    let layout = btf_resolve_struct_layout(btf, id)?;
+
+    let mut members = json::JsonValue::new_array();
+    for member in layout.members.iter() {
+        let mut value = object! {
+            "name": member.name.clone(),
+            "type": member.type_str.clone(),
+            "bit_offset": member.bit_offset,
+            "bitfield_size": member.bitfield_size,
+            "size": member.size,
+        };
+        if let Some(kind) = &member.from_anonymous {
+            value["anon"] = kind.clone().into();
+        }
+        let _ = members.push(value);
+    }
+
+    Some(object! {
+        "union": layout.is_union,
+        "size": layout.size,
+        "members": members,
+    })
+}
+
+// First id of types which belong to the module and not to vmlinux
+pub fn first_module_type_id(module: &str) -> u32 {
+    if module == "vmlinux" {
+        return 1;
+    }
+    let source = btf_registry().source().describe();
+    if let Some(id) = FIRST_MODULE_TYPE_IDS.lock().unwrap().get(&source) {
+        return *id;
+    }
+    let Some(base) = btf_setup_module("vmlinux") else {
+        return 1;
+    };
+    let mut id = 1;
+    while base.resolve_type_by_id(id).is_ok() {
+        id += 1;
+    }
+    FIRST_MODULE_TYPE_IDS.lock().unwrap().insert(source, id);
+    id
+}
+
+fn build_index_text(module: &str, btf: &Btf, key: &str) -> String {
+    let mut text = format!("{}\n{}\n", INDEX_MAGIC, key);
+    let mut push_entry = |kind: char, name: &str, value: json::JsonValue| {
+        text.push(kind);
+        text.push('\t');
+        text.push_str(name);
+        text.push('\t');
+        text.push_str(&value.dump());
+        text.push('\n');
+    };
+
+    let mut func_names = HashSet::new();
+    let mut id = first_module_type_id(module);
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        let name = t
+            .as_btf_type()
+            .and_then(|bt| btf.resolve_name(bt).ok())
+            .unwrap_or_default();
+
+        match &t {
+            // Duplicates are resolved all at once
+            Type::Func(_) if !name.is_empty() && func_names.insert(name.clone()) => {
+                let funcs: Vec<json::JsonValue> = btf_resolve_funcs(btf, &name, true)
+                    .iter()
+                    .map(|func| {
+                        let has_retval =
+                            func.children_vec.last().is_some_and(|c| c.name == "retval");
+                        object! { "func": item_to_json(func), "has_retval": has_retval }
+                    })
+                    .collect();
+                push_entry('F', &name, object! { "funcs": funcs });
+            }
+            Type::Struct(_) | Type::Union(_) if !name.is_empty() => {
+                if let Some(value) = struct_layout_json(btf, id) {
+                    push_entry('S', &name, value);
+                }
+            }
+            Type::Typedef(_) if name.starts_with("btf_trace_") => {
+                push_entry('R', &name["btf_trace_".len()..], object! {});
+            }
+            Type::Enum(_) | Type::Enum64(_) => {
+                let values = btf_enum_values(btf, &t).unwrap_or_default();
+                for (constant, value) in values.iter() {
+                    push_entry(
+                        'C',
+                        constant,
+                        object! { "enum": name.clone(), "value": value_to_json(*value) },
+                    );
+                }
+                if !name.is_empty() {
+                    let values: Vec<json::JsonValue> = values
+                        .into_iter()
+                        .map(|(constant, value)| json::array![constant, value_to_json(value)])
+                        .collect();
+                    push_entry('E', &name, object! { "values": values });
+                }
+            }
+            _ => {}
+        }
+        id += 1;
+    }
+
+    text
+}
+
+fn write_index(source: &BtfSource, module: &str, text: &str) {
+    let (Some(dir), Some(path)) = (index_dir(source), index_path(source, module)) else {
+        return;
+    };
+
+    // Write to temporary file first, so other instances never read partial index
+    let tmp_path = dir.join(format!("{}.idx.{}", module, std::process::id()));
+    let result = fs::create_dir_all(&dir)
+        .and_then(|_| fs::write(&tmp_path, text))
+        .and_then(|_| fs::rename(&tmp_path, &path));
+    if let Err(err) = result {
+        log_err!("Failed to write BTF index {:?}: {}", path, err);
+        let _ = fs::remove_file(&tmp_path);
+    }
+}
+
+fn load_index(source: &BtfSource, module: &str, key: &str) -> Option<BtfIndex> {
+    let text = fs::read_to_string(index_path(source, module)?).ok()?;
+    BtfIndex::parse(text, key)
+}
+
+fn build_index(source: &BtfSource, module: &str, key: &str) -> Option<BtfIndex> {
+    let btf = btf_setup_module(module)?;
+    // BTF source could be changed in the meantime
+    if btf_registry().source() != *source {
+        return None;
+    }
+
+    let text = build_index_text(module, &btf, key);
+    write_index(source, module, &text);
+    BtfIndex::parse(text, key)
+}
+
+fn set_state(state_key: &(String, String), state: IndexState) {
+    INDEXES.lock().unwrap().insert(state_key.clone(), state);
+}
+
+// What to do when there is no valid index on disk
+#[derive(Clone, Copy, PartialEq)]
+enum Missing {
+    BuildInBackground,
+    Build,
+    Skip,
+}
+
+// Index for the module if it is ready. When there is no valid index on disk, it is built in
+// background and until then callers have to use BTF directly.
+pub fn get(module: &str) -> Option<Arc<BtfIndex>> {
+    index(module, Missing::BuildInBackground)
+}
+
+// Index only when it is ready or on disk, nothing is built
+pub fn load(module: &str) -> Option<Arc<BtfIndex>> {
+    index(module, Missing::Skip)
+}
+
+// Indexes of modules missing on disk are built one after another in a single thread, e.g. for
+// modules whose traces had to be listed from BTF
+pub fn build_in_background(modules: Vec<String>) {
+    if modules.is_empty() {
+        return;
+    }
+    thread::spawn(move || {
+        for module in modules {
+            let _ = index(&module, Missing::Build);
+        }
+    });
+}
+
+fn index(module: &str, missing: Missing) -> Option<Arc<BtfIndex>> {
+    let module = if module.is_empty() { "vmlinux" } else { module };
+    let source = btf_registry().source();
+    let state_key = (source.describe(), module.to_string());
+
+    // Other callers see Building while the index file is read and parsed
+    {
+        let mut indexes = INDEXES.lock().unwrap();
+        match indexes.get(&state_key) {
+            Some(IndexState::Ready(index)) => return Some(index.clone()),
+            Some(IndexState::Building) => return None,
+            Some(IndexState::Unavailable(since)) if since.elapsed() < UNAVAILABLE_RETRY => {
+                return None
+            }
+            _ => {}
+        }
+        indexes.insert(state_key.clone(), IndexState::Building);
+    }
+
+    let Some(key) = index_key(&source, module) else {
+        set_state(&state_key, IndexState::Unavailable(Instant::now()));
+        return None;
+    };
+
+    if let Some(index) = load_index(&source, module, &key) {
+        log_dbg!(BTFRE, "Loaded BTF index for {}", module);
+        let index = Arc::new(index);
+        set_state(&state_key, IndexState::Ready(index.clone()));
+        return Some(index);
+    }
+
+    if missing == Missing::Skip {
+        // Another caller can still build it
+        INDEXES.lock().unwrap().remove(&state_key);
+        return None;
+    }
+
+    let build = move || {
+        log_dbg!(BTFRE, "Building BTF index for {}", state_key.1);
+        match build_index(&source, &state_key.1, &key) {
+            Some(index) => {
+                let index = Arc::new(index);
+                set_state(&state_key, IndexState::Ready(index.clone()));
+                Some(index)
+            }
+            None => {
+                set_state(&state_key, IndexState::Unavailable(Instant::now()));
+                None
+            }
+        }
+    };
+    match missing {
+        Missing::BuildInBackground => {
+            thread::spawn(build);
+            None
+        }
+        _ => build(),
+    }
+}
+
+// Called on start, so vmlinux index is ready (or being built) before the first completion
+pub fn init_vmlinux_index() {
+    let _ = get("vmlinux");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btf_mod::{btf_find_struct_id, btf_resolve_func};
+
+    #[test]
+    fn test_index_roundtrip() {
+        let Some(btf) = btf_setup_module("vmlinux") else {
+            return;
+        };
+
+        let text = build_index_text("vmlinux", &btf, "test-key");
+        assert!(BtfIndex::parse(text.clone(), "other-key").is_none());
+        let index = BtfIndex::parse(text, "test-key").unwrap();
+
+        for name in ["vfs_open", "alloc_pid", "tcp_check_req"] {
+            let cached = &index.find_funcs(name, true)[0];
+            let resolved = btf_resolve_func(&btf, name, true).unwrap();
+            assert_eq!(item_to_json(cached), item_to_json(&resolved));
+
+            let cached = &index.find_funcs(name, false)[0];
+            let resolved = btf_resolve_func(&btf, name, false).unwrap();
+            assert_eq!(item_to_json(cached), item_to_json(&resolved));
+        }
+        assert!(index.has_func("vfs_read"));
+        assert!(!index.has_func("Xblabla713h"));
+
+        let path = index.find_struct_layout("path").unwrap();
+        assert!(index.struct_names().any(|name| name == "task_struct"));
+        assert_eq!(path.members[0].name, "mnt");
+
+        let id = btf_find_struct_id(&btf, "task_struct").unwrap();
+        let resolved = btf_resolve_struct_layout(&btf, id).unwrap();
+        let cached = index.find_struct_layout("task_struct").unwrap();
+        assert_eq!(format!("{:?}", cached), format!("{:?}", resolved));
+
+        let values = index.find_enum("pid_type").unwrap();
+        assert!(values.contains(&("PIDTYPE_PID".to_string(), 0)));
+
+        assert!(index.func_names().any(|name| name == "vfs_read"));
+        assert!(index
+            .raw_tracepoint_names()
+            .any(|name| name == "sched_switch"));
+    }
+
+    #[test]
+    fn test_unavailable_index_retried() {
+        let module = "Xblabla713h_module";
+        let state_key = (btf_registry().source().describe(), module.to_string());
+        let Some(expired) = Instant::now().checked_sub(UNAVAILABLE_RETRY) else {
+            return;
+        };
+
+        set_state(&state_key, IndexState::Unavailable(expired));
+        assert!(load(module).is_none());
+        let state = INDEXES.lock().unwrap().remove(&state_key);
+        assert!(matches!(state, Some(IndexState::Unavailable(since)) if since > expired));
+    }
+}
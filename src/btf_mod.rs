@@ -1,5 +1,11 @@
 use btf_rs::*;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
 
+use crate::btf_cache_mod;
+use crate::btf_source_mod::{fnv1a_hash, BtfSource};
+use crate::config_mod;
 use crate::log_mod::{self, BTFRE};
 use crate::{log_dbg, log_err};
 
@@ -11,6 +17,8 @@ pub struct ResolvedBtfItem {
     pub type_vec: Vec<String>,
     pub type_id: u32,
     pub children_vec: Vec<ResolvedBtfItem>,
+    // Kind of anonymous struct or union the member was flattened from
+    pub from_anonymous: Option<String>,
 }
 
 fn get_int_type_vec(btf: &Btf, i: &btf::Int, type_vec: &mut Vec<String>) {
@@ -46,6 +54,11 @@ fn get_enum_type_vec(btf: &Btf, e: &btf::Enum, type_vec: &mut Vec<String>) {
     type_vec.push(e_name);
 }
 
+fn get_enum64_type_vec(btf: &Btf, e: &btf::Enum64, type_vec: &mut Vec<String>) {
+    let e_name = btf.resolve_name(e).unwrap_or_default();
+    type_vec.push(e_name);
+}
+
 fn get_func_proto_type_vec(btf: &Btf, fp: &btf::FuncProto, type_vec: &mut Vec<String>) {
     let mut ret_item = ResolvedBtfItem::default();
     if fp.return_type_id() > 0 {
@@ -78,6 +91,7 @@ fn resolve_struct_member(btf: &Btf, member: &btf::Member, id: u32) -> ResolvedBt
         type_vec: Vec::new(),
         type_id: 0,
         children_vec: Vec::new(),
+        from_anonymous: None,
     };
 
     match btf.resolve_type_by_id(id) {
@@ -94,6 +108,58 @@ fn resolve_struct_member(btf: &Btf, member: &btf::Member, id: u32) -> ResolvedBt
     item
 }
 
+// Members of anonymous struct or union, which are accessed as members of the parent
+fn anonymous_members(btf: &Btf, member: &btf::Member) -> Option<(&'static str, Vec<btf::Member>)> {
+    if !btf.resolve_name(member).unwrap_or_default().is_empty() {
+        return None;
+    }
+    match btf.resolve_type_by_id(member.get_type_id().ok()?).ok()? {
+        Type::Struct(st) => Some(("struct", st.members)),
+        Type::Union(u) => Some(("union", u.members)),
+        _ => None,
+    }
+}
+
+fn resolve_members(
+    btf: &Btf,
+    members: &[btf::Member],
+    from_anonymous: Option<&str>,
+    children: &mut Vec<ResolvedBtfItem>,
+) {
+    for member in members.iter() {
+        let id = member.get_type_id().unwrap_or_default();
+        if id == 0 {
+            continue;
+        }
+        if let Some((kind, anon_members)) = anonymous_members(btf, member) {
+            resolve_members(btf, &anon_members, Some(kind), children);
+            continue;
+        }
+        let mut child = resolve_struct_member(btf, member, id);
+        child.from_anonymous = from_anonymous.map(str::to_string);
+        children.push(child);
+    }
+}
+
+// Member by name, anonymous structs and unions are searched too. Returns also the kind of
+// anonymous aggregate the member is in.
+fn find_member(
+    btf: &Btf,
+    members: &[btf::Member],
+    name: &str,
+) -> Option<(btf::Member, Option<&'static str>)> {
+    for member in members.iter() {
+        if let Some((kind, anon_members)) = anonymous_members(btf, member) {
+            if let Some((found, inner_kind)) = find_member(btf, &anon_members, name) {
+                return Some((found, inner_kind.or(Some(kind))));
+            }
+        } else if btf.resolve_name(member).unwrap_or_default() == name {
+            return Some((member.clone(), None));
+        }
+    }
+    None
+}
+
 fn resolve_struct(btf: &Btf, base_id: u32) -> Option<ResolvedBtfItem> {
     let mut id = base_id;
     let mut type_vec: Vec<String> = Vec::new();
@@ -134,20 +200,14 @@ fn resolve_struct(btf: &Btf, base_id: u32) -> Option<ResolvedBtfItem> {
     };
 
     let mut children: Vec<ResolvedBtfItem> = Vec::new();
-
-    for member in st.members.iter() {
-        let id = member.get_type_id().unwrap_or_default();
-        if id != 0 {
-            let child = resolve_struct_member(btf, member, id);
-            children.push(child);
-        }
-    }
+    resolve_members(btf, &st.members, None, &mut children);
 
     Some(ResolvedBtfItem {
         name: btf.resolve_name(&st).unwrap_or_default(),
         type_vec,
         type_id: id,
         children_vec: children,
+        from_anonymous: None,
     })
 }
 
@@ -186,23 +246,230 @@ fn resolve_union(btf: &Btf, base_id: u32) -> Option<ResolvedBtfItem> {
     };
 
     let mut children: Vec<ResolvedBtfItem> = Vec::new();
-
-    for member in u.members.iter() {
-        let id = member.get_type_id().unwrap_or_default();
-        if id != 0 {
-            let child = resolve_struct_member(btf, member, id);
-            children.push(child);
-        }
-    }
+    resolve_members(btf, &u.members, None, &mut children);
 
     Some(ResolvedBtfItem {
         name: btf.resolve_name(&u).unwrap_or_default(),
         type_vec,
         type_id: id,
         children_vec: children,
+        from_anonymous: None,
     })
 }
 
+pub fn btf_resolve_struct_or_union(btf: &Btf, id: u32) -> Option<ResolvedBtfItem> {
+    resolve_struct(btf, id).or_else(|| resolve_union(btf, id))
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemberLayout {
+    pub name: String,
+    pub type_str: String,
+    pub bit_offset: u32,
+    pub bitfield_size: Option<u32>,
+    pub size: usize,
+    pub from_anonymous: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StructLayout {
+    pub name: String,
+    pub is_union: bool,
+    pub size: usize,
+    pub members: Vec<MemberLayout>,
+}
+
+// Size in bytes of the type, pointers are assumed to be 64-bit
+fn type_size(btf: &Btf, base_id: u32) -> usize {
+    let mut id = base_id;
+    loop {
+        match btf.resolve_type_by_id(id) {
+            Ok(Type::Int(i)) => return i.size(),
+            Ok(Type::Ptr(_)) => return 8,
+            Ok(Type::Struct(st)) | Ok(Type::Union(st)) => return st.size(),
+            Ok(Type::Enum(e)) => return e.size(),
+            Ok(Type::Enum64(e)) => return e.size(),
+            Ok(Type::Float(f)) => return f.size(),
+            Ok(Type::Array(a)) => {
+                return a.len() * type_size(btf, a.get_type_id().unwrap_or_default())
+            }
+            Ok(Type::Typedef(t)) => id = t.get_type_id().unwrap_or_default(),
+            Ok(Type::Const(c)) => id = c.get_type_id().unwrap_or_default(),
+            Ok(Type::Volatile(v)) => id = v.get_type_id().unwrap_or_default(),
+            Ok(Type::Restrict(r)) => id = r.get_type_id().unwrap_or_default(),
+            Ok(Type::TypeTag(tt)) => id = tt.get_type_id().unwrap_or_default(),
+            _ => return 0,
+        }
+    }
+}
+
+// C declaration parts of a member: type and array dimensions, e.g. "char" and "[16]"
+fn member_type_str(btf: &Btf, base_id: u32) -> (String, String) {
+    let mut id = base_id;
+    let mut dims = String::new();
+    while let Ok(Type::Array(a)) = btf.resolve_type_by_id(id) {
+        dims.push_str(&format!("[{}]", a.len()));
+        id = a.get_type_id().unwrap_or_default();
+    }
+
+    let mut item = ResolvedBtfItem::default();
+    resolve_type_id(btf, id, &mut item);
+    (item.type_vec.join(" "), dims)
+}
+
+// Members of anonymous structs and unions are flattened with offsets relative to the parent
+fn members_layout(
+    btf: &Btf,
+    members: &[btf::Member],
+    base_bit_offset: u32,
+    from_anonymous: Option<&str>,
+    layout: &mut Vec<MemberLayout>,
+) {
+    for member in members.iter() {
+        let bit_offset = base_bit_offset + member.bit_offset();
+        if let Some((kind, anon_members)) = anonymous_members(btf, member) {
+            members_layout(btf, &anon_members, bit_offset, Some(kind), layout);
+            continue;
+        }
+
+        let member_id = member.get_type_id().unwrap_or_default();
+        let (type_str, dims) = member_type_str(btf, member_id);
+        layout.push(MemberLayout {
+            name: btf.resolve_name(member).unwrap_or_default() + &dims,
+            type_str,
+            bit_offset,
+            bitfield_size: member.bitfield_size().filter(|size| *size > 0),
+            size: type_size(btf, member_id),
+            from_anonymous: from_anonymous.map(str::to_string),
+        });
+    }
+}
+
+pub fn btf_resolve_struct_layout(btf: &Btf, base_id: u32) -> Option<StructLayout> {
+    let mut id = base_id;
+    let (st, is_union) = loop {
+        match btf.resolve_type_by_id(id).ok()? {
+            Type::Struct(st) => break (st, false),
+            Type::Union(u) => break (u, true),
+            Type::Ptr(ptr) => id = ptr.get_type_id().unwrap_or_default(),
+            Type::Typedef(t) => id = t.get_type_id().unwrap_or_default(),
+            Type::Const(c) => id = c.get_type_id().unwrap_or_default(),
+            Type::Volatile(v) => id = v.get_type_id().unwrap_or_default(),
+            Type::TypeTag(tt) => id = tt.get_type_id().unwrap_or_default(),
+            _ => return None,
+        }
+    };
+
+    let mut members = Vec::new();
+    members_layout(btf, &st.members, 0, None, &mut members);
+
+    Some(StructLayout {
+        name: btf.resolve_name(&st).unwrap_or_default(),
+        is_union,
+        size: st.size(),
+        members,
+    })
+}
+
+// Names of all structs and unions
+pub fn btf_struct_names(btf: &Btf, first_id: u32) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut id = first_id;
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        if let Type::Struct(st) | Type::Union(st) = &t {
+            let name = btf.resolve_name(st).unwrap_or_default();
+            if !name.is_empty() {
+                names.push(name);
+            }
+        }
+        id += 1;
+    }
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+// Struct or union by name, declarations without members are skipped
+pub fn btf_find_struct_id(btf: &Btf, name: &str) -> Option<u32> {
+    btf.resolve_ids_by_name(name).ok()?.into_iter().find(|id| {
+        matches!(
+            btf.resolve_type_by_id(*id),
+            Ok(Type::Struct(_)) | Ok(Type::Union(_))
+        )
+    })
+}
+
+// Enumerators with values, signed enums are sign extended
+pub fn btf_enum_values(btf: &Btf, t: &Type) -> Option<Vec<(String, i128)>> {
+    let values = match t {
+        Type::Enum(e) => e
+            .members
+            .iter()
+            .map(|m| {
+                let value = if e.is_signed() {
+                    m.val() as i32 as i128
+                } else {
+                    m.val() as i128
+                };
+                (btf.resolve_name(m).unwrap_or_default(), value)
+            })
+            .collect(),
+        Type::Enum64(e) => e
+            .members
+            .iter()
+            .map(|m| {
+                let value = if e.is_signed() {
+                    m.val() as i64 as i128
+                } else {
+                    m.val() as i128
+                };
+                (btf.resolve_name(m).unwrap_or_default(), value)
+            })
+            .collect(),
+        _ => return None,
+    };
+    Some(values)
+}
+
+// Enumerators of enum type name, typedefs are followed, e.g. socket_state
+pub fn btf_find_enum_values(btf: &Btf, name: &str) -> Option<Vec<(String, i128)>> {
+    for base_id in btf.resolve_ids_by_name(name).ok()? {
+        let mut id = base_id;
+        loop {
+            match btf.resolve_type_by_id(id) {
+                Ok(t @ (Type::Enum(_) | Type::Enum64(_))) => return btf_enum_values(btf, &t),
+                Ok(Type::Typedef(t)) => id = t.get_type_id().unwrap_or_default(),
+                Ok(Type::Const(c)) => id = c.get_type_id().unwrap_or_default(),
+                Ok(Type::Volatile(v)) => id = v.get_type_id().unwrap_or_default(),
+                _ => break,
+            }
+        }
+    }
+    None
+}
+
+// Enum name and value of every enum constant, BTF has no index for enumerator names so all types
+// are walked
+pub fn btf_enum_constants(btf: &Btf) -> HashMap<String, (String, i128)> {
+    let mut constants = HashMap::new();
+    let mut id = 1;
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        if let Some(values) = btf_enum_values(btf, &t) {
+            let enum_name = t
+                .as_btf_type()
+                .and_then(|bt| btf.resolve_name(bt).ok())
+                .unwrap_or_default();
+            for (constant, value) in values {
+                constants
+                    .entry(constant)
+                    .or_insert_with(|| (enum_name.clone(), value));
+            }
+        }
+        id += 1;
+    }
+    constants
+}
+
 fn resolve_pointer(btf: &Btf, ptr: &btf::Ptr, item: &mut ResolvedBtfItem) {
     let mut chained_type = btf.resolve_chained_type(ptr).unwrap();
     let mut tag = None;
@@ -244,6 +511,8 @@ fn resolve_pointer(btf: &Btf, ptr: &btf::Ptr, item: &mut ResolvedBtfItem) {
         Type::Typedef(t) => get_typedef_type_vec(btf, &t, &mut item.type_vec),
         Type::Union(u) => get_union_type_vec(btf, &u, &mut item.type_vec),
         Type::Int(i) => get_int_type_vec(btf, &i, &mut item.type_vec),
+        Type::Enum(e) => get_enum_type_vec(btf, &e, &mut item.type_vec),
+        Type::Enum64(e) => get_enum64_type_vec(btf, &e, &mut item.type_vec),
         Type::Array(a) => get_array_type_vec(btf, &a, &mut item.type_vec),
         Type::FuncProto(fp) => get_func_proto_type_vec(btf, &fp, &mut item.type_vec),
         Type::Void => item.type_vec.push("void".to_string()),
@@ -299,6 +568,11 @@ fn resolve_type_id(btf: &Btf, id: u32, param_item: &mut ResolvedBtfItem) {
                 get_enum_type_vec(btf, &e, &mut param_item.type_vec);
                 break;
             }
+            Ok(Type::Enum64(e)) => {
+                param_item.type_id = e.get_type_id().unwrap_or_default();
+                get_enum64_type_vec(btf, &e, &mut param_item.type_vec);
+                break;
+            }
             Ok(Type::Union(u)) => {
                 param_item.type_id = u.get_type_id().unwrap_or_default();
                 get_union_type_vec(btf, &u, &mut param_item.type_vec);
@@ -340,6 +614,7 @@ fn resolve_func_parameters(
             type_vec: Vec::new(),
             type_id: 0,
             children_vec: Vec::new(),
+            from_anonymous: None,
         };
 
         let id = param.get_type_id().unwrap_or_default();
@@ -354,6 +629,7 @@ fn resolve_func_parameters(
             type_vec: Vec::new(),
             type_id: 0,
             children_vec: Vec::new(),
+            from_anonymous: None,
         };
         resolve_type_id(btf, ret_type_id, &mut ret_item);
         item.children_vec.push(ret_item);
@@ -366,6 +642,7 @@ fn resolve_parameter(btf: &Btf, param: &btf::Parameter) -> Option<ResolvedBtfIte
         type_vec: Vec::new(),
         type_id: 0,
         children_vec: Vec::new(),
+        from_anonymous: None,
     };
 
     // TODO other parameters types, merge with resolve_struct_member
@@ -374,6 +651,13 @@ fn resolve_parameter(btf: &Btf, param: &btf::Parameter) -> Option<ResolvedBtfIte
         Ok(Type::Int(i)) => get_int_type_vec(btf, &i, &mut parameter_item.type_vec),
         Ok(Type::Typedef(t)) => get_typedef_type_vec(btf, &t, &mut parameter_item.type_vec),
         Ok(Type::Union(u)) => get_union_type_vec(btf, &u, &mut parameter_item.type_vec),
+        Ok(Type::Enum(e)) => get_enum_type_vec(btf, &e, &mut parameter_item.type_vec),
+        Ok(Type::Enum64(e)) => get_enum64_type_vec(btf, &e, &mut parameter_item.type_vec),
+        Ok(Type::Const(_)) | Ok(Type::Volatile(_)) => resolve_type_id(
+            btf,
+            param.get_type_id().unwrap_or_default(),
+            &mut parameter_item,
+        ),
         Ok(x) => {
             log_dbg!(BTFRE, "Unhandled type {:?}", x);
             return None;
@@ -412,52 +696,472 @@ fn is_pointer_type(btf: &Btf, base_id: u32) -> bool {
     }
 }
 
-pub fn btf_resolve_func(btf: &Btf, name: &str, need_retval: bool) -> Option<ResolvedBtfItem> {
+fn same_prototype(a: &ResolvedBtfItem, b: &ResolvedBtfItem) -> bool {
+    a.children_vec.len() == b.children_vec.len()
+        && a.children_vec
+            .iter()
+            .zip(b.children_vec.iter())
+            .all(|(a, b)| a.name == b.name && a.type_vec == b.type_vec)
+}
+
+// Static functions can be in BTF several times, once for each compilation unit, and split module
+// BTF also sees functions of vmlinux with the same name. Returns all functions with different
+// prototypes, the ones with higher ids first, so module function wins over vmlinux one.
+pub fn btf_resolve_funcs(btf: &Btf, name: &str, need_retval: bool) -> Vec<ResolvedBtfItem> {
     log_dbg!(BTFRE, "Looking for {}", name);
-    if let Err(err) = btf.resolve_types_by_name(name) {
-        log_err!("Looking for {} failed with {:?}", name, err);
-        return None;
+    let mut ids = match btf.resolve_ids_by_name(name) {
+        Ok(ids) => ids,
+        Err(err) => {
+            log_err!("Looking for {} failed with {:?}", name, err);
+            return Vec::new();
+        }
+    };
+    ids.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut funcs: Vec<ResolvedBtfItem> = Vec::new();
+    for id in ids {
+        let func = match btf.resolve_type_by_id(id) {
+            Ok(Type::Func(func)) => func,
+            x => {
+                log_dbg!(BTFRE, "Resolved type is not a function, it's {:?}", x);
+                continue;
+            }
+        };
+        let mut item = ResolvedBtfItem {
+            name: "".to_string(),
+            type_vec: Vec::new(),
+            type_id: 0,
+            children_vec: Vec::new(),
+            from_anonymous: None,
+        };
+        item.type_id = func.get_type_id().unwrap_or_default();
+        item.name = btf.resolve_name(&func).unwrap_or_default();
+        item.type_vec = vec!["func".to_string()]; // TODO function prototype evaluation
+        resolve_func_parameters(btf, func, &mut item, need_retval);
+
+        if !funcs.iter().any(|f| same_prototype(f, &item)) {
+            funcs.push(item);
+        }
     }
 
-    let mut func_vec = btf.resolve_types_by_name(name).ok()?;
-    // TODO what todo if more then one function ?
-    if func_vec.len() != 1 {
-        return None;
+    if funcs.len() > 1 {
+        log_dbg!(BTFRE, "{} has {} different prototypes", name, funcs.len());
     }
+    funcs
+}
 
-    let func = match func_vec.pop() {
-        Some(Type::Func(func)) => func,
-        x => {
-            log_dbg!(BTFRE, "Resolved type is not a function, it's {:?}", x);
-            return None;
+pub fn btf_resolve_func(btf: &Btf, name: &str, need_retval: bool) -> Option<ResolvedBtfItem> {
+    btf_resolve_funcs(btf, name, need_retval).into_iter().next()
+}
+
+// Prototype of btf_trace_<name> typedef, pointer to function with "void *__data" parameter first
+fn raw_tracepoint_proto(btf: &Btf, name: &str) -> Option<(u32, btf::FuncProto)> {
+    let mut id = btf
+        .resolve_ids_by_name(&format!("btf_trace_{}", name))
+        .ok()?
+        .into_iter()
+        .find(|id| matches!(btf.resolve_type_by_id(*id), Ok(Type::Typedef(_))))?;
+    loop {
+        match btf.resolve_type_by_id(id).ok()? {
+            Type::Typedef(t) => id = t.get_type_id().unwrap_or_default(),
+            Type::Ptr(ptr) => id = ptr.get_type_id().unwrap_or_default(),
+            Type::FuncProto(proto) => return Some((id, proto)),
+            _ => return None,
+        }
+    }
+}
+
+// Parameter names differ between prototypes of the same types
+fn param_type_ids(proto: &btf::FuncProto) -> Vec<u32> {
+    proto
+        .parameters
+        .iter()
+        .map(|param| param.get_type_id().unwrap_or_default())
+        .collect()
+}
+
+// Raw struct of tracepoint event. Events defined by DEFINE_EVENT share the struct of their class,
+// the class is found by trace_event_raw_event_<class>() which has the same parameters as
+// btf_trace_<event>. Only functions from first_id are looked at, for split BTF of modules.
+pub fn btf_find_event_struct_id(btf: &Btf, event: &str, first_id: u32) -> Option<u32> {
+    if let Some(id) = btf_find_struct_id(btf, &format!("trace_event_raw_{}", event)) {
+        return Some(id);
+    }
+    let (_, proto) = raw_tracepoint_proto(btf, event)?;
+    let params = param_type_ids(&proto);
+
+    let mut classes = Vec::new();
+    let mut id = first_id;
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        if let Type::Func(func) = &t {
+            let name = btf.resolve_name(func).unwrap_or_default();
+            if let Some(class) = name.strip_prefix("trace_event_raw_event_") {
+                if let Ok(Type::FuncProto(fp)) = btf.resolve_chained_type(func) {
+                    if param_type_ids(&fp) == params {
+                        classes.push(class.to_string());
+                    }
+                }
+            }
         }
+        id += 1;
+    }
+
+    // More classes with the same parameters, events are usually named like their class, e.g.
+    // sched_wakeup of sched_wakeup_template. Class is not guessed when more match the same.
+    let common_prefix = |class: &str| {
+        class
+            .bytes()
+            .zip(event.bytes())
+            .take_while(|(a, b)| a == b)
+            .count()
     };
+    let class = match classes.as_slice() {
+        [class] => class,
+        _ => {
+            let best = classes.iter().map(|c| common_prefix(c)).max()?;
+            let mut best_classes = classes.iter().filter(|c| common_prefix(c) == best);
+            match (best_classes.next(), best_classes.next()) {
+                (Some(class), None) if best > 0 => class,
+                _ => return None,
+            }
+        }
+    };
+    btf_find_struct_id(btf, &format!("trace_event_raw_{}", class))
+}
+
+// Arguments of raw tracepoint. __probestub_<name> has names of them, btf_trace_<name> typedef
+// has only types. The first argument of both is tracepoint data and it is skipped.
+pub fn btf_resolve_raw_tracepoint(btf: &Btf, name: &str) -> Option<ResolvedBtfItem> {
+    if let Some(mut item) = btf_resolve_func(btf, &format!("__probestub_{}", name), false) {
+        if !item.children_vec.is_empty() {
+            item.children_vec.remove(0);
+        }
+        item.name = name.to_string();
+        return Some(item);
+    }
+
+    let (id, proto) = raw_tracepoint_proto(btf, name)?;
     let mut item = ResolvedBtfItem {
-        name: "".to_string(),
-        type_vec: Vec::new(),
-        type_id: 0,
+        name: name.to_string(),
+        type_vec: vec!["func".to_string()],
+        type_id: id,
         children_vec: Vec::new(),
+        from_anonymous: None,
     };
-    item.type_id = func.get_type_id().unwrap_or_default();
-    item.name = btf.resolve_name(&func).unwrap_or_default();
-    item.type_vec = vec!["func".to_string()]; // TODO function prototype evaluation
-    resolve_func_parameters(btf, func, &mut item, need_retval);
+    for (i, param) in proto.parameters.iter().enumerate().skip(1) {
+        let mut param_name = btf.resolve_name(param).unwrap_or_default();
+        if param_name.is_empty() {
+            param_name = format!("arg{}", i - 1);
+        }
+        let mut param_item = ResolvedBtfItem {
+            name: param_name,
+            ..Default::default()
+        };
+        resolve_type_id(
+            btf,
+            param.get_type_id().unwrap_or_default(),
+            &mut param_item,
+        );
+        item.children_vec.push(param_item);
+    }
     Some(item)
 }
 
-pub fn btf_setup_module(module: &str) -> Option<Btf> {
-    let btf_base = Btf::from_file("/sys/kernel/btf/vmlinux").ok()?;
-    if module.is_empty() || module == "vmlinux" {
-        return Some(btf_base);
+// Names of functions with type id from first_id, so split BTF of module is without vmlinux
+pub fn btf_func_names(btf: &Btf, first_id: u32) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut id = first_id;
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        if let Type::Func(func) = &t {
+            let name = btf.resolve_name(func).unwrap_or_default();
+            if !name.is_empty() {
+                names.push(name);
+            }
+        }
+        id += 1;
+    }
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+// Names of raw tracepoints, from btf_trace_<name> typedefs with type id from first_id, so split
+// BTF of module gives only its own tracepoints
+pub fn btf_raw_tracepoint_names(btf: &Btf, first_id: u32) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut id = first_id;
+    while let Ok(t) = btf.resolve_type_by_id(id) {
+        if let Type::Typedef(typedef) = &t {
+            let name = btf.resolve_name(typedef).unwrap_or_default();
+            if let Some(tracepoint) = name.strip_prefix("btf_trace_") {
+                names.push(tracepoint.to_string());
+            }
+        }
+        id += 1;
+    }
+    names.sort_unstable();
+    names.dedup();
+    names
+}
+
+// All BTF objects loaded by the server. vmlinux is parsed once and shared as the base of every
+// split module BTF, modules are loaded on first use. BTF and kallsyms are parsed without holding
+// the lock, so other threads can use what is already loaded in the meantime.
+#[derive(Default)]
+pub struct BtfRegistry {
+    // "btfSource" setting the registry was loaded for
+    source_setting: Option<String>,
+    source: BtfSource,
+    base: Option<Arc<Btf>>,
+    base_checksum: Option<u64>,
+    modules: HashMap<String, Option<Arc<Btf>>>,
+    // Size of raw BTF data of loaded objects
+    loaded_bytes: HashMap<String, u64>,
+    // Module symbols from /proc/kallsyms, function name to module
+    kallsyms_owners: Option<Arc<HashMap<String, String>>>,
+    // Only found modules are kept, the function can be in a module loaded later
+    func_owners: HashMap<String, String>,
+}
+
+static BTF_REGISTRY: LazyLock<Mutex<BtfRegistry>> =
+    LazyLock::new(|| Mutex::new(BtfRegistry::default()));
+
+static RUNNING_KERNEL_CHECKSUM: LazyLock<Option<u64>> = LazyLock::new(|| {
+    BtfSource::Running
+        .module_bytes("vmlinux")
+        .map(|b| fnv1a_hash(&b))
+});
+
+fn read_kallsyms_owners() -> HashMap<String, String> {
+    let mut owners = HashMap::new();
+    let Ok(kallsyms) = fs::read_to_string("/proc/kallsyms") else {
+        return owners;
+    };
+
+    // ffffffffc0a01000 t rt2800_link_tuner\t[rt2800lib]
+    for line in kallsyms.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(_addr), Some(kind), Some(name), Some(module)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if !kind.eq_ignore_ascii_case("t") {
+            continue;
+        }
+        if let Some(module) = module.strip_prefix('[').and_then(|m| m.strip_suffix(']')) {
+            owners
+                .entry(name.to_string())
+                .or_insert_with(|| module.to_string());
+        }
+    }
+
+    owners
+}
+
+fn has_func(btf: &Btf, name: &str) -> bool {
+    btf.resolve_types_by_name(name)
+        .is_ok_and(|types| types.iter().any(|t| matches!(t, Type::Func(_))))
+}
+
+impl BtfRegistry {
+    pub fn source(&self) -> BtfSource {
+        self.source.clone()
+    }
+
+    // Approximation, BTF data is kept parsed in memory and takes more than raw size
+    pub fn memory_usage(&self) -> u64 {
+        self.loaded_bytes.values().sum()
+    }
+}
+
+// Registry starts over when BTF source was changed in settings. Archives are extracted without
+// holding the lock.
+pub fn btf_registry() -> MutexGuard<'static, BtfRegistry> {
+    let setting = config_mod::get().btf_source;
+    let registry = BTF_REGISTRY.lock().unwrap();
+    if registry.source_setting == setting {
+        return registry;
     }
+    drop(registry);
 
-    let path = "/sys/kernel/btf/".to_string() + module;
-    if let Ok(btf) = Btf::from_split_file(&path, &btf_base) {
-        log_dbg!(BTFRE, "Loaded btf for {}", path);
+    let cache_dir = btf_cache_mod::cache_dir();
+    let source = BtfSource::from_setting(setting.as_deref(), cache_dir.as_deref());
+
+    let mut registry = BTF_REGISTRY.lock().unwrap();
+    if registry.source_setting != setting {
+        log_dbg!(BTFRE, "Using BTF from {}", source.describe());
+        *registry = BtfRegistry {
+            source_setting: setting,
+            source,
+            ..Default::default()
+        };
+    }
+    registry
+}
+
+// Checksum of vmlinux BTF, computed without parsing it
+pub fn btf_base_checksum() -> Option<u64> {
+    let source = {
+        let registry = btf_registry();
+        if let Some(checksum) = registry.base_checksum {
+            return Some(checksum);
+        }
+        registry.source()
+    };
+
+    let checksum = fnv1a_hash(&source.module_bytes("vmlinux")?);
+    let mut registry = btf_registry();
+    if registry.source() == source {
+        registry.base_checksum = Some(checksum);
+    }
+    Some(checksum)
+}
+
+fn btf_setup_base() -> Option<Arc<Btf>> {
+    let source = {
+        let registry = btf_registry();
+        if let Some(base) = &registry.base {
+            return Some(base.clone());
+        }
+        registry.source.clone()
+    };
+
+    let bytes = source.module_bytes("vmlinux")?;
+    let btf = Arc::new(Btf::from_bytes(&bytes).ok()?);
+
+    let mut registry = btf_registry();
+    // Source changed while parsing, the BTF is used only by this caller
+    if registry.source() != source {
         return Some(btf);
     }
+    // Parsed by another thread in the meantime
+    if let Some(base) = &registry.base {
+        return Some(base.clone());
+    }
+    log_dbg!(BTFRE, "Loaded btf for vmlinux from {}", source.describe());
+    registry.base = Some(btf.clone());
+    registry.base_checksum = Some(fnv1a_hash(&bytes));
+    registry
+        .loaded_bytes
+        .insert("vmlinux".to_string(), bytes.len() as u64);
+    Some(btf)
+}
 
-    None
+pub fn btf_setup_module(module: &str) -> Option<Arc<Btf>> {
+    if module.is_empty() || module == "vmlinux" {
+        return btf_setup_base();
+    }
+    let base = btf_setup_base()?;
+    let source = {
+        let registry = btf_registry();
+        if let Some(btf) = registry.modules.get(module) {
+            return btf.clone();
+        }
+        registry.source.clone()
+    };
+
+    let bytes = source.module_bytes(module).unwrap_or_default();
+    let btf = Btf::from_split_bytes(&bytes, &base).ok().map(Arc::new);
+
+    let mut registry = btf_registry();
+    if registry.source() != source {
+        return btf;
+    }
+    if let Some(loaded) = registry.modules.get(module) {
+        return loaded.clone();
+    }
+    if btf.is_some() {
+        registry
+            .loaded_bytes
+            .insert(module.to_string(), bytes.len() as u64);
+        log_dbg!(
+            BTFRE,
+            "Loaded btf for {}, {} objects use {} bytes of BTF data",
+            module,
+            registry.loaded_bytes.len(),
+            registry.memory_usage()
+        );
+        // Failed loads are not cached, the module can be loaded later
+        registry.modules.insert(module.to_string(), btf.clone());
+    }
+    btf
+}
+
+fn kallsyms_owner(func: &str) -> Option<String> {
+    let (source, owners) = {
+        let registry = btf_registry();
+        (registry.source(), registry.kallsyms_owners.clone())
+    };
+    // kallsyms is useful only when BTF is for the running kernel
+    if !source.is_running() {
+        return None;
+    }
+
+    let owners = match owners {
+        Some(owners) => owners,
+        None => {
+            let owners = Arc::new(read_kallsyms_owners());
+            let mut registry = btf_registry();
+            if registry.source() == source {
+                registry.kallsyms_owners = Some(owners.clone());
+            }
+            owners
+        }
+    };
+    owners.get(func).cloned()
+}
+
+fn lookup_func_module(func: &str) -> Option<String> {
+    let base = btf_setup_base()?;
+    if has_func(&base, func) {
+        return Some("vmlinux".to_string());
+    }
+
+    if let Some(module) = kallsyms_owner(func) {
+        if btf_setup_module(&module).is_some_and(|btf| has_func(&btf, func)) {
+            return Some(module);
+        }
+    }
+
+    // kallsyms can be restricted, look at least in modules which are already loaded
+    let mut loaded: Vec<(String, Arc<Btf>)> = btf_registry()
+        .modules
+        .iter()
+        .filter_map(|(module, btf)| Some((module.clone(), btf.clone()?)))
+        .collect();
+    loaded.sort_by(|a, b| a.0.cmp(&b.0));
+    loaded
+        .into_iter()
+        .find(|(_, btf)| has_func(btf, func))
+        .map(|(module, _)| module)
+}
+
+// Find module with BTF for the function, for probes without module like fentry:tcp_sendmsg
+pub fn btf_find_func_module(func: &str) -> Option<String> {
+    let source = {
+        let registry = btf_registry();
+        if let Some(owner) = registry.func_owners.get(func) {
+            return Some(owner.clone());
+        }
+        registry.source()
+    };
+
+    let owner = lookup_func_module(func)?;
+    let mut registry = btf_registry();
+    if registry.source() == source {
+        registry.func_owners.insert(func.to_string(), owner.clone());
+    }
+    Some(owner)
+}
+
+// False when scripts are developed against BTF of another kernel, so bpftrace running here
+// would check them against wrong kernel
+pub fn btf_targets_running_kernel() -> bool {
+    if btf_registry().source().is_running() {
+        return true;
+    }
+    btf_base_checksum().is_some() && btf_base_checksum() == *RUNNING_KERNEL_CHECKSUM
 }
 
 fn chain_str_to_tokens(names_chain: &str) -> Vec<&str> {
@@ -521,6 +1225,94 @@ fn resolve_struct_or_union(
     }
 }
 
+// Member accesses after first name, e.g. ["->", "mm", "->"], type_id is type of the first name.
+// Cast to pointer can be given by first_is_pointer when there is no such pointer type in BTF.
+fn resolve_member_accesses(
+    btf: &Btf,
+    mut type_id: u32,
+    first_name: &str,
+    names: &[&str],
+    first_is_pointer: Option<bool>,
+) -> Option<ResolvedVariable> {
+    // Handle struct/union members: use -> for pointrs and . for direct access
+    let mut last_name = first_name;
+    let mut last_anonymous = None;
+    let mut first_is_pointer = first_is_pointer;
+    let mut names_iter_peek = names.iter().peekable();
+    while let Some(op) = names_iter_peek.next() {
+        let is_pointer = first_is_pointer
+            .take()
+            .unwrap_or_else(|| is_pointer_type(btf, type_id));
+        if *op == "->" {
+            if !is_pointer {
+                return None;
+            }
+        } else if *op == "." {
+            if is_pointer {
+                return None;
+            }
+        } else {
+            return None;
+        }
+
+        let member_name = if let Some(name) = names_iter_peek.next() {
+            name
+        } else {
+            if names.last() == Some(&"->") || names.last() == Some(&".") {
+                break;
+            }
+            return None;
+        };
+
+        loop {
+            if type_id == 0 {
+                // TODO error
+                return None;
+            }
+
+            match btf.resolve_type_by_id(type_id) {
+                Ok(Type::Const(c)) => {
+                    type_id = c.get_type_id().unwrap_or_default();
+                    continue;
+                }
+                Ok(Type::Ptr(ptr)) => {
+                    type_id = ptr.get_type_id().unwrap_or_default();
+                    continue;
+                }
+                Ok(Type::Struct(st)) | Ok(Type::Union(st)) => {
+                    let (member, from_anonymous) = find_member(btf, &st.members, member_name)?;
+
+                    type_id = member.get_type_id().unwrap_or_default();
+                    last_name = member_name;
+                    last_anonymous = from_anonymous;
+                    break;
+                }
+                // TODO
+                // Type::Int(i) =>(),  /* get_int_type_vec(btf, &i, &mut item.type_vec), */
+                // Type::Typedef(t) =>(),  /* get_typedef_type_vec(btf, &t, &mut item.type_vec), */
+                // Type::Array(a) =>(),  /* get_array_type_vec(btf, &a, &mut item.type_vec), */
+                Ok(x) => {
+                    log_dbg!(BTFRE, "Unhandled type {:?}", x);
+                    return None;
+                }
+                Err(_) => {
+                    log_err!("Failed to resolve BTF type_id {}", type_id);
+                    return None;
+                }
+            }
+        }
+    }
+
+    let mut var_item = ResolvedBtfItem {
+        name: last_name.to_string(),
+        from_anonymous: last_anonymous.map(str::to_string),
+        ..Default::default()
+    };
+    resolve_type_id(btf, type_id, &mut var_item);
+
+    Some(resolve_struct_or_union(btf, var_item, type_id))
+}
+
 pub fn btf_iterate_over_names_chain(
     btf: &Btf,
     func: &ResolvedBtfItem,
@@ -551,7 +1343,7 @@ pub fn btf_iterate_over_names_chain(
             }
         };
 
-        let mut type_id = if first_name.starts_with("retval") {
+        let type_id = if first_name.starts_with("retval") {
             func_proto.return_type_id()
         } else {
             let first_param = func_proto
@@ -568,90 +1360,8 @@ pub fn btf_iterate_over_names_chain(
             first_param.get_type_id().unwrap_or_default()
         };
 
-        // Handle struct/union members: use -> for pointrs and . for direct access
-        let mut last_name = *first_name;
-        let mut names_iter_peek = names_iter.peekable();
-        while let Some(op) = names_iter_peek.next() {
-            let is_pointer = is_pointer_type(btf, type_id);
-            if *op == "->" {
-                if !is_pointer {
-                    return None;
-                }
-            } else if *op == "." {
-                if is_pointer {
-                    return None;
-                }
-            } else {
-                return None;
-            }
-
-            let member_name = if let Some(name) = names_iter_peek.next() {
-                name
-            } else {
-                if names_chain_vec.last() == Some(&"->") || names_chain_vec.last() == Some(&".") {
-                    break;
-                }
-                return None;
-            };
-
-            loop {
-                if type_id == 0 {
-                    // TODO error
-                    return None;
-                }
-
-                match btf.resolve_type_by_id(type_id) {
-                    Ok(Type::Const(c)) => {
-                        type_id = c.get_type_id().unwrap_or_default();
-                        continue;
-                    }
-                    Ok(Type::Ptr(ptr)) => {
-                        type_id = ptr.get_type_id().unwrap_or_default();
-                        continue;
-                    }
-                    Ok(Type::Struct(st)) => {
-                        let member = st
-                            .members
-                            .iter()
-                            .find(|&m| btf.resolve_name(m).unwrap().eq(member_name))?;
-
-                        type_id = member.get_type_id().unwrap_or_default();
-                        last_name = member_name;
-                        break;
-                    }
-                    Ok(Type::Union(u)) => {
-                        let member = u
-                            .members
-                            .iter()
-                            .find(|&m| btf.resolve_name(m).unwrap().eq(member_name))?;
-
-                        type_id = member.get_type_id().unwrap_or_default();
-                        last_name = member_name;
-                        break;
-                    }
-                    // TODO
-                    // Type::Int(i) =>(),  /* get_int_type_vec(btf, &i, &mut item.type_vec), */
-                    // Type::Typedef(t) =>(),  /* get_typedef_type_vec(btf, &t, &mut item.type_vec), */
-                    // Type::Array(a) =>(),  /* get_array_type_vec(btf, &a, &mut item.type_vec), */
-                    Ok(x) => {
-                        log_dbg!(BTFRE, "Unhandled type {:?}", x);
-                        return None;
-                    }
-                    Err(_) => {
-                        log_err!("Failed to resolve BTF type_id {}", type_id);
-                        return None;
-                    }
-                }
-            }
-        }
-
-        let mut var_item = ResolvedBtfItem {
-            name: last_name.to_string(),
-            ..Default::default()
-        };
-        resolve_type_id(btf, type_id, &mut var_item);
-
-        Some(resolve_struct_or_union(btf, var_item, type_id))
+        let names: Vec<&str> = names_iter.copied().collect();
+        resolve_member_accesses(btf, type_id, first_name, &names, None)
     } else if is_retval {
         if let Some(retval) = func
             .children_vec
@@ -680,6 +1390,25 @@ pub fn btf_iterate_over_names_chain(
     }
 }
 
+// Chain of member accesses on expression of struct or union type, e.g. "->mm->" for
+// ((struct task_struct *)curtask)->mm->
+pub fn btf_iterate_over_type_chain(
+    btf: &Btf,
+    type_id: u32,
+    is_pointer: bool,
+    names_chain_str: &str,
+) -> Option<ResolvedVariable> {
+    let names = chain_str_to_tokens(names_chain_str);
+    let names: Vec<&str> = names.into_iter().filter(|name| !name.is_empty()).collect();
+    let name = btf
+        .resolve_type_by_id(type_id)
+        .ok()?
+        .as_btf_type()
+        .and_then(|bt| btf.resolve_name(bt).ok())
+        .unwrap_or_default();
+    resolve_member_accesses(btf, type_id, &name, &names, Some(is_pointer))
+}
+
 #[allow(clippy::len_zero)]
 #[cfg(test)]
 mod tests {
@@ -691,6 +1420,126 @@ mod tests {
 
         let btf2 = btf_setup_module("Xblabla713h");
         assert!(btf2.is_none());
+
+        let btf3 = btf_setup_module("").unwrap();
+        assert!(Arc::ptr_eq(&btf1.unwrap(), &btf3));
+    }
+
+    #[test]
+    fn test_find_func_module() {
+        assert_eq!(btf_find_func_module("vfs_read").as_deref(), Some("vmlinux"));
+        assert_eq!(btf_find_func_module("Xblabla713h"), None);
+        assert!(!btf_registry().func_owners.contains_key("Xblabla713h"));
+
+        if btf_setup_module("mac80211").is_some() {
+            assert_eq!(
+                btf_find_func_module("ieee80211_register_hw").as_deref(),
+                Some("mac80211")
+            );
+        }
+        assert!(btf_registry().memory_usage() > 0);
+    }
+
+    #[test]
+    fn test_struct_layout() {
+        let btf = btf_setup_module("vmlinux").unwrap();
+
+        let id = btf_find_struct_id(&btf, "path").unwrap();
+        let layout = btf_resolve_struct_layout(&btf, id).unwrap();
+        assert_eq!(layout.name, "path");
+        assert_eq!(layout.size, 16);
+        assert_eq!(layout.members[0].name, "mnt");
+        assert_eq!(layout.members[0].type_str, "struct vfsmount *");
+        assert_eq!(layout.members[1].bit_offset, 64);
+        assert_eq!(layout.members[1].size, 8);
+
+        let id = btf_find_struct_id(&btf, "task_struct").unwrap();
+        let layout = btf_resolve_struct_layout(&btf, id).unwrap();
+        let comm = layout
+            .members
+            .iter()
+            .find(|m| m.name == "comm[16]")
+            .unwrap();
+        assert_eq!(comm.type_str, "char");
+        assert_eq!(comm.size, 16);
+        assert!(layout.members.iter().any(|m| m.bitfield_size.is_some()));
+    }
+
+    #[test]
+    fn test_anonymous_members() {
+        let btf = btf_setup_module("vmlinux").unwrap();
+
+        let id = btf_find_struct_id(&btf, "sk_buff").unwrap();
+        let skb = btf_resolve_struct_or_union(&btf, id).unwrap();
+        let next = skb.children_vec.iter().find(|c| c.name == "next").unwrap();
+        assert_eq!(next.from_anonymous.as_deref(), Some("struct"));
+        let dev = skb.children_vec.iter().find(|c| c.name == "dev").unwrap();
+        assert_eq!(dev.from_anonymous.as_deref(), Some("union"));
+        assert!(skb.children_vec.iter().all(|c| !c.name.is_empty()));
+
+        let layout = btf_resolve_struct_layout(&btf, id).unwrap();
+        let dev = layout.members.iter().find(|m| m.name == "dev").unwrap();
+        assert_eq!(dev.bit_offset, 128);
+        assert_eq!(dev.from_anonymous.as_deref(), Some("union"));
+
+        let func = btf_resolve_func(&btf, "consume_skb", false).unwrap();
+        let var = btf_iterate_over_names_chain(&btf, &func, "args.skb->dev").unwrap();
+        assert_eq!(var.var.type_vec, vec!["struct", "net_device", "*"]);
+        assert_eq!(var.var.from_anonymous.as_deref(), Some("union"));
+    }
+
+    #[test]
+    fn test_resolve_funcs() {
+        let btf = btf_setup_module("vmlinux").unwrap();
+
+        // There is also struct autofs_dev_ioctl
+        assert!(btf.resolve_ids_by_name("autofs_dev_ioctl").unwrap().len() > 1);
+        let funcs = btf_resolve_funcs(&btf, "autofs_dev_ioctl", true);
+        assert_eq!(funcs.len(), 1);
+        assert_eq!(funcs[0].name, "autofs_dev_ioctl");
+        assert!(btf_resolve_func(&btf, "autofs_dev_ioctl", false).is_some());
+        assert!(btf_resolve_funcs(&btf, "task_struct", true).is_empty());
+
+        let mut other = funcs[0].clone();
+        assert!(same_prototype(&funcs[0], &other));
+        other.children_vec[0].type_vec = vec!["int".to_string()];
+        assert!(!same_prototype(&funcs[0], &other));
+    }
+
+    #[test]
+    fn test_resolve_raw_tracepoint() {
+        let btf = btf_setup_module("vmlinux").unwrap();
+
+        let item = btf_resolve_raw_tracepoint(&btf, "sched_switch").unwrap();
+        let args: Vec<&str> = item.children_vec.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(args, vec!["preempt", "prev", "next", "prev_state"]);
+        assert_eq!(
+            item.children_vec[1].type_vec,
+            vec!["struct", "task_struct", "*"]
+        );
+        assert!(btf_resolve_raw_tracepoint(&btf, "no_such_tracepoint").is_none());
+
+        let names = btf_raw_tracepoint_names(&btf, 1);
+        assert!(names.contains(&"sched_switch".to_string()));
+    }
+
+    #[test]
+    fn test_enum_values() {
+        let btf = btf_setup_module("vmlinux").unwrap();
+
+        let values = btf_find_enum_values(&btf, "hrtimer_mode").unwrap();
+        assert!(values.contains(&("HRTIMER_MODE_ABS".to_string(), 0)));
+        assert!(values.contains(&("HRTIMER_MODE_REL".to_string(), 1)));
+
+        // Typedef of enum
+        let values = btf_find_enum_values(&btf, "socket_state").unwrap();
+        assert!(values.iter().any(|(name, _)| name == "SS_CONNECTED"));
+        assert!(btf_find_enum_values(&btf, "task_struct").is_none());
+
+        let constants = btf_enum_constants(&btf);
+        let (enum_name, value) = &constants["HRTIMER_MODE_REL"];
+        assert_eq!(enum_name, "hrtimer_mode");
+        assert_eq!(*value, 1);
     }
 
     #[test]
@@ -800,12 +1649,8 @@ mod tests {
     #[test]
     // #[ignore]
     fn test_resolve_rt2800_link_tuner() {
-        let btf = match btf_setup_module("rt2800lib") {
-            Some(btf) => btf,
-            None => {
-                eprintln!("\x1b[33mskipped\x1b[0m: rt2800lib module not loaded");
-                return;
-            }
+        let Some(btf) = btf_setup_module("rt2800lib") else {
+            return;
         };
         let base = btf_resolve_func(&btf, "rt2800_link_tuner", true).unwrap();
         let resolved = btf_iterate_over_names_chain(&btf, &base, "qual->").unwrap();
@@ -873,12 +1718,8 @@ mod tests {
     #[test]
     fn test_resolve_ieee80211_hw_array_in_struct() {
         // This test requires mac80211 module to be loaded
-        let btf = match btf_setup_module("mac80211") {
-            Some(btf) => btf,
-            None => {
-                eprintln!("\x1b[33mskipped\x1b[0m: mac80211 module not loaded");
-                return;
-            }
+        let Some(btf) = btf_setup_module("mac80211") else {
+            return;
         };
         let base = btf_resolve_func(&btf, "ieee80211_register_hw", true).unwrap();
 
@@ -0,0 +1,262 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+use crate::elf_mod::{self, Elf};
+use crate::log_mod::{self, BTFRE};
+use crate::{log_dbg, log_err};
+
+pub const BTF_DIR: &str = "/sys/kernel/btf";
+
+// Where BTF comes from, set by "btfSource" setting. By default it is the running kernel, for
+// developing scripts for other kernels it can be:
+//  - directory with vmlinux and module BTF files, e.g. copy of /sys/kernel/btf
+//  - raw BTF file or vmlinux ELF with .BTF section
+//  - tarball of any of the above, also BTFHub archives like 5.4.0-1000-aws.btf.tar.xz
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum BtfSource {
+    #[default]
+    Running,
+    Dir(PathBuf),
+    File(PathBuf),
+}
+
+pub fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn is_archive(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    [
+        ".tar", ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.bz2", ".tar.zst",
+    ]
+    .iter()
+    .any(|ext| name.ends_with(ext))
+}
+
+fn find_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() && depth < 8 {
+            find_files(&path, depth + 1, files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+// Threads noticing the changed setting at once do not extract to the same directory
+static EXTRACT_LOCK: Mutex<()> = Mutex::new(());
+
+// Tarballs are extracted once to the cache directory, tar takes care of the compression
+fn extract_archive(archive: &Path, cache_dir: &Path) -> Option<PathBuf> {
+    let _guard = EXTRACT_LOCK.lock().unwrap();
+
+    let mtime = fs::metadata(archive)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(|t| format!("{:?}", t))
+        .unwrap_or_default();
+    let id = fnv1a_hash(format!("{}{}", archive.display(), mtime).as_bytes());
+    let dir = cache_dir.join(format!("btf-{:016x}", id));
+    let done_marker = dir.join(".extracted");
+
+    if !done_marker.exists() {
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).ok()?;
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(archive)
+            .arg("-C")
+            .arg(&dir)
+            .status();
+        if !status.is_ok_and(|s| s.success()) {
+            log_err!("Failed to extract BTF archive {}", archive.display());
+            return None;
+        }
+        fs::write(&done_marker, "").ok()?;
+        log_dbg!(
+            BTFRE,
+            "Extracted {} to {}",
+            archive.display(),
+            dir.display()
+        );
+    }
+
+    Some(dir)
+}
+
+fn source_from_extracted(dir: &Path) -> Option<BtfSource> {
+    let mut files = Vec::new();
+    find_files(dir, 0, &mut files);
+    files.retain(|f| !f.ends_with(".extracted"));
+
+    if let Some(vmlinux) = files.iter().find(|f| f.ends_with("vmlinux")) {
+        return Some(BtfSource::Dir(vmlinux.parent()?.to_path_buf()));
+    }
+    match files.as_slice() {
+        [file] => Some(BtfSource::File(file.clone())),
+        _ => None,
+    }
+}
+
+impl BtfSource {
+    pub fn from_setting(setting: Option<&str>, cache_dir: Option<&Path>) -> BtfSource {
+        let Some(setting) = setting.filter(|s| !s.is_empty()) else {
+            return BtfSource::Running;
+        };
+        let path = PathBuf::from(setting);
+
+        let source = if path.is_dir() {
+            Some(BtfSource::Dir(path.clone()))
+        } else if is_archive(&path) {
+            cache_dir
+                .and_then(|cache_dir| extract_archive(&path, cache_dir))
+                .and_then(|dir| source_from_extracted(&dir))
+        } else if path.is_file() {
+            Some(BtfSource::File(path.clone()))
+        } else {
+            None
+        };
+
+        source.unwrap_or_else(|| {
+            log_err!(
+                "Invalid BTF source {}, using running kernel",
+                path.display()
+            );
+            BtfSource::Running
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self == BtfSource::Running
+    }
+
+    // File with BTF for the module, vmlinux is the base BTF
+    pub fn module_path(&self, module: &str) -> Option<PathBuf> {
+        let module = if module.is_empty() { "vmlinux" } else { module };
+        match self {
+            BtfSource::Running => Some(Path::new(BTF_DIR).join(module)),
+            BtfSource::Dir(dir) => Some(dir.join(module)),
+            BtfSource::File(file) if module == "vmlinux" => Some(file.clone()),
+            BtfSource::File(_) => None,
+        }
+    }
+
+    // Raw BTF data of the module, extracted from .BTF section for ELF files
+    pub fn module_bytes(&self, module: &str) -> Option<Vec<u8>> {
+        let data = fs::read(self.module_path(module)?).ok()?;
+        if !elf_mod::is_elf(&data) {
+            return Some(data);
+        }
+
+        let elf = Elf::parse(&data)?;
+        elf.section_data(".BTF").map(|btf| btf.to_vec())
+    }
+
+    // vmlinux first, then modules with BTF files
+    pub fn module_names(&self) -> Vec<String> {
+        let dir = match self {
+            BtfSource::Running => Path::new(BTF_DIR),
+            BtfSource::Dir(dir) => dir.as_path(),
+            BtfSource::File(_) => return vec!["vmlinux".to_string()],
+        };
+        let mut modules: Vec<String> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_file())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .filter(|name| name != "vmlinux")
+                    .collect()
+            })
+            .unwrap_or_default();
+        modules.sort();
+        modules.insert(0, "vmlinux".to_string());
+        modules
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            BtfSource::Running => BTF_DIR.to_string(),
+            BtfSource::Dir(path) | BtfSource::File(path) => path.display().to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf_mod::tests::build_elf;
+    use btf_rs::Btf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bpftrace-ls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_sources() {
+        let Ok(vmlinux) = fs::read(Path::new(BTF_DIR).join("vmlinux")) else {
+            return;
+        };
+        let dir = temp_dir("btf-source");
+
+        // Raw BTF file
+        let raw = dir.join("raw.btf");
+        fs::write(&raw, &vmlinux).unwrap();
+        let source = BtfSource::from_setting(raw.to_str(), None);
+        assert_eq!(source, BtfSource::File(raw.clone()));
+        assert_eq!(source.module_bytes("vmlinux").unwrap(), vmlinux);
+        assert!(source.module_bytes("mac80211").is_none());
+
+        // vmlinux ELF with .BTF section
+        let elf = dir.join("vmlinux-elf");
+        fs::write(&elf, build_elf(&[(".text", b"\x90"), (".BTF", &vmlinux)])).unwrap();
+        let source = BtfSource::from_setting(elf.to_str(), None);
+        let btf = Btf::from_bytes(&source.module_bytes("").unwrap()).unwrap();
+        assert!(btf.resolve_types_by_name("vfs_read").is_ok());
+
+        // Copy of /sys/kernel/btf packed to tarball
+        let btf_dir = dir.join("sys/kernel/btf");
+        fs::create_dir_all(&btf_dir).unwrap();
+        fs::write(btf_dir.join("vmlinux"), &vmlinux).unwrap();
+        let archive = dir.join("btf.tar.gz");
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(&dir)
+            .arg("sys")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let cache_dir = dir.join("cache");
+        let source = BtfSource::from_setting(archive.to_str(), Some(&cache_dir));
+        let BtfSource::Dir(extracted) = &source else {
+            panic!("Unexpected source {:?}", source);
+        };
+        assert!(extracted.starts_with(&cache_dir));
+        assert_eq!(source.module_bytes("vmlinux").unwrap(), vmlinux);
+
+        assert_eq!(
+            BtfSource::from_setting(Some("/nonexistent/btf"), None),
+            BtfSource::Running
+        );
+        assert_eq!(BtfSource::from_setting(None, None), BtfSource::Running);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
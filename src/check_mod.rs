@@ -0,0 +1,123 @@
+// Helpers shared by the server's own checks: format strings, stdlib signatures, tracepoint and
+// scratch variable members, USDT arguments and lints. Checks walk the syntax tree of the document
+// which is already kept in DOCUMENTS_STATE, the text is parsed here only when there is none, e.g.
+// for files of workspace which are not open.
+use json::object;
+use tree_sitter::{Node, Parser, Point, Tree};
+
+use crate::log_err;
+use crate::log_mod;
+use crate::parser;
+
+pub const SEVERITY_ERROR: u32 = 1;
+pub const SEVERITY_WARNING: u32 = 2;
+pub const SEVERITY_INFORMATION: u32 = 3;
+pub const SEVERITY_HINT: u32 = 4;
+
+pub fn parse(text: &str) -> Option<Tree> {
+    let mut parser = Parser::new();
+    if let Err(e) = parser.set_language(&tree_sitter_bpftrace::LANGUAGE.into()) {
+        log_err!("Failed to set bpftrace language {}", e);
+        return None;
+    }
+    parser.parse(text, None)
+}
+
+pub fn node_text<'a>(node: &Node, text: &'a str) -> &'a str {
+    node.utf8_text(text.as_bytes()).unwrap_or_default()
+}
+
+pub fn call_arguments<'t>(call: &Node<'t>) -> Vec<Node<'t>> {
+    let Some(arguments) = call.child_by_field_name("arguments") else {
+        return Vec::new();
+    };
+    let mut cursor = arguments.walk();
+    let args = arguments
+        .named_children(&mut cursor)
+        .filter(|arg| !arg.is_extra())
+        .collect();
+    args
+}
+
+// Probes of the action the node is in
+pub fn probes_for_node(node: &Node, text: &str) -> Vec<String> {
+    let mut parent = node.parent();
+    while let Some(p) = parent {
+        if p.kind() == "action" {
+            return parser::find_probes_for_action(&p, text);
+        }
+        parent = p.parent();
+    }
+    Vec::new()
+}
+
+// LSP counts characters in UTF-16 code units, tree-sitter columns are bytes
+pub fn utf16_column(text: &str, point: Point) -> usize {
+    let line = text.split('\n').nth(point.row).unwrap_or_default();
+    match line.get(..point.column) {
+        Some(head) => head.encode_utf16().count(),
+        None => point.column,
+    }
+}
+
+// Byte column of position sent by client in UTF-16 code units
+pub fn byte_column(line: &str, utf16_column: usize) -> usize {
+    let mut units = 0;
+    for (idx, c) in line.char_indices() {
+        if units >= utf16_column {
+            return idx;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+pub fn encode_range(text: &str, start: Point, end: Point) -> json::JsonValue {
+    object! {
+        "start": { "line": start.row, "character": utf16_column(text, start) },
+        "end": { "line": end.row, "character": utf16_column(text, end) },
+    }
+}
+
+pub fn encode_range_diag(
+    text: &str,
+    start: Point,
+    end: Point,
+    severity: u32,
+    message: String,
+) -> json::JsonValue {
+    object! {
+        "range": encode_range(text, start, end),
+        "severity": severity,
+        "source": "bpftrace-ls",
+        "message": message,
+    }
+}
+
+pub fn encode_diag(node: &Node, text: &str, severity: u32, message: String) -> json::JsonValue {
+    encode_range_diag(
+        text,
+        node.start_position(),
+        node.end_position(),
+        severity,
+        message,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf16_columns() {
+        let text = "begin {\n  printf(\"žluť %d\", 1) }";
+        // Byte column 17 is after "%d", 'ž' and 'ť' take two bytes but one UTF-16 unit
+        assert_eq!(utf16_column(text, Point::new(1, 17)), 15);
+        assert_eq!(utf16_column(text, Point::new(0, 5)), 5);
+
+        let line = text.split('\n').nth(1).unwrap();
+        assert_eq!(byte_column(line, 15), 17);
+        assert_eq!(byte_column(line, 100), line.len());
+        assert_eq!(byte_column("a😀b", 3), 5);
+    }
+}
@@ -1,12 +1,14 @@
 use std::env;
-use std::io;
-use std::process::Command;
-use std::process::Output;
-use std::sync::LazyLock;
-use std::sync::OnceLock;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::log_err;
-use crate::log_mod;
+use crate::config_mod;
+use crate::log_mod::{self, DIAGN};
+use crate::{log_dbg, log_err};
 
 static USE_SUDO: OnceLock<bool> = OnceLock::new();
 static USE_DRY_RUN: OnceLock<bool> = OnceLock::new();
@@ -19,7 +21,156 @@ impl CustomCommand {
 }
 static CUSTOM_COMMAND: LazyLock<CustomCommand> = LazyLock::new(CustomCommand::new);
 
-fn sudo_bpftrace_command(use_sudo: bool, args: &[&str]) -> io::Result<Output> {
+const SIGTERM: i32 = 15;
+const SIGKILL: i32 = 9;
+
+// Time given to the process group to exit after SIGTERM, before sending SIGKILL
+const KILL_GRACE_PERIOD: Duration = Duration::from_millis(200);
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+extern "C" {
+    fn kill(pid: i32, sig: i32) -> i32;
+}
+
+fn signal_process_group(pgid: u32, sig: i32) {
+    // SAFETY: kill(2) has no memory safety requirements, negative pid addresses the whole
+    // process group created for the child with process_group(0).
+    let ret = unsafe { kill(-(pgid as i32), sig) };
+    if ret != 0 {
+        log_dbg!(
+            DIAGN,
+            "Signal {} to process group {} failed: {}",
+            sig,
+            pgid,
+            io::Error::last_os_error()
+        );
+    }
+}
+
+// Read whole stream, but keep only first max_bytes. We continue to read after the limit is
+// reached, otherwise the child would block on full pipe.
+fn spawn_capped_reader<R: Read + Send + 'static>(
+    mut stream: R,
+    max_bytes: usize,
+) -> Arc<Mutex<Vec<u8>>> {
+    let buf = Arc::new(Mutex::new(Vec::new()));
+    let thread_buf = buf.clone();
+
+    thread::spawn(move || {
+        let mut chunk = [0u8; 8192];
+        loop {
+            match stream.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let mut buf = thread_buf.lock().unwrap();
+                    let room = max_bytes.saturating_sub(buf.len());
+                    buf.extend_from_slice(&chunk[..n.min(room)]);
+                }
+            }
+        }
+    });
+
+    buf
+}
+
+fn terminate_child(child: &mut Child) {
+    let pgid = child.id();
+
+    // sudo relays SIGTERM to the bpftrace process it started, what we can not do directly as
+    // bpftrace runs as root.
+    signal_process_group(pgid, SIGTERM);
+
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if let Ok(Some(_)) = child.try_wait() {
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    signal_process_group(pgid, SIGKILL);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+fn take_output(buf: &Arc<Mutex<Vec<u8>>>) -> Vec<u8> {
+    std::mem::take(&mut *buf.lock().unwrap())
+}
+
+// Run command in its own process group, kill the whole group when timeout expire
+pub fn run_command_with_limits(
+    cmd: &mut Command,
+    timeout: Duration,
+    max_output_bytes: usize,
+) -> io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+
+    let stdout_buf = spawn_capped_reader(child.stdout.take().unwrap(), max_output_bytes);
+    let stderr_buf = spawn_capped_reader(child.stderr.take().unwrap(), max_output_bytes);
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if start.elapsed() >= timeout {
+            log_err!("Command {:?} timed out after {:?}", cmd, timeout);
+            terminate_child(&mut child);
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("command timed out after {} ms", timeout.as_millis()),
+            ));
+        }
+
+        thread::sleep(POLL_INTERVAL);
+    };
+
+    // Child exited, but grandchildren can still keep pipes open, do not wait for them forever
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    while Arc::strong_count(&stdout_buf) > 1 || Arc::strong_count(&stderr_buf) > 1 {
+        if Instant::now() >= deadline {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    Ok(Output {
+        status,
+        stdout: take_output(&stdout_buf),
+        stderr: take_output(&stderr_buf),
+    })
+}
+
+// Run with preferred variant first and remember the one that works. Timeout says nothing about
+// the variant, e.g. machine can be just slow, so nothing is remembered and no retry is done.
+fn detect_variant<F>(detected: &OnceLock<bool>, preferred: bool, mut run: F) -> io::Result<Output>
+where
+    F: FnMut(bool) -> io::Result<Output>,
+{
+    if let Some(variant) = detected.get() {
+        return run(*variant);
+    }
+
+    match run(preferred) {
+        Ok(output) if output.status.success() => {
+            let _ = detected.set(preferred);
+            return Ok(output);
+        }
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => return Err(e),
+        _ => {}
+    }
+
+    let _ = detected.set(!preferred);
+    run(!preferred)
+}
+
+fn sudo_bpftrace_command(use_sudo: bool, args: &[&str], timeout: Duration) -> io::Result<Output> {
     let mut cmd = if use_sudo {
         Command::new("sudo")
     } else {
@@ -30,50 +181,34 @@ fn sudo_bpftrace_command(use_sudo: bool, args: &[&str]) -> io::Result<Output> {
         cmd.arg("bpftrace");
     }
 
-    cmd.args(args).output()
+    cmd.args(args);
+    run_command_with_limits(&mut cmd, timeout, config_mod::get().max_output_bytes)
 }
 
-pub fn bpftrace_command(args: &[&str]) -> io::Result<Output> {
+pub fn bpftrace_command(args: &[&str], timeout: Duration) -> io::Result<Output> {
     if let Some(custom_cmd) = &CUSTOM_COMMAND.0 {
-        return Command::new(custom_cmd).args(args).output();
+        let mut cmd = Command::new(custom_cmd);
+        cmd.args(args);
+        return run_command_with_limits(&mut cmd, timeout, config_mod::get().max_output_bytes);
     }
 
-    if let Some(use_sudo) = USE_SUDO.get() {
-        return sudo_bpftrace_command(*use_sudo, args);
-    }
-
-    if let Ok(output) = sudo_bpftrace_command(false, args) {
-        if output.status.success() {
-            let _ = USE_SUDO.set(false);
-            return Ok(output);
-        }
-    }
-
-    let _ = USE_SUDO.set(true);
-    sudo_bpftrace_command(true, args)
+    detect_variant(&USE_SUDO, false, |use_sudo| {
+        sudo_bpftrace_command(use_sudo, args, timeout)
+    })
 }
 
 pub fn bpftrace_dry_run_command(prog: &str) -> io::Result<Output> {
     let args_dry_run = vec!["--dry-run", "-e", prog];
     let args_d = vec!["-d", "-e", prog];
+    let timeout = config_mod::get().dry_run_timeout;
 
-    if let Some(use_dry_run) = USE_DRY_RUN.get() {
-        if *use_dry_run {
-            return bpftrace_command(&args_dry_run);
+    detect_variant(&USE_DRY_RUN, true, |use_dry_run| {
+        if use_dry_run {
+            bpftrace_command(&args_dry_run, timeout)
         } else {
-            return bpftrace_command(&args_d);
-        }
-    }
-
-    if let Ok(output) = bpftrace_command(&args_dry_run) {
-        if output.status.success() {
-            let _ = USE_DRY_RUN.set(true);
-            return Ok(output);
+            bpftrace_command(&args_d, timeout)
         }
-    }
-
-    let _ = USE_DRY_RUN.set(false);
-    bpftrace_command(&args_d)
+    })
 }
 
 pub fn init_bpftrace_dry_run() {
@@ -82,3 +217,60 @@ pub fn init_bpftrace_dry_run() {
         log_err!("Failed to detect bpftrace dry-run command, error {:?}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_output() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "echo out; echo err >&2"]);
+        let output = run_command_with_limits(&mut cmd, Duration::from_secs(5), 1024).unwrap();
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+    }
+
+    #[test]
+    fn test_command_output_is_capped() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "yes | head -c 100000"]);
+        let output = run_command_with_limits(&mut cmd, Duration::from_secs(5), 100).unwrap();
+
+        assert_eq!(output.stdout.len(), 100);
+    }
+
+    #[test]
+    fn test_command_timeout_kills_process_group() {
+        let start = Instant::now();
+        let mut cmd = Command::new("sh");
+        // Background sleep is in the same process group and keeps stdout open
+        cmd.args(["-c", "sleep 30 & sleep 30"]);
+        let result = run_command_with_limits(&mut cmd, Duration::from_millis(100), 1024);
+
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_timeout_does_not_detect_variant() {
+        let detected = OnceLock::new();
+        let mut runs = Vec::new();
+        let result = detect_variant(&detected, true, |variant| {
+            runs.push(variant);
+            Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert_eq!(runs, vec![true]);
+        assert_eq!(detected.get(), None);
+
+        // Failure of preferred variant falls back to the other one and remembers it
+        let result = detect_variant(&detected, true, |variant| {
+            Command::new(if variant { "false" } else { "true" }).output()
+        });
+        assert!(result.unwrap().status.success());
+        assert_eq!(detected.get(), Some(&false));
+    }
+}
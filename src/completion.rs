@@ -1,21 +1,40 @@
 use json::{self, object};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::str::Lines;
-use std::sync::{LazyLock, Mutex, OnceLock};
+use std::sync::{Arc, LazyLock, Mutex, OnceLock};
 use std::time::Instant;
 use tree_sitter::Node;
 
+use crate::btf_cache_mod::{self, first_module_type_id};
 use crate::btf_mod::{
-    btf_iterate_over_names_chain, btf_resolve_func, btf_setup_module, ResolvedBtfItem,
-    ResolvedVariable,
+    btf_enum_constants, btf_find_enum_values, btf_find_func_module, btf_find_struct_id,
+    btf_iterate_over_names_chain, btf_registry, btf_resolve_funcs, btf_resolve_raw_tracepoint,
+    btf_resolve_struct_layout, btf_setup_module, btf_struct_names, ResolvedBtfItem,
+    ResolvedVariable, StructLayout,
 };
+use crate::btf_source_mod::BtfSource;
+use crate::check_mod::{node_text, probes_for_node, utf16_column};
 use crate::cmd_mod::bpftrace_command;
+use crate::config_mod;
+use crate::format_string::{self, FORMAT_SPECIFIERS};
 use crate::gen::completion::{bpftrace_probe_providers, bpftrace_stdlib_functions};
+use crate::gen::signatures::stdlib_signature;
+use crate::history_mod;
 use crate::log_mod::{self, COMPL, HOVER};
 use crate::parser::{self, SyntaxLocation};
+use crate::proc_mod;
+use crate::trace_index_mod::{fuzzy_score, TraceIndex};
+use crate::trace_list_mod::native_traces_list;
+use crate::tracepoint::{
+    field_name, is_common_field, resolve_tracepoint_chain, tracepoint_args_layout,
+};
+use crate::uprobe::{self, path_completions, process_binary, UserBinary};
+use crate::usdt;
+use crate::var_types::{find_var_types, infer_expr_type, resolve_var_chain, VarType};
 use crate::DOCUMENTS_STATE;
 use crate::{log_dbg, log_err, log_vdbg};
-use btf_rs::Btf;
+use btf_rs::{Btf, Type};
 
 #[allow(unused)]
 #[derive(PartialEq, Clone, Copy)]
@@ -47,6 +66,18 @@ enum CompletionItemKind {
     TypeParameter = 25,
 }
 
+#[allow(unused)]
+enum InsertTextFormat {
+    PlainText = 1,
+    Snippet = 2,
+}
+
+impl From<InsertTextFormat> for json::JsonValue {
+    fn from(format: InsertTextFormat) -> json::JsonValue {
+        json::JsonValue::from(format as u8)
+    }
+}
+
 impl From<CompletionItemKind> for json::JsonValue {
     fn from(kind: CompletionItemKind) -> json::JsonValue {
         json::JsonValue::from(kind as u8)
@@ -56,12 +87,19 @@ impl From<CompletionItemKind> for json::JsonValue {
 static PROBES_ARGS_MAP: LazyLock<Mutex<HashMap<String, String>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
-static MODULE_BTF_MAP: LazyLock<Mutex<HashMap<String, Btf>>> =
-    LazyLock::new(|| Mutex::new(HashMap::new()));
+type SharedTraceIndex = Arc<OnceLock<Option<TraceIndex>>>;
 
-static AVAILABE_TRACES: OnceLock<Option<String>> = OnceLock::new();
+// Keyed by BTF source, traces of the source are listed once
+static TRACE_INDEXES: LazyLock<Mutex<HashMap<String, SharedTraceIndex>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
 
-static FENTRY_KFUNC_NAME: OnceLock<&'static str> = OnceLock::new();
+fn with_module_btf<T, F>(module: &str, f: F) -> Option<T>
+where
+    F: FnOnce(&Btf) -> Option<T>,
+{
+    let btf = btf_setup_module(module)?;
+    f(&btf)
+}
 
 fn btf_item_to_str(item: &ResolvedBtfItem, with_name: bool) -> String {
     let mut s = item.type_vec.join(" ").to_string();
@@ -75,7 +113,7 @@ fn btf_item_to_str(item: &ResolvedBtfItem, with_name: bool) -> String {
 }
 
 fn resolve_args_name_chain(
-    module: &String,
+    module: &str,
     resolved_func: &ResolvedBtfItem,
     this_argument: &str,
 ) -> Option<ResolvedVariable> {
@@ -94,16 +132,9 @@ fn resolve_args_name_chain(
     //     names_chain
     // );
 
-    let module_btf_map = MODULE_BTF_MAP.lock().unwrap();
-
-    if let Some(resolved_var) = module_btf_map
-        .get(module)
-        .and_then(|btf| btf_iterate_over_names_chain(btf, resolved_func, this_argument))
-    {
-        return Some(resolved_var);
-    }
-
-    None
+    with_module_btf(module, |btf| {
+        btf_iterate_over_names_chain(btf, resolved_func, this_argument)
+    })
 }
 
 fn is_fentry_probe(probe: &str) -> bool {
@@ -143,7 +174,9 @@ fn find_probe_args_by_command(probe: &str) -> String {
     let mut probe_args = "".to_string();
     if let Some(args) = probes_args_map.get(&probe) {
         probe_args = args.to_string();
-    } else if let Ok(output) = bpftrace_command(&["-l", "-v", &probe]) {
+    } else if let Ok(output) =
+        bpftrace_command(&["-l", "-v", &probe], config_mod::get().probe_info_timeout)
+    {
         if let Ok(stdout_probe_args) = String::from_utf8(output.stdout) {
             probe_args = stdout_probe_args.clone();
         }
@@ -162,41 +195,515 @@ fn find_probe_args_by_command(probe: &str) -> String {
     probe_args
 }
 
-fn find_kfunc_args_by_btf(kfunc: &str, need_retval: bool) -> Option<(String, ResolvedBtfItem)> {
+// All different prototypes of the kernel function, see btf_resolve_funcs()
+fn find_kfunc_prototypes_by_btf(
+    kfunc: &str,
+    need_retval: bool,
+) -> Option<(String, Vec<ResolvedBtfItem>)> {
     let kfunc_vec: Vec<&str> = kfunc.split(":").collect();
     log_dbg!(COMPL, "kfunc_vec {:?}", kfunc_vec);
 
-    if kfunc_vec.len() != 3 {
+    // Module can be omitted, e.g. fentry:tcp_sendmsg
+    let (module, func) = match kfunc_vec.len() {
+        2 => (btf_find_func_module(kfunc_vec[1])?, kfunc_vec[1]),
+        3 => (kfunc_vec[1].to_string(), kfunc_vec[2]),
+        _ => return None,
+    };
+    if module.is_empty() {
         return None;
     }
 
-    let module = kfunc_vec[1];
-    if module.is_empty() {
+    // Prefer on-disk index, so BTF does not need to be parsed for the first completion
+    let funcs = match btf_cache_mod::get(&module) {
+        Some(index) => index.find_funcs(func, need_retval),
+        None => with_module_btf(&module, |btf| {
+            Some(btf_resolve_funcs(btf, func, need_retval))
+        })
+        .unwrap_or_default(),
+    };
+    if funcs.is_empty() {
         return None;
     }
 
-    let mut module_btf_map = MODULE_BTF_MAP.lock().unwrap();
+    Some((module, funcs))
+}
 
-    let this_btf;
-    if let Some(btf) = module_btf_map.get(module) {
-        this_btf = btf;
-    } else {
-        log_dbg!(COMPL, "Looking for btf for module: {}", module);
-        if let Some(btf) = btf_setup_module(module) {
-            module_btf_map.insert(module.to_string(), btf);
-            this_btf = module_btf_map.get(module).unwrap();
-        } else {
-            return None;
+fn is_raw_tracepoint_probe(probe: &str) -> bool {
+    probe.starts_with("rawtracepoint:") || probe.starts_with("rt:")
+}
+
+// Raw tracepoint args from BTF, module can be omitted, e.g. rawtracepoint:sched_switch
+fn find_raw_tracepoint_args(probe: &str) -> Option<(String, ResolvedBtfItem)> {
+    if !is_raw_tracepoint_probe(probe) || probe.contains('*') {
+        return None;
+    }
+    let tokens: Vec<&str> = probe.split(':').collect();
+    let (module, name) = match tokens.len() {
+        2 => (
+            btf_find_func_module(&format!("__probestub_{}", tokens[1]))
+                .unwrap_or("vmlinux".to_string()),
+            tokens[1],
+        ),
+        3 => (tokens[1].to_string(), tokens[2]),
+        _ => return None,
+    };
+
+    let item = with_module_btf(&module, |btf| btf_resolve_raw_tracepoint(btf, name))?;
+    Some((module, item))
+}
+
+// When there are different prototypes the first one is used for completion
+fn find_kfunc_args_by_btf(kfunc: &str, need_retval: bool) -> Option<(String, ResolvedBtfItem)> {
+    let (module, funcs) = find_kfunc_prototypes_by_btf(kfunc, need_retval)?;
+    Some((module, funcs.into_iter().next()?))
+}
+
+fn func_prototypes_hover_str(probe: &str, funcs: &[ResolvedBtfItem]) -> String {
+    if let [func] = funcs {
+        return format!("{}:\n```c\n{}```", probe, func_proto_str(func));
+    }
+
+    let mut s = format!(
+        "{} has {} different prototypes in BTF, args are resolved using the first one:\n",
+        probe,
+        funcs.len()
+    );
+    for (i, func) in funcs.iter().enumerate() {
+        s.push_str(&format!(
+            "{}.\n```c\n{}\n```\n",
+            i + 1,
+            func_proto_str(func)
+        ));
+    }
+    s
+}
+
+// Check if there is BTF for the kernel function, so fentry/fexit can be used instead of kprobe
+pub fn kernel_func_has_btf(module: &str, func: &str) -> bool {
+    if let Some(index) = btf_cache_mod::get(module) {
+        return index.has_func(func);
+    }
+
+    with_module_btf(module, |btf| btf.resolve_types_by_name(func).ok())
+        .is_some_and(|types| types.iter().any(|t| matches!(t, Type::Func(_))))
+}
+
+// BTF type of args.* or retval expression, for fentry/fexit probes only
+pub fn resolve_args_type(probes_vec: &[String], args_chain: &str) -> Option<Vec<String>> {
+    let (is_kfunc, has_retval) = are_all_kfuncs(probes_vec);
+    if !is_kfunc {
+        return None;
+    }
+
+    let (module, resolved_func) = find_kfunc_list_arguments(probes_vec, has_retval)?;
+    let resolved_var = resolve_args_name_chain(&module, &resolved_func, args_chain)?;
+
+    Some(resolved_var.var.type_vec)
+}
+
+// Error numbers, most kernel functions return them negated. Numbers used only inside of kernel,
+// like ERESTARTSYS, are in linux/errno.h of kernel headers.
+const ERRNO_HEADERS: &[&str] = &[
+    "/usr/include/asm-generic/errno-base.h",
+    "/usr/include/asm-generic/errno.h",
+    "/lib/modules/{release}/build/include/linux/errno.h",
+];
+
+static ERRNO_NAMES: LazyLock<Vec<(String, i128)>> = LazyLock::new(|| {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    let mut names = Vec::new();
+    for header in ERRNO_HEADERS {
+        let path = header.replace("{release}", release.trim());
+        if let Ok(text) = std::fs::read_to_string(path) {
+            names.extend(parse_errno_header(&text));
+        }
+    }
+    names
+});
+
+// Lines like "#define	EAGAIN		11	/* Try again */", aliases like EWOULDBLOCK are skipped
+fn parse_errno_header(text: &str) -> Vec<(String, i128)> {
+    text.lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next() != Some("#define") {
+                return None;
+            }
+            let name = words.next().filter(|name| name.starts_with('E'))?;
+            let value = words.next()?.parse::<i128>().ok()?;
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+// Module BTF is split, so enums of vmlinux are found too
+fn find_enum_values(module: &str, name: &str) -> Option<Vec<(String, i128)>> {
+    if let Some(values) = btf_cache_mod::get(module).and_then(|index| index.find_enum(name)) {
+        return Some(values);
+    }
+    with_module_btf(module, |btf| btf_find_enum_values(btf, name))
+}
+
+// Index of a module has only its own types, types of vmlinux are in the vmlinux index
+fn index_modules(module: &str) -> Vec<&str> {
+    match module {
+        "" | "vmlinux" => vec!["vmlinux"],
+        _ => vec![module, "vmlinux"],
+    }
+}
+
+type EnumConstants = Arc<HashMap<String, (String, i128)>>;
+
+// Keyed by BTF source and module, used until the BTF index is ready
+static ENUM_CONSTANTS: LazyLock<Mutex<HashMap<(String, String), EnumConstants>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn btf_module_enum_constants(module: &str) -> Option<EnumConstants> {
+    let key = (btf_registry().source().describe(), module.to_string());
+    if let Some(constants) = ENUM_CONSTANTS.lock().unwrap().get(&key) {
+        return Some(constants.clone());
+    }
+
+    let constants = Arc::new(with_module_btf(module, |btf| {
+        Some(btf_enum_constants(btf))
+    })?);
+    ENUM_CONSTANTS
+        .lock()
+        .unwrap()
+        .insert(key, constants.clone());
+    Some(constants)
+}
+
+pub fn find_enum_constant(module: &str, name: &str) -> Option<(String, i128)> {
+    for index_module in index_modules(module) {
+        if let Some(index) = btf_cache_mod::get(index_module) {
+            if let Some(constant) = index.find_enum_constant(name) {
+                return Some(constant);
+            }
+        }
+    }
+    btf_module_enum_constants(module)?.get(name).cloned()
+}
+
+fn find_struct_layout(module: &str, name: &str) -> Option<StructLayout> {
+    for index_module in index_modules(module) {
+        if let Some(layout) =
+            btf_cache_mod::get(index_module).and_then(|index| index.find_struct_layout(name))
+        {
+            return Some(layout);
+        }
+    }
+    with_module_btf(module, |btf| {
+        btf_resolve_struct_layout(btf, btf_find_struct_id(btf, name)?)
+    })
+}
+
+// Left hand side of comparison with the operand at the end of head, e.g. args.mode for
+// "if (args.mode == HRTIMER_MO"
+fn comparison_lhs(head: &str) -> Option<&str> {
+    let head = head
+        .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_' || c == '-')
+        .trim_end();
+    let head = head
+        .strip_suffix("==")
+        .or_else(|| head.strip_suffix("!="))?
+        .trim_end();
+    let lhs = head
+        .rsplit([' ', '{', '[', ',', '!'])
+        .next()?
+        .trim_start_matches('(');
+
+    (lhs.starts_with("args.") || lhs.starts_with("retval")).then_some(lhs)
+}
+
+// Values that make sense to compare with lhs: enumerators for enum types and error numbers
+// for integer return values. Returns type name and the values.
+fn comparison_values(
+    probes_compl: &ProbesCompletion,
+    lhs: &str,
+) -> Option<(String, Vec<(String, i128)>)> {
+    let (module, resolved_func) = probes_compl.btf_probe_args.as_ref()?;
+    let var = resolve_args_name_chain(module, resolved_func, lhs)?.var;
+    let type_name = var.type_vec.iter().rev().find(|t| !t.is_empty())?;
+    if type_name.ends_with('*') {
+        return None;
+    }
+
+    if let Some(values) = find_enum_values(module, type_name) {
+        return Some((format!("enum {}", type_name), values));
+    }
+
+    let is_int = matches!(
+        type_name.as_str(),
+        "int" | "long" | "long int" | "ssize_t" | "s32" | "s64"
+    );
+    if lhs.starts_with("retval") && is_int {
+        let values = ERRNO_NAMES
+            .iter()
+            .map(|(name, value)| (name.clone(), -value))
+            .collect();
+        return Some(("errno".to_string(), values));
+    }
+
+    None
+}
+
+fn enum_decl_str(type_name: &str, values: &[(String, i128)]) -> String {
+    let mut s = format!("{} {{\n", type_name);
+    for (name, value) in values.iter() {
+        s.push_str(&format!("        {} = {},\n", name, value));
+    }
+    s.push_str("};");
+    s
+}
+
+// Enumerators or error numbers when completing right side of comparison
+fn add_comparison_values(
+    line_head: &str,
+    probes_compl: &ProbesCompletion,
+    items: &mut json::JsonValue,
+) {
+    let Some(lhs) = comparison_lhs(line_head) else {
+        return;
+    };
+    let Some((type_name, values)) = comparison_values(probes_compl, lhs) else {
+        return;
+    };
+
+    let is_errno = type_name == "errno";
+    for (name, value) in values {
+        let mut item = object! {
+            "label": name.clone(),
+            "kind": CompletionItemKind::EnumMember,
+            "detail": format!("{} {} = {}", type_name, name, value),
+        };
+        // bpftrace does not know errno names, so the number is inserted
+        if is_errno {
+            let number = if line_head.ends_with('-') {
+                -value
+            } else {
+                value
+            };
+            item["insertText"] = number.to_string().into();
+        }
+        let _ = items.push(item);
+    }
+}
+
+fn parse_integer_literal(s: &str) -> Option<i128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+    let value = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Struct and union definitions in C code, e.g. "struct foo {"
+fn c_struct_definitions(text: &str) -> Vec<String> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let mut names = Vec::new();
+    for keyword in ["struct ", "union "] {
+        for (pos, _) in text.match_indices(keyword) {
+            if text[..pos].ends_with(is_ident) {
+                continue;
+            }
+            let rest = text[pos + keyword.len()..].trim_start();
+            let (name, after) = rest.split_at(rest.find(|c| !is_ident(c)).unwrap_or(rest.len()));
+            if !name.is_empty() && after.trim_start().starts_with('{') {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+// Directory of the script, relative paths in it are relative to this directory
+fn script_dir(uri: &str) -> Option<&Path> {
+    uri.strip_prefix("file://")
+        .and_then(|path| Path::new(path).parent())
+}
+
+// Headers included by the script, nested includes are not followed
+fn included_headers(text: &str, uri: &str) -> Vec<PathBuf> {
+    let script_dir = script_dir(uri);
+    let system_dirs = [Path::new("/usr/include"), Path::new("/usr/local/include")];
+
+    let mut headers = Vec::new();
+    for line in text.lines() {
+        let Some(include) = line
+            .trim_start()
+            .strip_prefix('#')
+            .and_then(|l| l.trim_start().strip_prefix("include"))
+            .map(str::trim)
+        else {
+            continue;
+        };
+
+        let candidates: Vec<PathBuf> =
+            if let Some(name) = include.strip_prefix('"').and_then(|i| i.strip_suffix('"')) {
+                script_dir
+                    .iter()
+                    .chain(system_dirs.iter())
+                    .map(|dir| dir.join(name))
+                    .collect()
+            } else if let Some(name) = include.strip_prefix('<').and_then(|i| i.strip_suffix('>')) {
+                system_dirs.iter().map(|dir| dir.join(name)).collect()
+            } else {
+                continue;
+            };
+
+        if let Some(path) = candidates.into_iter().find(|path| path.is_file()) {
+            headers.push(path);
         }
     }
+    headers
+}
 
-    if let Some(ret) = btf_resolve_func(this_btf, kfunc_vec[2], need_retval) {
-        return Some((module.to_string(), ret));
+// Complete struct or union name after struct keyword, e.g. in cast (struct task_
+type StructNames = Arc<Vec<String>>;
+
+// Keyed by BTF source and module, names of module do not include vmlinux
+static STRUCT_NAMES: LazyLock<Mutex<HashMap<(String, String), StructNames>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Names are kept once they come from the BTF index, before that BTF is walked each time
+fn module_struct_names(module: &str) -> StructNames {
+    let key = (btf_registry().source().describe(), module.to_string());
+    if let Some(names) = STRUCT_NAMES.lock().unwrap().get(&key) {
+        return names.clone();
     }
 
+    let Some(index) = btf_cache_mod::get(module) else {
+        let first_id = first_module_type_id(module);
+        let names = with_module_btf(module, |btf| Some(btf_struct_names(btf, first_id)));
+        return Arc::new(names.unwrap_or_default());
+    };
+    let mut names: Vec<String> = index.struct_names().map(str::to_string).collect();
+    names.sort_unstable();
+    let names = Arc::new(names);
+    STRUCT_NAMES.lock().unwrap().insert(key, names.clone());
+    names
+}
+
+fn encode_completion_for_struct_names(
+    text: &str,
+    uri: &str,
+    module: &str,
+    prefix: &str,
+) -> json::JsonValue {
+    let mut names: Vec<String> = c_struct_definitions(text);
+    for header in included_headers(text, uri) {
+        if let Ok(header_text) = std::fs::read_to_string(&header) {
+            names.extend(c_struct_definitions(&header_text));
+        }
+    }
+
+    for index_module in index_modules(module) {
+        names.extend(
+            module_struct_names(index_module)
+                .iter()
+                .filter(|name| name.starts_with(prefix))
+                .cloned(),
+        );
+    }
+
+    names.retain(|name| name.starts_with(prefix));
+    names.sort_unstable();
+    names.dedup();
+
+    let max_count = 200;
+    let is_incomplete = names.len() > max_count;
+    let mut items = json::JsonValue::new_array();
+    for name in names.into_iter().take(max_count) {
+        let _ = items.push(object! {
+            "label": name,
+            "kind": CompletionItemKind::Struct,
+        });
+    }
+
+    object! {
+        "result": {
+            "isIncomplete": is_incomplete,
+            "items": items,
+        }
+    }
+}
+
+// Expression in parentheses at the end of head, e.g. "((struct task_struct *)curtask)"
+fn parenthesized_suffix(head: &str) -> Option<&str> {
+    let before = head.strip_suffix(')')?;
+    let mut depth = 1;
+    for (i, c) in before.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some(&head[i..]);
+        }
+    }
     None
 }
 
+// Type of expression and chain of member accesses up to the cursor, for
+//  - ((struct task_struct *)curtask)->mm->
+//  - curtask->mm->
+//  - $s->sk_socket-> where type of $s is inferred from its assignments
+// Type of the base expression is inferred the same way as for scratch variables.
+fn typed_expr_chain(
+    head: &str,
+    probes: &[String],
+    vars: &HashMap<String, VarType>,
+) -> Option<(VarType, String)> {
+    // Member name being typed is filtered by the client
+    let head = head.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    if !head.ends_with("->") && !head.ends_with('.') {
+        return None;
+    }
+
+    let chain_start = head
+        .rfind(|c: char| !(c.is_alphanumeric() || "_.->$".contains(c)))
+        .map(|i| i + 1)
+        .unwrap_or_default();
+    let chain = &head[chain_start..];
+
+    let base_len = chain.find(['.', '-']).unwrap_or(chain.len());
+    let (base, rest) = match chain.split_at(base_len) {
+        // Completed from arguments of the probe
+        ("args" | "retval", _) => return None,
+        ("", rest) => (parenthesized_suffix(&head[..chain_start])?, rest),
+        (base, rest) => (base, rest),
+    };
+    let var = infer_expr_type(base, probes, vars)?;
+    Some((var, rest.to_string()))
+}
+
+fn encode_completion_for_typed_expr(var: &VarType, chain: &str) -> Option<json::JsonValue> {
+    log_dbg!(
+        COMPL,
+        "Complete for {} with chain {}",
+        var.type_str(),
+        chain
+    );
+
+    let var = resolve_var_chain(var, chain)?;
+
+    Some(object! {
+        "result": {
+            "isIncomplete": false,
+            "items": items_from_resolved_btf(&var.var_type?),
+        }
+    })
+}
+
 fn items_from_resolved_btf(btf_item: &ResolvedBtfItem) -> json::JsonValue {
     let mut items = json::JsonValue::new_array();
 
@@ -204,10 +711,14 @@ fn items_from_resolved_btf(btf_item: &ResolvedBtfItem) -> json::JsonValue {
         if child.name == "retval" {
             continue;
         }
+        let mut detail = btf_item_to_str(child, false);
+        if let Some(kind) = &child.from_anonymous {
+            detail.push_str(&format!(" (in anonymous {})", kind));
+        }
         let completion = object! {
             "label": child.name.clone(),
             "kind" : 5,
-            "detail" : btf_item_to_str(child, false),
+            "detail" : detail,
             // TODO
             // "documentation" : field_type,
         };
@@ -278,6 +789,129 @@ fn are_all_kfuncs(probes_vec: &[String]) -> (bool, bool) {
     (is_kfunc, has_retval)
 }
 
+// Fields of tracepoint from format file, members of nested structs from BTF
+fn items_from_tracepoint(probe: &str, args_with_fields: &str) -> Option<json::JsonValue> {
+    let chain = args_with_fields.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    if chain != "args." {
+        let var = resolve_tracepoint_chain(probe, chain.strip_prefix("args")?)?;
+        return Some(items_from_resolved_btf(&var.var_type?));
+    }
+
+    let layout = tracepoint_args_layout(probe)?;
+    let mut items = json::JsonValue::new_array();
+    for member in layout.members.iter() {
+        let name = field_name(member);
+        if is_common_field(name) {
+            continue;
+        }
+        let completion = object! {
+            "label": name,
+            "kind" : 5,
+            "detail" : format!("{}{}", member.type_str, &member.name[name.len()..]),
+        };
+        let _ = items.push(completion);
+    }
+    Some(items)
+}
+
+type UprobeFunc = (Arc<UserBinary>, ResolvedBtfItem, bool);
+
+// Function of uprobe from BTF or DWARF of the binary, with its return value for the prototype
+// and flag if it is uretprobe
+fn find_uprobe_func(probe: &str, pid: Option<u32>) -> Option<UprobeFunc> {
+    let (path, func, is_ret) = uprobe::parse_uprobe(probe)?;
+    let binary = process_binary(&path, pid)?;
+    let resolved_func = binary.resolve_func(&func, true)?;
+    Some((binary, resolved_func, is_ret))
+}
+
+// Arguments available in the probe, retval only in uretprobe
+fn find_uprobe_args(probe: &str, pid: Option<u32>) -> Option<(Arc<UserBinary>, ResolvedBtfItem)> {
+    let (binary, mut resolved_func, is_ret) = find_uprobe_func(probe, pid)?;
+    if !is_ret {
+        resolved_func
+            .children_vec
+            .retain(|arg| arg.name != "retval");
+    }
+    Some((binary, resolved_func))
+}
+
+// Arguments of user space function, members of structs only when the binary has BTF
+fn items_from_uprobe(
+    probe: &str,
+    pid: Option<u32>,
+    args_with_fields: &str,
+) -> Option<json::JsonValue> {
+    let (binary, resolved_func) = find_uprobe_args(probe, pid)?;
+    let chain = args_with_fields.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    if chain == "args." {
+        return Some(items_from_resolved_btf(&resolved_func));
+    }
+
+    let var = binary.resolve_args_chain(&resolved_func, chain)?;
+    Some(items_from_resolved_btf(&var.var_type?))
+}
+
+fn uprobe_hover_str(probe: &str, pid: Option<u32>, args_with_fields: &str) -> Option<String> {
+    if args_with_fields == "args." {
+        let (_binary, resolved_func, _is_ret) = find_uprobe_func(probe, pid)?;
+        return Some(format!(
+            "Arguments of {}:\n```c\n{}\n```",
+            probe,
+            func_proto_str(&resolved_func)
+        ));
+    }
+
+    let (binary, resolved_func) = find_uprobe_args(probe, pid)?;
+    let name = args_with_fields.strip_prefix("args.")?;
+    let var = match resolved_func.children_vec.iter().find(|c| c.name == name) {
+        Some(arg) => arg.clone(),
+        None => {
+            binary
+                .resolve_args_chain(&resolved_func, args_with_fields)?
+                .var
+        }
+    };
+    Some(format!("```c\n{}\n```", btf_item_to_str(&var, true)))
+}
+
+// Field of tracepoint with its offset, or all fields for args.
+fn tracepoint_hover_str(probe: &str, args_with_fields: &str) -> Option<String> {
+    let layout = tracepoint_args_layout(probe)?;
+    if args_with_fields == "args." {
+        return Some(format!(
+            "Arguments of {}:\n```c\n{}\n```",
+            probe,
+            struct_layout_str(&layout)
+        ));
+    }
+
+    let chain = args_with_fields.strip_prefix("args")?;
+    let name = chain.strip_prefix('.')?;
+    if let Some(member) = layout.members.iter().find(|m| field_name(m) == name) {
+        let mut hover = format!(
+            "```c\n{} {}; /* offset {}, size {} */\n```",
+            member.type_str,
+            member.name,
+            member.bit_offset / 8,
+            member.size
+        );
+        let field = resolve_tracepoint_chain(probe, chain);
+        if let Some(type_layout) = field.and_then(|f| f.var_type).and_then(|var_type| {
+            with_module_btf("vmlinux", |btf| {
+                btf_resolve_struct_layout(btf, var_type.type_id)
+            })
+        }) {
+            hover.push_str(&format!("\n```c\n{}\n```", struct_layout_str(&type_layout)));
+        }
+        return Some(hover);
+    }
+
+    // Nested member, resolved through raw struct of the event
+    let var = resolve_tracepoint_chain(probe, chain)?;
+    Some(format!("```c\n{}\n```", btf_item_to_str(&var.var, true)))
+}
+
 // Complete args. i.e. kfunc:xe:__fini_dbm { printf("%s\n", str(args.drm->driver->name)) }
 fn encode_completion_for_args_or_retval(
     probes_compl: ProbesCompletion,
@@ -288,7 +922,14 @@ fn encode_completion_for_args_or_retval(
     let probes_vec = &probes_compl.probes_vec;
     let probe = probes_vec.first()?;
 
-    let items = if args_with_fields.ends_with("args.") && !probes_compl.is_kfunc {
+    let items = if let Some(items) = items_from_tracepoint(probe, args_with_fields) {
+        items
+    } else if let Some(items) = items_from_uprobe(probe, probes_compl.pid, args_with_fields) {
+        items
+    } else if args_with_fields.ends_with("args.")
+        && !probes_compl.is_kfunc
+        && probes_compl.btf_probe_args.is_none()
+    {
         let probe_args = find_probe_args_by_command(probe);
         let mut probe_args_iter = probe_args.lines();
 
@@ -320,24 +961,87 @@ fn encode_completion_for_args_or_retval(
     Some(data)
 }
 
-pub fn add_action_block_keywords(items: &mut json::JsonValue) {
+// Complete specifiers after % in printf-like format string
+fn encode_completion_for_format_specifiers() -> json::JsonValue {
+    let mut items = json::JsonValue::new_array();
+
+    for (specifier, description) in FORMAT_SPECIFIERS {
+        let item = object! {
+            "label": format!("%{}", specifier),
+            "kind": CompletionItemKind::Value,
+            "detail": description.to_string(),
+            "insertText": specifier.to_string(),
+            "filterText": specifier.to_string(),
+        };
+        let _ = items.push(item);
+    }
+
+    object! {
+        "result": {
+            "isIncomplete": false,
+            "items": items,
+        }
+    }
+}
+
+// Blocks inserted for control flow keywords when client supports snippets
+const KEYWORD_SNIPPETS: &[(&str, &str)] = &[
+    ("else", "else {\n\t$0\n}"),
+    ("for", "for (\\$${1:kv} : @${2:map}) {\n\t$0\n}"),
+    ("if", "if (${1:condition}) {\n\t$0\n}"),
+    ("unroll", "unroll(${1:count}) {\n\t$0\n}"),
+    ("while", "while (${1:condition}) {\n\t$0\n}"),
+];
+
+pub fn add_action_block_keywords(items: &mut json::JsonValue, snippets: bool) {
     let keywords = [
         "break", "continue", "else", "for", "if", "let", "offsetof", "return", "sizeof", "unroll",
         "while",
     ];
 
     for kw in keywords {
-        let item = object! {
+        let mut item = object! {
             "label": kw.to_owned(),
             "kind": CompletionItemKind::Keyword,
             // "detail": "TODO",
             // "documentation": "need better documentation",
         };
+        if let Some((_, snippet)) = KEYWORD_SNIPPETS.iter().find(|(k, _)| *k == kw) {
+            if snippets {
+                item["insertText"] = (*snippet).into();
+                item["insertTextFormat"] = InsertTextFormat::Snippet.into();
+            }
+        }
 
         let _ = items.push(item);
     }
 }
 
+// Call with placeholders for required parameters of the first overload, e.g. hist(${1:n})
+fn stdlib_call_snippet(name: &str) -> Option<String> {
+    let overload = stdlib_signature(name)?.overloads.first()?;
+    let params: Vec<String> = overload
+        .params
+        .iter()
+        .filter(|p| !p.optional)
+        .enumerate()
+        .map(|(i, p)| format!("${{{}:{}}}", i + 1, p.name))
+        .collect();
+    Some(format!("{}({})", name, params.join(", ")))
+}
+
+fn add_stdlib_snippets(items: &mut json::JsonValue) {
+    for item in items.members_mut() {
+        if item["kind"] != CompletionItemKind::Function as u8 {
+            continue;
+        }
+        if let Some(snippet) = item["label"].as_str().and_then(stdlib_call_snippet) {
+            item["insertText"] = snippet.into();
+            item["insertTextFormat"] = InsertTextFormat::Snippet.into();
+        }
+    }
+}
+
 fn add_action_block_variables(
     node: &Node,
     text: &str,
@@ -404,7 +1108,7 @@ fields using the dot syntax, e.g., `$x = str(args.filename);`. "#
 
     // TODO add only for probes where 'args' it is valid
     // TODO get details and doc for other probes i.e. tracepoint
-    if is_kfunc {
+    if is_kfunc || probes_compl.btf_probe_args.is_some() {
         if let Some((btf_details, btf_docs)) = get_details_and_docs(probes_compl, "args.", false) {
             details = btf_details;
             docs = btf_docs
@@ -430,6 +1134,26 @@ fn add_args_and_retval_keywords(probes_compl: &ProbesCompletion, items: &mut jso
     }
 
     add_args(probes_compl, probes_compl.is_kfunc, items);
+
+    // Raw tracepoint arguments are also available as argN
+    let is_raw_tracepoint = probes_compl
+        .probes_vec
+        .first()
+        .is_some_and(|probe| is_raw_tracepoint_probe(probe));
+    if let Some((_module, resolved_btf)) = probes_compl
+        .btf_probe_args
+        .as_ref()
+        .filter(|_| is_raw_tracepoint)
+    {
+        for (i, arg) in resolved_btf.children_vec.iter().enumerate() {
+            let item = object! {
+                "label": format!("arg{}", i),
+                "kind" : CompletionItemKind::Variable,
+                "detail" : btf_item_to_str(arg, true),
+            };
+            let _ = items.push(item);
+        }
+    }
 }
 
 fn encode_completion_for_action(
@@ -444,12 +1168,20 @@ fn encode_completion_for_action(
     // TODO preload btf module
     let mut items = json::JsonValue::new_array();
 
+    let snippets = config_mod::client_capabilities().snippet_support;
     bpftrace_stdlib_functions(&mut items);
-    add_action_block_keywords(&mut items);
+    if snippets {
+        add_stdlib_snippets(&mut items);
+    }
+    add_action_block_keywords(&mut items, snippets);
     add_action_block_variables(node, text, line_nr, char_nr, &mut items);
     add_source_file_macros(node, text, &mut items);
 
     if !probes_compl.probes_vec.is_empty() {
+        let line_str = text.lines().nth(line_nr).unwrap_or_default();
+        if let Some(line_head) = line_str.get(..=char_nr) {
+            add_comparison_values(line_head, &probes_compl, &mut items);
+        }
         add_args_and_retval_keywords(&probes_compl, &mut items);
     }
 
@@ -499,10 +1231,84 @@ fn func_proto_str(item: &ResolvedBtfItem) -> String {
     s
 }
 
+// pahole-like layout: byte offset and size of every member, bitfields and holes
+fn struct_layout_str(layout: &StructLayout) -> String {
+    let kind = if layout.is_union { "union" } else { "struct" };
+    let mut s = format!("{} {} {{\n", kind, layout.name);
+
+    let decls: Vec<String> = layout
+        .members
+        .iter()
+        .map(|m| {
+            let mut decl = m.type_str.clone();
+            if !decl.ends_with('*') {
+                decl.push(' ');
+            }
+            decl.push_str(&m.name);
+            if let Some(bits) = m.bitfield_size {
+                decl.push_str(&format!(":{}", bits));
+            }
+            decl.push(';');
+            decl
+        })
+        .collect();
+    let width = decls.iter().map(|d| d.len()).max().unwrap_or_default();
+
+    let mut end_bits = 0;
+    for (m, decl) in layout.members.iter().zip(decls.iter()) {
+        if !layout.is_union && m.bit_offset > end_bits {
+            let hole = m.bit_offset - end_bits;
+            if hole % 8 == 0 {
+                s.push_str(&format!("\n        /* XXX {} bytes hole */\n\n", hole / 8));
+            } else {
+                s.push_str(&format!("        /* XXX {} bits hole */\n", hole));
+            }
+        }
+
+        let offset = m.bit_offset / 8;
+        let comment = match m.bitfield_size {
+            Some(bits) => {
+                end_bits = end_bits.max(m.bit_offset + bits);
+                format!("/* {:>5}:{:<2} {:>5} */", offset, m.bit_offset % 8, m.size)
+            }
+            None => {
+                end_bits = end_bits.max(m.bit_offset + m.size as u32 * 8);
+                format!("/* {:>5}    {:>5} */", offset, m.size)
+            }
+        };
+        let comment = match &m.from_anonymous {
+            Some(kind) => format!("{} /* anonymous {} */", comment, kind),
+            None => comment,
+        };
+        s.push_str(&format!(
+            "        {:<width$} {}\n",
+            decl,
+            comment,
+            width = width
+        ));
+    }
+
+    s.push_str(&format!("\n        /* size: {} */\n", layout.size));
+    let padding_bits = (layout.size as u32 * 8).saturating_sub(end_bits);
+    if !layout.is_union && padding_bits > 0 {
+        if padding_bits % 8 == 0 {
+            s.push_str(&format!("        /* padding: {} */\n", padding_bits / 8));
+        } else {
+            s.push_str(&format!(
+                "        /* bit_padding: {} bits */\n",
+                padding_bits
+            ));
+        }
+    }
+    s.push_str("};");
+
+    s
+}
+
 fn bpftrace_get_traces_list() -> Option<String> {
     let start = Instant::now();
 
-    let Ok(output) = bpftrace_command(&["-l"]) else {
+    let Ok(output) = bpftrace_command(&["-l"], config_mod::get().list_timeout) else {
         log_err!("Failed to get output from bpftrace command");
         return None;
     };
@@ -522,14 +1328,59 @@ fn bpftrace_get_traces_list() -> Option<String> {
     Some(traces)
 }
 
+// Traces read from kernel, bpftrace -l is used only for providers which could not be read. It
+// lists traces of the running kernel, so it is not used for BTF of another kernel.
+fn build_trace_index(source: &BtfSource) -> Option<TraceIndex> {
+    let mut index = TraceIndex::from_list(&native_traces_list(source));
+
+    let missing = ["kprobe", "fentry", "tracepoint", "rawtracepoint"]
+        .iter()
+        .any(|provider| !index.has_provider(provider));
+    let missing = missing && source.is_running();
+    if missing {
+        if let Some(traces) = bpftrace_get_traces_list() {
+            let missing_traces: String = traces
+                .lines()
+                .filter(|line| {
+                    line.split_once(':')
+                        .is_some_and(|(provider, _)| !index.has_provider(provider))
+                })
+                .map(|line| format!("{}\n", line))
+                .collect();
+            index.extend(&missing_traces);
+        }
+    }
+
+    if index.len() == 0 {
+        return None;
+    }
+    log_dbg!(COMPL, "Indexed {} traces", index.len());
+    Some(index)
+}
+
+// Callers of the same source wait for the index to be built
+fn trace_index() -> SharedTraceIndex {
+    let source = btf_registry().source();
+    let index = TRACE_INDEXES
+        .lock()
+        .unwrap()
+        .entry(source.describe())
+        .or_default()
+        .clone();
+    index.get_or_init(|| build_trace_index(&source));
+    index
+}
+
 pub fn init_available_traces() {
-    let _ = AVAILABE_TRACES.get_or_init(bpftrace_get_traces_list);
+    let _ = trace_index();
 }
 
 fn encode_completion_for_line(
     prefix: &str,
     line_str: &str,
     short_prefix: Option<&str>,
+    pid: Option<u32>,
+    script_dir: Option<&Path>,
 ) -> Option<json::JsonValue> {
     log_dbg!(
         COMPL,
@@ -538,109 +1389,155 @@ fn encode_completion_for_line(
         short_prefix
     );
 
-    // TOOD separate traces for each type i.e. kprobe, tracepoint
-    // TODO add kretprobe, kretfunc support
-    let Some(available_traces) = AVAILABE_TRACES.get_or_init(bpftrace_get_traces_list) else {
+    // User space binaries are not listed by bpftrace
+    if prefix == "uprobe" || prefix == "uretprobe" {
+        return Some(encode_completion_for_uprobe(line_str, pid, script_dir));
+    }
+    if prefix == "usdt" {
+        return Some(encode_completion_for_usdt(line_str, pid, script_dir));
+    }
+
+    let trace_index = trace_index();
+    let Some(index) = trace_index.get().and_then(|index| index.as_ref()) else {
         return Some(encode_no_completion());
     };
 
-    let mut items = json::JsonValue::new_array();
-    let mut is_incomplete = false;
-
     let max_count = 200;
-    let mut count = max_count;
-    let mut duplicates: HashMap<String, u32> = HashMap::new();
+    let mut items = json::JsonValue::new_array();
 
-    let mut line_tokens: Vec<&str> = line_str.split(":").collect();
+    let mut line_tokens: Vec<&str> = line_str.trim().split(":").collect();
 
     if let Some(short_name) = short_prefix {
         assert!(line_str.trim().starts_with(short_name));
         line_tokens[0] = prefix;
     }
 
-    if line_tokens[0] == "kfunc"
-        || line_tokens[0] == "kretfunc"
-        || line_tokens[0] == "fentry"
-        || line_tokens[0] == "fexit"
-    {
-        let fentry_kfunc_name = FENTRY_KFUNC_NAME.get_or_init(||
-            // TOOD: rewrite this to have separte lines for each probe type
-            if available_traces.contains("\nfentry") {
-                "fentry"
-            } else {
-                "kfunc"
-            }
-        );
-        line_tokens[0] = fentry_kfunc_name;
-    } else if line_tokens[0] == "kretprobe" {
-        line_tokens[0] = "kprobe";
-    }
+    let provider = match line_tokens[0] {
+        "kfunc" | "kretfunc" | "fentry" | "fexit" if index.has_provider("fentry") => "fentry",
+        "kfunc" | "kretfunc" | "fentry" | "fexit" => "kfunc",
+        "kretprobe" => "kprobe",
+        provider => provider,
+    };
+    let query = line_tokens[1..].join(":");
+    log_dbg!(COMPL, "Searching for '{}' in {} traces", query, provider);
 
-    let search_line = line_tokens.join(":");
-    log_dbg!(COMPL, "Searching for line '{}'", search_line);
+    // Item for token following already typed ones, with prototype for functions
+    let token_item = |token: &str, is_last: bool| -> json::JsonValue {
+        let kind = if is_last {
+            CompletionItemKind::Property
+        } else {
+            CompletionItemKind::Module
+        };
+        let mut item = object! {
+            "label": token,
+            "kind": kind,
+        };
+        // Prototype from BTF is added in completionItem/resolve, module is found from the probe
+        if is_last && matches!(provider, "kfunc" | "fentry" | "rawtracepoint") {
+            let complete = &query[..query.rfind(':').map_or(0, |i| i + 1)];
+            item["data"] = object! {
+                "probe": format!("{}:{}{}", provider, complete, token),
+                "type": provider,
+            };
+        }
+        log_vdbg!(COMPL, "Adding complete item: {}", token);
+        item
+    };
 
-    for trace_line in available_traces.lines() {
-        if trace_line.trim().starts_with(search_line.trim()) {
-            //TODO: save matched tokens ans skip duplicate lines here
-            // log_vdbg!(COMPL, "Found available traces line '{}'", trace_line);
+    let last_token = match line_tokens[..] {
+        [_, .., last] => last,
+        _ => "",
+    };
+    let complete = &query[..query.len() - last_token.len()];
+    let is_last_token = |token: &str| -> bool {
+        index
+            .with_prefix(provider, &format!("{}{}:", complete, token))
+            .is_empty()
+    };
 
-            let trace_tokens: Vec<&str> = trace_line.split(":").collect();
+    // Candidates are ranked by tier: exact match, used before, prefix match, fuzzy match. Within
+    // tier by usage count or fuzzy score, then by name.
+    let mut candidates: Vec<(u8, i64, String, bool)> = Vec::new();
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut is_incomplete = false;
+    let rank_limit = max_count * 5;
+    let usage = |token: &str, is_last: bool| -> i64 {
+        match is_last {
+            true => history_mod::usage_count(provider, token) as i64,
+            false => 0,
+        }
+    };
 
-            let mut match_tokens = 0;
-            for i in 0..std::cmp::min(trace_tokens.len(), line_tokens.len()) {
-                if trace_tokens[i] != line_tokens[i] {
-                    break;
-                }
-                match_tokens += 1;
+    if query.contains(['*', '?']) {
+        // Traces matched by wildcard, tokens at position of the last typed one
+        let position = line_tokens.len() - 2;
+        for trace in index.wildcard(provider, &query) {
+            let trace_tokens: Vec<&str> = trace.split(':').collect();
+            let Some(token) = trace_tokens.get(position) else {
+                continue;
+            };
+            if seen.insert(token.to_string()) {
+                let is_last = position == trace_tokens.len() - 1;
+                candidates.push((2, -usage(token, is_last), token.to_string(), is_last));
             }
+            if candidates.len() >= rank_limit {
+                is_incomplete = true;
+                break;
+            }
+        }
+    } else {
+        let tokens = index.next_tokens(provider, &query, rank_limit);
+        is_incomplete = tokens.len() >= rank_limit;
+        for t in tokens {
+            let used = usage(t.token, t.is_last);
+            let tier = match (t.token == last_token, used > 0) {
+                (true, _) => 0,
+                (false, true) => 1,
+                (false, false) => 2,
+            };
+            seen.insert(t.token.to_string());
+            candidates.push((tier, -used, t.token.to_string(), t.is_last));
+        }
 
-            if trace_tokens.len() > match_tokens {
-                let label = trace_tokens[match_tokens];
-
-                match duplicates.get(label) {
-                    None => duplicates.insert(label.to_string(), 1),
-                    Some(_) => continue,
-                };
-
-                let kind = if match_tokens == trace_tokens.len() - 1 {
-                    CompletionItemKind::Property
-                } else {
-                    CompletionItemKind::Module
-                };
-
-                let mut item = object! {
-                    "label": label,
-                    "kind": kind,
-                    // "detail": "TODO",
-                    // "documentation": "need better documentation",
-                };
+        // Used probes which are not among listed prefix matches
+        for (name, count) in history_mod::used_names(provider) {
+            let matches = name.starts_with(last_token) || fuzzy_score(last_token, &name).is_some();
+            let is_known = index
+                .with_prefix(provider, &format!("{}{}", complete, name))
+                .first()
+                .is_some_and(|t| t[complete.len()..] == name);
+            if matches && is_known && seen.insert(name.clone()) {
+                candidates.push((1, -(count as i64), name, true));
+            }
+        }
 
-                if (trace_tokens[0] == "kfunc" || trace_tokens[0] == "fentry")
-                    && kind == CompletionItemKind::Property
-                {
-                    if let Some((_module, resolved_btf)) = find_kfunc_args_by_btf(trace_line, true)
-                    {
-                        item["detail"] = func_proto_str(&resolved_btf).into();
-                    }
+        if !last_token.is_empty() {
+            for (token, score) in index.fuzzy(provider, complete, last_token, rank_limit) {
+                if !seen.insert(token.to_string()) {
+                    continue;
                 }
-
-                log_vdbg!(
-                    COMPL,
-                    "Adding complete item: {} : {}",
-                    label,
-                    item["detail"].to_string()
-                );
-
-                let _ = items.push(item);
-                count -= 1;
-                if count < 0 {
-                    is_incomplete = true;
-                    break;
+                let is_last = is_last_token(token);
+                let used = usage(token, is_last);
+                match used > 0 {
+                    true => candidates.push((1, -used, token.to_string(), is_last)),
+                    false => candidates.push((3, -(score as i64), token.to_string(), is_last)),
                 }
             }
         }
     }
 
+    candidates.sort();
+    is_incomplete |= candidates.len() > max_count;
+    for (rank, (_tier, _order, token, is_last)) in candidates.iter().take(max_count).enumerate() {
+        let mut item = token_item(token, *is_last);
+        item["sortText"] = format!("{:04}", rank).into();
+        // Client filters by typed text, which does not match fuzzy or wildcard matches
+        if !token.starts_with(last_token) {
+            item["filterText"] = last_token.into();
+        }
+        let _ = items.push(item);
+    }
+
     let data = object! {
         "result": {
             "isIncomplete": is_incomplete,
@@ -651,6 +1548,178 @@ fn encode_completion_for_line(
     Some(data)
 }
 
+// Paths to binaries first, then functions from ELF symbols, e.g. uprobe:/bin/bash:readline
+fn encode_completion_for_uprobe(
+    line_str: &str,
+    pid: Option<u32>,
+    script_dir: Option<&Path>,
+) -> json::JsonValue {
+    let target = line_str
+        .trim()
+        .split_once(':')
+        .map(|(_, target)| target)
+        .unwrap_or_default();
+
+    let mut items = json::JsonValue::new_array();
+    let mut is_incomplete = false;
+    let max_count = 200;
+
+    if let Some((path, func_prefix)) = target.rsplit_once(':') {
+        let Some(binary) = process_binary(path, pid) else {
+            return encode_no_completion();
+        };
+        for func in binary
+            .functions
+            .iter()
+            .filter(|f| f.starts_with(func_prefix))
+        {
+            if items.len() >= max_count {
+                is_incomplete = true;
+                break;
+            }
+            let mut item = object! {
+                "label": func.clone(),
+                "kind": CompletionItemKind::Function,
+            };
+            if let Some(resolved) = binary.resolve_func(func, true) {
+                item["detail"] = func_proto_str(&resolved).into();
+            }
+            let _ = items.push(item);
+        }
+    } else {
+        is_incomplete = add_path_items(&mut items, target, pid, script_dir, max_count);
+    }
+
+    object! {
+        "result": {
+            "isIncomplete": is_incomplete,
+            "items": items,
+        }
+    }
+}
+
+// Objects mapped by target process, then directories, executables and libraries, returns true
+// when the list is truncated
+fn add_path_items(
+    items: &mut json::JsonValue,
+    partial: &str,
+    pid: Option<u32>,
+    script_dir: Option<&Path>,
+    max_count: usize,
+) -> bool {
+    // Labels are relative to the last typed directory, as for directory listing
+    let dir_len = partial.rfind('/').map_or(0, |i| i + 1);
+    let mapped: Vec<String> = pid
+        .map(proc_mod::mapped_objects)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|object| object.starts_with(partial))
+        .collect();
+    for object in mapped.iter() {
+        let _ = items.push(object! {
+            "label": object[dir_len..].to_string(),
+            "kind": CompletionItemKind::File,
+            "detail": format!("mapped by {}", pid.unwrap_or_default()),
+        });
+    }
+
+    for (name, is_dir) in path_completions(partial, script_dir, pid) {
+        if mapped.iter().any(|object| object[dir_len..] == name) {
+            continue;
+        }
+        if items.len() >= max_count {
+            return true;
+        }
+        let item = if is_dir {
+            object! {
+                "label": name.clone(),
+                "kind": CompletionItemKind::Folder,
+                "insertText": format!("{}/", name),
+            }
+        } else {
+            object! {
+                "label": name,
+                "kind": CompletionItemKind::File,
+            }
+        };
+        let _ = items.push(item);
+    }
+    false
+}
+
+// Paths first, then providers and probe names from ELF notes, e.g. usdt:/bin/app:provider:name
+fn encode_completion_for_usdt(
+    line_str: &str,
+    pid: Option<u32>,
+    script_dir: Option<&Path>,
+) -> json::JsonValue {
+    let target = line_str
+        .trim()
+        .split_once(':')
+        .map(|(_, target)| target)
+        .unwrap_or_default();
+
+    let mut items = json::JsonValue::new_array();
+    let mut is_incomplete = false;
+
+    match target.split(':').collect::<Vec<&str>>()[..] {
+        [partial] => is_incomplete = add_path_items(&mut items, partial, pid, script_dir, 200),
+        [path, provider_prefix] => {
+            let Some(binary) = process_binary(path, pid) else {
+                return encode_no_completion();
+            };
+            let mut providers: Vec<&str> = binary
+                .usdt
+                .iter()
+                .map(|p| p.provider.as_str())
+                .filter(|p| p.starts_with(provider_prefix))
+                .collect();
+            providers.sort();
+            providers.dedup();
+            for provider in providers {
+                let count = binary
+                    .usdt
+                    .iter()
+                    .filter(|p| p.provider == provider)
+                    .count();
+                let _ = items.push(object! {
+                    "label": provider,
+                    "kind": CompletionItemKind::Module,
+                    "detail": format!("{} probes", count),
+                });
+            }
+        }
+        [path, provider, name_prefix] => {
+            let Some(binary) = process_binary(path, pid) else {
+                return encode_no_completion();
+            };
+            for probe in binary
+                .usdt
+                .iter()
+                .filter(|p| p.provider == provider && p.name.starts_with(name_prefix))
+            {
+                let _ = items.push(object! {
+                    "label": probe.name.clone(),
+                    "kind": CompletionItemKind::Event,
+                    "detail": format!("{} arguments: {}", probe.args.len(), probe.args.join(" ")),
+                    "documentation": {
+                        "kind": "markdown",
+                        "value": format!("```c\n{}```", usdt::usdt_args_str(probe)),
+                    },
+                });
+            }
+        }
+        _ => return encode_no_completion(),
+    }
+
+    object! {
+        "result": {
+            "isIncomplete": is_incomplete,
+            "items": items,
+        }
+    }
+}
+
 pub fn add_empty_line_keywords(items: &mut json::JsonValue) {
     // TODO "config" is only allowed in preamble
     let keywords = ["config", "import", "macro", "let"];
@@ -667,11 +1736,67 @@ pub fn add_empty_line_keywords(items: &mut json::JsonValue) {
     }
 }
 
+// Whole probes for common tasks, label, snippet and description
+const PROBE_SNIPPETS: &[(&str, &str, &str)] = &[
+    (
+        "fentry count",
+        "fentry:vmlinux:${1:func} {\n\t@[${2:comm}] = count();\n}",
+        "Count calls of kernel function",
+    ),
+    (
+        "fentry latency",
+        "fentry:vmlinux:${1:func} {\n\t@start[tid] = nsecs;\n}\n\n\
+         fexit:vmlinux:${1:func} /@start[tid]/ {\n\t@ns = hist(nsecs - @start[tid]);\n\
+         \tdelete(@start[tid]);\n}",
+        "Histogram of kernel function latency",
+    ),
+    (
+        "kprobe count",
+        "kprobe:${1:func} {\n\t@[${2:comm}] = count();\n}",
+        "Count calls of kernel function",
+    ),
+    (
+        "tracepoint",
+        "tracepoint:${1:category}:${2:event} {\n\t$0\n}",
+        "Kernel tracepoint",
+    ),
+    (
+        "uprobe",
+        "uprobe:${1:binary}:${2:func} {\n\t$0\n}",
+        "User space function",
+    ),
+    (
+        "profile stacks",
+        "profile:hz:${1:99} {\n\t@[${2:kstack}] = count();\n}",
+        "Sample stacks on all CPUs",
+    ),
+    (
+        "interval print",
+        "interval:s:${1:1} {\n\tprint(@${2:map});\n\tclear(@${2:map});\n}",
+        "Print and clear map periodically",
+    ),
+];
+
+fn add_probe_snippets(items: &mut json::JsonValue) {
+    for (label, snippet, description) in PROBE_SNIPPETS {
+        let _ = items.push(object! {
+            "label": *label,
+            "kind": CompletionItemKind::Snippet,
+            "detail": *description,
+            "insertText": *snippet,
+            "insertTextFormat": InsertTextFormat::Snippet,
+        });
+    }
+}
+
 fn encode_completion_for_empty_line() -> json::JsonValue {
     let mut items = json::JsonValue::new_array();
 
     bpftrace_probe_providers(&mut items);
     add_empty_line_keywords(&mut items);
+    if config_mod::client_capabilities().snippet_support {
+        add_probe_snippets(&mut items);
+    }
 
     let data = object! {
         "result": {
@@ -695,7 +1820,11 @@ fn encode_no_completion() -> json::JsonValue {
     empty_data
 }
 
-fn encode_completion_for_probes(line_str: &str) -> json::JsonValue {
+fn encode_completion_for_probes(
+    line_str: &str,
+    pid: Option<u32>,
+    script_dir: Option<&Path>,
+) -> json::JsonValue {
     let prefixes = [
         ("begin", None),
         ("end", None),
@@ -707,6 +1836,9 @@ fn encode_completion_for_probes(line_str: &str) -> json::JsonValue {
         ("iter", Some("it")),
         ("hardware", Some("h")),
         ("software", Some("s")),
+        ("uprobe", Some("u")),
+        ("uretprobe", Some("ur")),
+        ("usdt", Some("U")),
         ("rawtracepoint", Some("rt")),
         ("tracepoint", Some("t")),
         ("kprobe", Some("k")),
@@ -722,7 +1854,9 @@ fn encode_completion_for_probes(line_str: &str) -> json::JsonValue {
             if !line_str.trim().starts_with(prefix.0) {
                 continue;
             }
-            if let Some(data) = encode_completion_for_line(prefix.0, line_str, None) {
+            if let Some(data) =
+                encode_completion_for_line(prefix.0, line_str, None, pid, script_dir)
+            {
                 return data;
             }
         }
@@ -742,7 +1876,9 @@ fn encode_completion_for_probes(line_str: &str) -> json::JsonValue {
                 continue;
             }
 
-            if let Some(data) = encode_completion_for_line(prefix.0, line_str, prefix.1) {
+            if let Some(data) =
+                encode_completion_for_line(prefix.0, line_str, prefix.1, pid, script_dir)
+            {
                 return data;
             }
         }
@@ -799,22 +1935,27 @@ struct ProbesCompletion {
     btf_probe_args: Option<(String, ResolvedBtfItem)>,
     is_kfunc: bool,
     has_retval: bool,
+    // Target process for uprobe binaries
+    pid: Option<u32>,
 }
 
 impl ProbesCompletion {
-    fn new(probes_vec: Vec<String>) -> ProbesCompletion {
+    fn new(probes_vec: Vec<String>, pid: Option<u32>) -> ProbesCompletion {
         let (is_kfunc, has_retval) = are_all_kfuncs(&probes_vec);
         let mut btf_probe_args = None;
         if is_kfunc {
             // TODO
             // let kfunc = kprobe_to_kfunc(probe);
             btf_probe_args = find_kfunc_list_arguments(&probes_vec, has_retval);
+        } else if let Some(probe) = probes_vec.first() {
+            btf_probe_args = find_raw_tracepoint_args(probe);
         }
         ProbesCompletion {
             probes_vec,
             btf_probe_args,
             is_kfunc,
             has_retval,
+            pid,
         }
     }
 }
@@ -835,7 +1976,35 @@ pub fn encode_completion(content: json::JsonValue) -> json::JsonValue {
     if loc == SyntaxLocation::Action {
         let probes_vec = parser::find_probes_for_action(&node, text);
         log_dbg!(COMPL, "Action completion for probes vec {:?}", probes_vec);
-        let probes_compl = ProbesCompletion::new(probes_vec);
+        if format_string::is_in_format_string(&node, text, line_nr, char_nr) {
+            if line_str.chars().nth(char_nr) == Some('%') {
+                return encode_completion_for_format_specifiers();
+            }
+            return encode_no_completion();
+        }
+
+        let probes_compl = ProbesCompletion::new(probes_vec, proc_mod::target_pid(text));
+        let module = probes_compl
+            .btf_probe_args
+            .as_ref()
+            .map(|(module, _)| module.clone())
+            .unwrap_or("vmlinux".to_string());
+        let line_head = line_str.get(..=char_nr).unwrap_or(line_str);
+
+        if is_struct_type_name(line_str, char_nr + 1) {
+            let prefix = line_head
+                .rsplit(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .next()
+                .unwrap_or_default();
+            return encode_completion_for_struct_names(text, &uri, &module, prefix);
+        }
+
+        let vars = find_var_types(&node, text, line_nr, char_nr);
+        if let Some((var, chain)) = typed_expr_chain(line_head, &probes_compl.probes_vec, &vars) {
+            if let Some(data) = encode_completion_for_typed_expr(&var, &chain) {
+                return data;
+            }
+        }
 
         if let Some(args) = parser::is_args_or_retval(line_str, char_nr) {
             // TODO handle probes with wildcard
@@ -861,7 +2030,7 @@ pub fn encode_completion(content: json::JsonValue) -> json::JsonValue {
                     "Parse error completion for probes vec {:?}",
                     probes_vec
                 );
-                let probes_compl = ProbesCompletion::new(probes_vec);
+                let probes_compl = ProbesCompletion::new(probes_vec, proc_mod::target_pid(text));
 
                 if let Some(data) = encode_completion_for_args_or_retval(probes_compl, &args) {
                     return data;
@@ -880,26 +2049,49 @@ pub fn encode_completion(content: json::JsonValue) -> json::JsonValue {
         };
 
         log_dbg!(COMPL, "Complete for line head: '{line_head}'");
-        return encode_completion_for_probes(line_head);
+        return encode_completion_for_probes(
+            line_head,
+            proc_mod::target_pid(text),
+            script_dir(&uri),
+        );
     }
 
     encode_no_completion()
 }
 
+// Details of probe items which need BTF, with data set in encode_completion_for_line
 pub fn encode_completion_resolve(content: json::JsonValue) -> json::JsonValue {
-    // TODO
-    log_dbg!(COMPL, "Completion resolve for: {}", content);
-
-    let params = content["params"].clone();
-    // TOOD: use clangd to get documentation ?
-    // params["documentation"] = "Do this MARKUP".into();
-    log_dbg!(COMPL, "documentation {}", params["documentation"]);
-
-    let data = object! {
-        "result": params,
+    let mut item = content["params"].clone();
+    let data = &content["params"]["data"];
+    log_dbg!(COMPL, "Completion resolve for: {}", data);
+
+    let probe = data["probe"].to_string();
+    let resolved = match data["type"].as_str() {
+        Some("kfunc") | Some("fentry") => find_kfunc_prototypes_by_btf(&probe, true),
+        Some("rawtracepoint") => {
+            find_raw_tracepoint_args(&probe).map(|(module, func)| (module, vec![func]))
+        }
+        _ => None,
     };
 
-    data
+    // BTF has no source locations, so the link is an identifier search in the latest kernel
+    if let Some((module, funcs)) = resolved.filter(|(_, funcs)| !funcs.is_empty()) {
+        item["detail"] = func_proto_str(&funcs[0]).into();
+        item["documentation"] = object! {
+            "kind": "markdown",
+            "value": format!(
+                "{}\n\nDefined in `{}`, [search for {} in Linux sources](https://elixir.bootlin.com/linux/latest/A/ident/{})",
+                func_prototypes_hover_str(&probe, &funcs),
+                module,
+                funcs[0].name,
+                funcs[0].name
+            ),
+        };
+    }
+
+    object! {
+        "result": item,
+    }
 }
 
 fn find_hover_str<LF, RF>(line: &str, char_nr: usize, lcond: LF, rcond: RF) -> String
@@ -934,6 +2126,17 @@ where
     found.to_string()
 }
 
+// Is the word at char_nr preceded by struct or union keyword
+fn is_struct_type_name(line: &str, char_nr: usize) -> bool {
+    let Some(before) = line.get(..char_nr) else {
+        return false;
+    };
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let before = before.trim_end_matches(is_ident).trim_end();
+    let keyword = &before[before.rfind(|c| !is_ident(c)).map_or(0, |i| i + 1)..];
+    keyword == "struct" || keyword == "union"
+}
+
 fn cmp_child(a: &ResolvedBtfItem, b: &ResolvedBtfItem) -> bool {
     if a.name != b.name {
         return false;
@@ -1038,10 +2241,51 @@ fn get_details_and_docs(
         docs.push_str(&format!("{}{};{}\n", c_open, hover_name, c_close));
     } else {
         details = hover_name;
+        if let Some(kind) = &resolved_variable.var.from_anonymous {
+            details.push_str(&format!(" (member of anonymous {})", kind));
+        }
         details.push_str(":\n");
     }
 
-    if let Some(var_type) = resolved_variable.var_type {
+    // Hover shows layout of structs and unions
+    let layout = resolved_variable
+        .var_type
+        .as_ref()
+        .filter(|var_type| {
+            hover && !is_args && var_type.type_vec.first() != Some(&"func".to_string())
+        })
+        .and_then(|var_type| {
+            with_module_btf(module, |btf| {
+                btf_resolve_struct_layout(btf, var_type.type_id)
+            })
+        });
+
+    let enum_values = resolved_variable
+        .var
+        .type_vec
+        .iter()
+        .rev()
+        .find(|t| !t.is_empty())
+        .filter(|_| hover && resolved_variable.var_type.is_none())
+        .and_then(|type_name| {
+            find_enum_values(module, type_name).map(|values| (type_name.clone(), values))
+        });
+
+    if let Some(layout) = layout {
+        docs.push_str(&format!(
+            "{}{}{}",
+            c_open,
+            struct_layout_str(&layout),
+            c_close
+        ));
+    } else if let Some((type_name, values)) = enum_values {
+        docs.push_str(&format!(
+            "{}{}{}",
+            c_open,
+            enum_decl_str(&format!("enum {}", type_name), &values),
+            c_close
+        ));
+    } else if let Some(var_type) = resolved_variable.var_type {
         let s = if is_args {
             &format!("{}struct args\n{{\n", c_open)
         } else {
@@ -1069,12 +2313,21 @@ fn get_details_and_docs(
             if child.name == "retval" {
                 continue;
             }
-            let s = format!(
-                "        {:<width$} {}; \n",
-                btf_item_to_str(child, false),
-                &child.name,
-                width = max_type_width
-            );
+            let s = match &child.from_anonymous {
+                Some(kind) => format!(
+                    "        {:<width$} {}; /* anonymous {} */\n",
+                    btf_item_to_str(child, false),
+                    &child.name,
+                    kind,
+                    width = max_type_width
+                ),
+                None => format!(
+                    "        {:<width$} {}; \n",
+                    btf_item_to_str(child, false),
+                    &child.name,
+                    width = max_type_width
+                ),
+            };
             docs.push_str(&s);
         }
 
@@ -1084,6 +2337,27 @@ fn get_details_and_docs(
     Some((details, docs))
 }
 
+// Type of scratch variable or its member, with layout if it is a struct
+fn var_hover_str(var: &VarType, expr: &str, chain: &str) -> Option<String> {
+    let expr_type = if chain.is_empty() {
+        var.clone()
+    } else {
+        VarType {
+            type_vec: resolve_var_chain(var, chain)?.var.type_vec,
+            module: var.module.clone(),
+        }
+    };
+
+    let mut hover = format!("```c\n{} {}\n```", expr_type.type_str(), expr);
+    let layout = expr_type
+        .struct_target()
+        .and_then(|(name, _)| find_struct_layout(&expr_type.module, &name));
+    if let Some(layout) = layout {
+        hover.push_str(&format!("\n```c\n{}\n```", struct_layout_str(&layout)));
+    }
+    Some(hover)
+}
+
 pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
     log_dbg!(HOVER, "Received hover with data {}", content);
     let (uri, line_nr, char_nr) = unpack_text_document_info(content);
@@ -1096,6 +2370,7 @@ pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
     };
 
     let (text, loc, node, line_str) = get_document_state!(text_doc, line_nr, char_nr, data, HOVER);
+    let pid = proc_mod::target_pid(text);
 
     if loc == SyntaxLocation::ProbesList {
         assert_eq!(node.kind(), "probes_list");
@@ -1111,15 +2386,39 @@ pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
         let probe = probe_node.utf8_text(text.as_bytes()).unwrap_or_default();
         log_dbg!(HOVER, "Hover for probe {}", probe);
 
+        if let Some(usdt_probe) = usdt::find_usdt_probe(probe, pid) {
+            return object! {
+                  "result": {
+                      "contents": usdt::usdt_hover_str(probe, &usdt_probe),
+                  },
+            };
+        }
+
+        if let Some((_binary, resolved_btf, _is_ret)) = find_uprobe_func(probe, pid) {
+            return object! {
+                  "result": {
+                      "contents": func_prototypes_hover_str(probe, &[resolved_btf]),
+                  },
+            };
+        }
+
+        if let Some((_module, resolved_btf)) = find_raw_tracepoint_args(probe) {
+            return object! {
+                  "result": {
+                      "contents": func_prototypes_hover_str(probe, &[resolved_btf]),
+                  },
+            };
+        }
+
         if !is_btf_probe(probe) {
             return empty_data;
         }
 
-        let args_by_btf = find_kfunc_args_by_btf(probe, true);
-        if let Some((_module, resolved_btf)) = args_by_btf {
+        let prototypes = find_kfunc_prototypes_by_btf(probe, true);
+        if let Some((_module, funcs)) = prototypes {
             data = object! {
                   "result": {
-                      "contents": format!("{}:\n```c\n{}```", probe, func_proto_str(&resolved_btf)),
+                      "contents": func_prototypes_hover_str(probe, &funcs),
                   },
             };
         }
@@ -1146,7 +2445,129 @@ pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
 
         let (is_kfunc, has_retval) = are_all_kfuncs(&probes_vec);
 
-        let btf_probe_args = find_kfunc_list_arguments(&probes_vec, has_retval);
+        let btf_probe_args = find_kfunc_list_arguments(&probes_vec, has_retval)
+            .or_else(|| find_raw_tracepoint_args(probes_vec.first()?));
+
+        // Raw tracepoint argN is the same as args.<name>
+        let arg_nr = found
+            .strip_prefix("arg")
+            .and_then(|n| n.parse::<usize>().ok())
+            .filter(|_| {
+                probes_vec
+                    .first()
+                    .is_some_and(|p| is_raw_tracepoint_probe(p))
+            });
+        if let Some(arg) = arg_nr.and_then(|n| btf_probe_args.as_ref()?.1.children_vec.get(n)) {
+            found = format!("args.{}", arg.name);
+        }
+
+        // USDT argN with its location, e.g. arg0 in %rdi
+        if let Some(n) = found
+            .strip_prefix("arg")
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            if let Some(spec) = probes_vec
+                .first()
+                .and_then(|probe| usdt::find_usdt_probe(probe, pid))
+                .and_then(|usdt_probe| usdt_probe.args.get(n).cloned())
+            {
+                return object! {
+                    "result": {
+                        "contents": format!(
+                            "```c\n{}: {}\n```\n{}",
+                            found,
+                            spec,
+                            usdt::arg_spec_description(&spec)
+                        ),
+                    },
+                };
+            }
+        }
+
+        // struct/union type name, e.g. in cast (struct task_struct *)curtask
+        if is_struct_type_name(line_str, char_nr) {
+            let module = btf_probe_args
+                .as_ref()
+                .map(|(module, _)| module.as_str())
+                .unwrap_or("vmlinux");
+            let name = found.trim_end_matches('*');
+            let Some(layout) = find_struct_layout(module, name) else {
+                return empty_data;
+            };
+
+            return object! {
+                  "result": {
+                      "contents": format!("```c\n{}\n```", struct_layout_str(&layout)),
+                  },
+            };
+        }
+
+        // Enum constant, e.g. TCP_ESTABLISHED
+        let is_constant_name = found.starts_with(|c: char| c.is_ascii_uppercase())
+            && found
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if is_constant_name {
+            let module = btf_probe_args
+                .as_ref()
+                .map(|(module, _)| module.as_str())
+                .unwrap_or("vmlinux");
+            let Some((enum_name, value)) = find_enum_constant(module, &found) else {
+                return empty_data;
+            };
+
+            return object! {
+                  "result": {
+                      "contents": format!("```c\nenum {} {} = {}\n```", enum_name, found, value),
+                  },
+            };
+        }
+
+        // User space function args, e.g. args.fd
+        if found.starts_with("args.") {
+            if let Some(hover) = probes_vec
+                .first()
+                .and_then(|probe| uprobe_hover_str(probe, pid, &found))
+            {
+                return object! {
+                    "result": {
+                        "contents": hover,
+                    },
+                };
+            }
+        }
+
+        // Tracepoint args, e.g. args.prev_comm
+        if found.starts_with("args.") {
+            if let Some(hover) = probes_vec
+                .first()
+                .and_then(|probe| tracepoint_hover_str(probe, &found))
+            {
+                return object! {
+                    "result": {
+                        "contents": hover,
+                    },
+                };
+            }
+        }
+
+        // Scratch variable with inferred type, e.g. $sk or $sk->sk_socket
+        if found.starts_with('$') {
+            let vars = find_var_types(&node, text, line_nr, char_nr);
+            let var_len = found.find(['.', '-']).unwrap_or(found.len());
+            let Some(var) = vars.get(&found[..var_len]) else {
+                return empty_data;
+            };
+            return match var_hover_str(var, &found, &found[var_len..]) {
+                Some(hover) => object! {
+                    "result": {
+                        "contents": hover,
+                    },
+                },
+                None => empty_data,
+            };
+        }
+
         if btf_probe_args.is_none() {
             return empty_data;
         }
@@ -1155,8 +2576,29 @@ pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
             btf_probe_args,
             is_kfunc,
             has_retval,
+            pid,
         };
 
+        // Integer literal compared with enum or retval is decoded, e.g. retval == -11
+        if let Some(literal) = parse_integer_literal(&found) {
+            let Some((type_name, values)) = line_str
+                .get(..=char_nr)
+                .and_then(comparison_lhs)
+                .and_then(|lhs| comparison_values(&probes_compl, lhs))
+            else {
+                return empty_data;
+            };
+            let Some((name, _)) = values.iter().find(|(_, value)| *value == literal) else {
+                return empty_data;
+            };
+
+            return object! {
+                  "result": {
+                      "contents": format!("```c\n{} {} = {}\n```", type_name, name, literal),
+                  },
+            };
+        }
+
         let Some((details, docs)) = get_details_and_docs(&probes_compl, &found, true) else {
             return empty_data;
         };
@@ -1174,10 +2616,101 @@ pub fn encode_hover(content: json::JsonValue) -> json::JsonValue {
     data
 }
 
+// Enumerator or error name of integer literal compared with args or retval, e.g. retval == -11
+fn comparison_hint(
+    node: &Node,
+    text: &str,
+    probes_cache: &mut HashMap<Vec<String>, ProbesCompletion>,
+) -> Option<json::JsonValue> {
+    let operator = node.child_by_field_name("operator")?;
+    if !matches!(node_text(&operator, text), "==" | "!=") {
+        return None;
+    }
+    let left = node.child_by_field_name("left")?;
+    let right = node.child_by_field_name("right")?;
+    let (lhs, literal) = match parse_integer_literal(node_text(&right, text)) {
+        Some(_) => (left, right),
+        None => (right, left),
+    };
+    let value = parse_integer_literal(node_text(&literal, text))?;
+    let lhs_str = node_text(&lhs, text);
+    if !lhs_str.starts_with("args.") && !lhs_str.starts_with("retval") {
+        return None;
+    }
+
+    let probes = probes_for_node(node, text);
+    let probes_compl = probes_cache
+        .entry(probes.clone())
+        .or_insert_with(|| ProbesCompletion::new(probes, None));
+    let (_, values) = comparison_values(probes_compl, lhs_str)?;
+    let (name, _) = values.iter().find(|(_, v)| *v == value)?;
+
+    let end = literal.end_position();
+    Some(object! {
+        "position": {
+            "line": end.row,
+            "character": utf16_column(text, end),
+        },
+        "label": name.clone(),
+        "paddingLeft": true,
+    })
+}
+
+fn collect_inlay_hints(
+    node: Node,
+    text: &str,
+    lines: (usize, usize),
+    probes_cache: &mut HashMap<Vec<String>, ProbesCompletion>,
+    hints: &mut json::JsonValue,
+) {
+    if node.end_position().row < lines.0 || node.start_position().row > lines.1 {
+        return;
+    }
+    if node.kind() == "binary_expression" {
+        if let Some(hint) = comparison_hint(&node, text, probes_cache) {
+            let _ = hints.push(hint);
+        }
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect_inlay_hints(child, text, lines, probes_cache, hints);
+    }
+}
+
+pub fn encode_inlay_hints(content: json::JsonValue) -> json::JsonValue {
+    let uri = content["params"]["textDocument"]["uri"].to_string();
+    let range = &content["params"]["range"];
+    let lines = (
+        range["start"]["line"].as_usize().unwrap_or_default(),
+        range["end"]["line"].as_usize().unwrap_or(usize::MAX),
+    );
+
+    let mut hints = json::JsonValue::new_array();
+    if let Some(text_doc) = DOCUMENTS_STATE.get(&uri) {
+        if let Some(tree) = &text_doc.syntax_tree {
+            let mut probes_cache = HashMap::new();
+            collect_inlay_hints(
+                tree.root_node(),
+                &text_doc.text,
+                lines,
+                &mut probes_cache,
+                &mut hints,
+            );
+        }
+    }
+    log_dbg!(HOVER, "Inlay hints for {}: {}", uri, hints);
+
+    object! {
+        "result": hints,
+    }
+}
+
 #[allow(clippy::len_zero)]
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::btf_mod::MemberLayout;
     use std::process::Command;
     use std::sync::atomic::{AtomicUsize, Ordering};
     static URI_COUNTER: AtomicUsize = AtomicUsize::new(0);
@@ -1414,6 +2947,18 @@ mod tests {
         check_completion_resutls(result, fields);
     }
 
+    #[test]
+    fn test_args_completion_without_module() {
+        let text = r#"fexit:posix_acl_alloc { retval-> }"#;
+        let json_content = document_content_setup(text, 0, text.len() - 2);
+
+        let result = encode_completion(json_content);
+        assert!(result["result"]["items"].len() > 0);
+
+        let fields = vec!["a_entries"];
+        check_completion_resutls(result, fields);
+    }
+
     #[test]
     fn test_args_completion_find_ge_pid_retval() {
         let text = r#"fexit:vmlinux:find_ge_pid { retval->stashed->d_parent }"#;
@@ -1426,6 +2971,98 @@ mod tests {
         check_completion_resutls(result, fields);
     }
 
+    #[test]
+    fn test_fuzzy_probes_completion() {
+        let text = "fentry:vmlinux:retransmit_sk";
+        let json_content = document_content_setup(text, 0, text.len());
+        let result = encode_completion(json_content);
+        let items = &result["result"]["items"];
+        let item = items
+            .members()
+            .find(|item| item["label"] == "tcp_retransmit_skb")
+            .unwrap();
+        assert_eq!(item["filterText"], "retransmit_sk");
+
+        // Prefix matches are ranked before fuzzy ones
+        let text = "fentry:vmlinux:vfs_rea";
+        let json_content = document_content_setup(text, 0, text.len());
+        let result = encode_completion(json_content);
+        let items = &result["result"]["items"];
+        assert!(items[0]["label"].to_string().starts_with("vfs_rea"));
+        assert_eq!(items[0]["sortText"], "0000");
+        assert!(items.members().any(|item| item["filterText"] == "vfs_rea"));
+    }
+
+    #[test]
+    fn test_completion_resolve() {
+        let text = "fentry:vmlinux:vfs_read";
+        let json_content = document_content_setup(text, 0, text.len());
+        let result = encode_completion(json_content);
+        let item = result["result"]["items"]
+            .members()
+            .find(|item| item["label"] == "vfs_read")
+            .unwrap()
+            .clone();
+        assert!(item["detail"].is_null());
+        assert_eq!(item["data"]["probe"], "fentry:vmlinux:vfs_read");
+        assert_eq!(item["data"]["type"], "fentry");
+
+        let resolved = encode_completion_resolve(object! { "params": item });
+        let detail = resolved["result"]["detail"].to_string();
+        assert!(
+            detail.starts_with("ssize_t vfs_read(struct file *file"),
+            "{}",
+            detail
+        );
+        let docs = resolved["result"]["documentation"]["value"].to_string();
+        assert!(docs.contains("Defined in `vmlinux`"), "{}", docs);
+        assert!(
+            docs.contains("[search for vfs_read in Linux sources](https://elixir.bootlin.com/"),
+            "{}",
+            docs
+        );
+    }
+
+    #[test]
+    fn test_snippets() {
+        let mut items = json::JsonValue::new_array();
+        add_action_block_keywords(&mut items, true);
+        let item = items.members().find(|item| item["label"] == "if").unwrap();
+        assert_eq!(item["insertText"], "if (${1:condition}) {\n\t$0\n}");
+        assert_eq!(item["insertTextFormat"], 2);
+
+        let mut items = json::JsonValue::new_array();
+        add_action_block_keywords(&mut items, false);
+        assert!(items.members().all(|item| item["insertText"].is_null()));
+
+        assert_eq!(stdlib_call_snippet("hist").unwrap(), "hist(${1:n})");
+        assert_eq!(stdlib_call_snippet("no_such_function"), None);
+
+        // Probe snippets are valid scripts with default placeholder values
+        let mut items = json::JsonValue::new_array();
+        add_probe_snippets(&mut items);
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(&tree_sitter_bpftrace::LANGUAGE.into())
+            .unwrap();
+        for item in items.members() {
+            let script = fill_placeholders(item["insertText"].as_str().unwrap());
+            let tree = parser.parse(&script, None).unwrap();
+            assert!(!tree.root_node().has_error(), "{}", script);
+        }
+    }
+
+    // Replaces ${n:default} with default and $0 with nothing
+    fn fill_placeholders(snippet: &str) -> String {
+        let mut script = snippet.replace("$0", "");
+        while let Some(start) = script.find("${") {
+            let end = start + script[start..].find('}').unwrap();
+            let default = script[start..end].split_once(':').unwrap().1.to_string();
+            script.replace_range(start..=end, &default);
+        }
+        script
+    }
+
     #[test]
     fn test_modules_completion_for_short_tracepoint() {
         let text = r#"t:"#;
@@ -1531,4 +3168,394 @@ fentry:vmlinux:vfs_writev,
         assert!(hover.contains(r"kfunc:vmlinux:posix_timer_fn"));
         assert!(hover.contains(r"hrtimer_restart posix_timer_fn(struct hrtimer *timer)"));
     }
+
+    #[test]
+    fn test_hover_struct_layout() {
+        let text = r#"fentry:vmlinux:vfs_open { print(args.path->mnt); }"#;
+        let json_content = document_content_setup(text, 0, 40);
+        let result = encode_hover(json_content);
+
+        let hover = result["result"]["contents"].as_str().unwrap();
+        assert!(hover.contains("struct path {"), "{}", hover);
+        assert!(hover.contains("struct vfsmount *mnt;  /*     0        8 */"));
+        assert!(hover.contains("struct dentry *dentry; /*     8        8 */"));
+        assert!(hover.contains("/* size: 16 */"));
+
+        let text = r#"kprobe:do_nanosleep { $t = (struct timespec64 *)arg0; }"#;
+        let json_content = document_content_setup(text, 0, 38);
+        let result = encode_hover(json_content);
+
+        let hover = result["result"]["contents"].as_str().unwrap();
+        assert!(hover.contains("struct timespec64 {"), "{}", hover);
+        assert!(hover.contains("tv_nsec;"));
+        assert!(hover.contains("/* size: 16 */"));
+    }
+
+    #[test]
+    fn test_enum_comparison_completion() {
+        let text = r#"fentry:vmlinux:hrtimer_start_range_ns { if (args.mode == HRT) { } }"#;
+        let json_content = document_content_setup(text, 0, 60);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["HRTIMER_MODE_ABS", "HRTIMER_MODE_REL"]);
+
+        let text = r#"fexit:vmlinux:vfs_open { if (retval == -) { } }"#;
+        let json_content = document_content_setup(text, 0, 40);
+        let result = encode_completion(json_content);
+        let items = &result["result"]["items"];
+        let eagain = items
+            .members()
+            .find(|item| item["label"] == "EAGAIN")
+            .unwrap();
+        assert_eq!(eagain["insertText"], "11");
+
+        assert_eq!(
+            comparison_lhs("if (args.sk->sk_state != TCP_"),
+            Some("args.sk->sk_state")
+        );
+        assert_eq!(comparison_lhs("$x = args.mode"), None);
+    }
+
+    #[test]
+    fn test_anonymous_members_completion() {
+        let text = r#"fentry:vmlinux:consume_skb { args.skb-> }"#;
+        let json_content = document_content_setup(text, 0, text.len() - 2);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["next", "dev", "len", "rbnode"]);
+
+        let text = r#"fentry:vmlinux:consume_skb { print(args.skb->dev); }"#;
+        let json_content = document_content_setup(text, 0, 47);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("(member of anonymous union)"), "{}", hover);
+    }
+
+    #[test]
+    fn test_typed_expr_completion() {
+        let text = r#"kprobe:do_nanosleep { print(((struct task_struct *)curtask)->mm->); }"#;
+        let json_content = document_content_setup(text, 0, 65);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["mmap_base", "pgd"]);
+
+        let text = r#"kprobe:do_nanosleep { print(curtask->real_parent->p); }"#;
+        let json_content = document_content_setup(text, 0, 51);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["pid", "comm"]);
+
+        let text =
+            "kprobe:tcp_sendmsg {\n  $s = (struct sock *)arg0;\n  print($s->__sk_common.);\n}";
+        let json_content = document_content_setup(text, 2, 24);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["skc_family", "skc_state"]);
+
+        let text = "fentry:tcp_sendmsg {\n  $sk = args.sk;\n  print($sk->);\n}";
+        let json_content = document_content_setup(text, 2, 13);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["sk_socket", "sk_rcvbuf"]);
+
+        let vars = HashMap::new();
+        let probes = vec!["kprobe:do_nanosleep".to_string()];
+        let (var, chain) =
+            typed_expr_chain("x = ((union bpf_attr*)arg0)->", &probes, &vars).unwrap();
+        assert_eq!(var.type_str(), "union bpf_attr *");
+        assert_eq!(chain, "->");
+        assert!(typed_expr_chain("x = ((int *)arg0)->", &probes, &vars)
+            .is_some_and(|(var, _)| var.struct_target().is_none()));
+        assert_eq!(typed_expr_chain("print(args.", &probes, &vars), None);
+    }
+
+    #[test]
+    fn test_struct_names_completion() {
+        let text = "struct my_data { int x; }\nkprobe:do_nanosleep { $t = (struct task_s *)arg0; $d = (struct my_ *)arg1; }";
+        let json_content = document_content_setup(text, 1, 41);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["task_struct"]);
+
+        let json_content = document_content_setup(text, 1, 66);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["my_data"]);
+
+        assert!(is_struct_type_name("(struct task", 10));
+        assert!(!is_struct_type_name("$x = mystruct task", 16));
+
+        // Module names do not repeat vmlinux
+        assert!(module_struct_names("vmlinux").contains(&"task_struct".to_string()));
+        if btf_setup_module("mac80211").is_some() {
+            let names = module_struct_names("mac80211");
+            assert!(names.contains(&"ieee80211_local".to_string()));
+            assert!(!names.contains(&"task_struct".to_string()));
+        }
+    }
+
+    #[test]
+    fn test_tracepoint_args() {
+        let text = "tracepoint:sched:sched_switch { print(args.); }";
+        let json_content = document_content_setup(text, 0, 43);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["prev_comm", "next_pid"]);
+
+        let json_content = document_content_setup(text, 0, 39);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("char prev_comm[16];"), "{}", hover);
+
+        let text = "tracepoint:sched:sched_switch { print(args.prev_pid); }";
+        let json_content = document_content_setup(text, 0, 46);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.contains("pid_t prev_pid; /* offset 24, size 4 */"),
+            "{}",
+            hover
+        );
+    }
+
+    #[test]
+    fn test_raw_tracepoint_args() {
+        let text = "rawtracepoint:sched_switch { print(args.); print(arg1); }";
+        let json_content = document_content_setup(text, 0, 40);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["prev", "next"]);
+
+        let json_content = document_content_setup(text, 0, 52);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("struct task_struct * prev"), "{}", hover);
+
+        let json_content = document_content_setup(text, 0, 5);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.contains("void sched_switch(bool preempt"),
+            "{}",
+            hover
+        );
+
+        let text = "rawtracepoint:vmlinux:sched_sw";
+        let json_content = document_content_setup(text, 0, text.len() - 1);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["sched_switch"]);
+    }
+
+    #[test]
+    fn test_uprobe_completion() {
+        let exe = std::fs::read_link("/proc/self/exe").unwrap();
+        let exe = exe.to_str().unwrap();
+
+        let text = format!("uprobe:{}", &exe[..exe.len() - 2]);
+        let json_content = document_content_setup(&text, 0, text.len() - 1);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec![exe.rsplit('/').next().unwrap()]);
+
+        // Objects mapped by target process
+        let text = format!("// bpftrace-ls: pid={}\nuprobe:", std::process::id());
+        let json_content = document_content_setup(&text, 1, 7);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec![exe]);
+
+        let text = format!("ur:{}:mai", exe);
+        let json_content = document_content_setup(&text, 0, text.len() - 1);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["main"]);
+
+        let text = format!("uprobe:{}:read_str {{ print(args.); }}", exe);
+        let pos = text.find("args.").unwrap() + 4;
+        let json_content = document_content_setup(&text, 0, pos + 1);
+        let result = encode_completion(json_content);
+        assert!(!result["result"]["items"]
+            .members()
+            .any(|item| item["label"] == "retval"));
+        check_completion_resutls(result, vec!["table", "offset"]);
+
+        let json_content = document_content_setup(&text, 0, pos);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.contains("&str read_str(&[u8] table, usize offset)"),
+            "{}",
+            hover
+        );
+    }
+
+    #[test]
+    fn test_usdt_completion() {
+        let lib = crate::usdt::tests::UsdtLibrary::new("completion");
+
+        let text = format!("U:{}:ser", lib.path());
+        let json_content = document_content_setup(&text, 0, text.len() - 1);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["server"]);
+
+        let text = format!("usdt:{}:server:req", lib.path());
+        let json_content = document_content_setup(&text, 0, text.len() - 1);
+        let result = encode_completion(json_content);
+        check_completion_resutls(result, vec!["request"]);
+
+        let text = format!("usdt:{}:server:request {{ print(arg1); }}", lib.path());
+        let json_content = document_content_setup(&text, 0, 10);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("has 2 arguments"), "{}", hover);
+        assert!(hover.contains("arg0: 8@%rdi"), "{}", hover);
+
+        let pos = text.find("arg1").unwrap() + 2;
+        let json_content = document_content_setup(&text, 0, pos);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("unsigned 64-bit in %rsi"), "{}", hover);
+    }
+
+    #[test]
+    fn test_hover_var_type() {
+        let text = "fentry:tcp_sendmsg { $sk = args.sk; print($sk->sk_socket); }";
+
+        let json_content = document_content_setup(text, 0, 43);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.starts_with("```c\nstruct sock * $sk\n```"),
+            "{}",
+            hover
+        );
+        assert!(hover.contains("struct sock {"), "{}", hover);
+
+        let json_content = document_content_setup(text, 0, 50);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.starts_with("```c\nstruct socket * $sk->sk_socket\n```"),
+            "{}",
+            hover
+        );
+    }
+
+    #[test]
+    fn test_hover_enum() {
+        let text = r#"fexit:vmlinux:hrtimer_start_range_ns { if (args.mode == 1 || args.mode == HRTIMER_MODE_ABS) { } }"#;
+
+        let json_content = document_content_setup(text, 0, 49);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("HRTIMER_MODE_REL = 1,"), "{}", hover);
+
+        let json_content = document_content_setup(text, 0, 56);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.contains("enum hrtimer_mode HRTIMER_MODE_REL = 1"),
+            "{}",
+            hover
+        );
+
+        let json_content = document_content_setup(text, 0, 75);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(
+            hover.contains("enum hrtimer_mode HRTIMER_MODE_ABS = 0"),
+            "{}",
+            hover
+        );
+
+        let text = r#"fexit:vmlinux:vfs_open { if (retval == -11) { } }"#;
+        let json_content = document_content_setup(text, 0, 41);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("errno EAGAIN = -11"), "{}", hover);
+    }
+
+    #[test]
+    fn test_inlay_hints() {
+        let text = "fexit:vmlinux:hrtimer_start_range_ns {\n  if (args.mode == 1 || 0 != args.mode) { }\n  if (retval == -11 || args.mode > 1) { } }";
+        let mut json_content = document_content_setup(text, 0, 0);
+        json_content["params"]["range"] = object! {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 2, "character": 0 },
+        };
+
+        let hints = encode_inlay_hints(json_content.clone())["result"].clone();
+        let labels: Vec<String> = hints.members().map(|h| h["label"].to_string()).collect();
+        assert_eq!(labels, ["HRTIMER_MODE_REL", "HRTIMER_MODE_ABS"]);
+        assert_eq!(hints[0]["position"]["line"], 1);
+        assert_eq!(hints[0]["position"]["character"], 20);
+
+        // Only lines of the range are checked
+        json_content["params"]["range"]["end"]["line"] = 0.into();
+        let hints = encode_inlay_hints(json_content)["result"].clone();
+        assert!(hints.is_empty());
+
+        let text = "fexit:vmlinux:vfs_open { if (retval == -11) { } }";
+        let mut json_content = document_content_setup(text, 0, 0);
+        json_content["params"]["range"] = object! {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 1, "character": 0 },
+        };
+        let hints = encode_inlay_hints(json_content)["result"].clone();
+        assert_eq!(hints[0]["label"], "EAGAIN");
+        assert_eq!(hints[0]["position"]["character"], 42);
+    }
+
+    #[test]
+    fn test_parse_errno_header() {
+        let header = "#define\tEAGAIN\t\t11\t/* Try again */\n#define\tEWOULDBLOCK\tEAGAIN\n#ifndef _ERRNO_H";
+        assert_eq!(parse_errno_header(header), [("EAGAIN".to_string(), 11)]);
+    }
+
+    #[test]
+    fn test_func_prototypes_hover() {
+        let func = |arg_type: &str| ResolvedBtfItem {
+            name: "cleanup".to_string(),
+            type_vec: vec!["func".to_string()],
+            children_vec: vec![ResolvedBtfItem {
+                name: "dev".to_string(),
+                type_vec: vec!["struct".to_string(), arg_type.to_string(), "*".to_string()],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let s = func_prototypes_hover_str("fentry:cleanup", &[func("device")]);
+        assert_eq!(
+            s,
+            "fentry:cleanup:\n```c\nvoid cleanup(struct device *dev);```"
+        );
+
+        let s = func_prototypes_hover_str("fentry:cleanup", &[func("device"), func("net_device")]);
+        assert!(
+            s.starts_with("fentry:cleanup has 2 different prototypes"),
+            "{}",
+            s
+        );
+        assert!(
+            s.contains("1.\n```c\nvoid cleanup(struct device *dev);"),
+            "{}",
+            s
+        );
+        assert!(
+            s.contains("2.\n```c\nvoid cleanup(struct net_device *dev);"),
+            "{}",
+            s
+        );
+
+        let text = r#"fentry:vmlinux:autofs_dev_ioctl { }"#;
+        let json_content = document_content_setup(text, 0, 20);
+        let hover = encode_hover(json_content)["result"]["contents"].to_string();
+        assert!(hover.contains("autofs_dev_ioctl("), "{}", hover);
+    }
+
+    #[test]
+    fn test_struct_layout_holes() {
+        let layout = StructLayout {
+            name: "s".to_string(),
+            is_union: false,
+            size: 16,
+            members: vec![
+                MemberLayout {
+                    name: "a".to_string(),
+                    type_str: "int".to_string(),
+                    bit_offset: 0,
+                    bitfield_size: Some(3),
+                    size: 4,
+                    from_anonymous: None,
+                },
+                MemberLayout {
+                    name: "p".to_string(),
+                    type_str: "void *".to_string(),
+                    bit_offset: 64,
+                    bitfield_size: None,
+                    size: 8,
+                    from_anonymous: None,
+                },
+            ],
+        };
+
+        let s = struct_layout_str(&layout);
+        assert!(s.contains("int a:3; /*     0:0      4 */"), "{}", s);
+        assert!(s.contains("/* XXX 61 bits hole */"), "{}", s);
+        assert!(s.contains("void *p; /*     8        8 */"), "{}", s);
+    }
 }
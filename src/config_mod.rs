@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+
+use crate::log_dbg;
+use crate::log_mod::{self, NOTIF};
+
+// Server settings, can be set by client with "initializationOptions" on initialize request and
+// later changed by "workspace/didChangeConfiguration" notification. Settings are accepted either
+// directly or nested under "bpftrace-ls" key.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub dry_run_timeout: Duration,
+    pub list_timeout: Duration,
+    pub probe_info_timeout: Duration,
+    pub max_output_bytes: usize,
+    pub diagnostics_debounce: Duration,
+    pub max_parallel_diagnostics: usize,
+    // Lint rule id to LSP severity, None when the rule is turned off
+    pub lint_severity: HashMap<String, Option<u32>>,
+    // BTF of other kernel than the running one, see btf_source_mod
+    pub btf_source: Option<String>,
+    pub dry_run_on_alternate_btf: bool,
+    // Process of `bpftrace -p PID`, see proc_mod
+    pub target_pid: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dry_run_timeout: Duration::from_secs(10),
+            list_timeout: Duration::from_secs(60),
+            probe_info_timeout: Duration::from_secs(10),
+            max_output_bytes: 16 * 1024 * 1024,
+            diagnostics_debounce: Duration::from_millis(300),
+            max_parallel_diagnostics: 4,
+            lint_severity: HashMap::new(),
+            btf_source: None,
+            dry_run_on_alternate_btf: false,
+            target_pid: None,
+        }
+    }
+}
+
+static CONFIG: LazyLock<RwLock<Config>> = LazyLock::new(|| RwLock::new(Config::default()));
+
+// Incremented on every update, results computed with older settings can be recognized by it
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+pub fn get() -> Config {
+    CONFIG.read().unwrap().clone()
+}
+
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Acquire)
+}
+
+fn settings_root(settings: &json::JsonValue) -> &json::JsonValue {
+    if settings["bpftrace-ls"].is_object() {
+        &settings["bpftrace-ls"]
+    } else {
+        settings
+    }
+}
+
+fn update_duration_ms(value: &json::JsonValue, duration: &mut Duration) {
+    if let Some(ms) = value.as_u64() {
+        *duration = Duration::from_millis(ms);
+    }
+}
+
+fn parse_severity(value: &str) -> Option<Option<u32>> {
+    match value {
+        "off" => Some(None),
+        "error" => Some(Some(1)),
+        "warning" => Some(Some(2)),
+        "info" | "information" => Some(Some(3)),
+        "hint" => Some(Some(4)),
+        _ => None,
+    }
+}
+
+// "lint": { "printf-newline": "off", "prefer-fentry": "hint" }
+fn parse_lint_severity(lint: &json::JsonValue) -> HashMap<String, Option<u32>> {
+    let mut lint_severity = HashMap::new();
+    for (rule, value) in lint.entries() {
+        match value.as_str().and_then(parse_severity) {
+            Some(severity) => {
+                lint_severity.insert(rule.to_string(), severity);
+            }
+            None => log_dbg!(NOTIF, "Invalid severity {} for lint rule {}", value, rule),
+        }
+    }
+    lint_severity
+}
+
+pub fn update_from_json(settings: &json::JsonValue) {
+    if !settings.is_object() {
+        return;
+    }
+    let root = settings_root(settings);
+
+    let mut config = CONFIG.write().unwrap();
+
+    update_duration_ms(&root["dryRunTimeoutMs"], &mut config.dry_run_timeout);
+    update_duration_ms(&root["listTimeoutMs"], &mut config.list_timeout);
+    update_duration_ms(&root["probeInfoTimeoutMs"], &mut config.probe_info_timeout);
+
+    if let Some(max_output) = root["maxOutputBytes"].as_usize() {
+        config.max_output_bytes = max_output;
+    }
+
+    update_duration_ms(
+        &root["diagnosticsDebounceMs"],
+        &mut config.diagnostics_debounce,
+    );
+    if let Some(max_parallel) = root["maxParallelDiagnostics"].as_usize() {
+        config.max_parallel_diagnostics = max_parallel.max(1);
+    }
+
+    if root.has_key("btfSource") {
+        config.btf_source = root["btfSource"].as_str().map(|s| s.to_string());
+    }
+    if let Some(dry_run) = root["dryRunOnAlternateBtf"].as_bool() {
+        config.dry_run_on_alternate_btf = dry_run;
+    }
+    if root.has_key("targetPid") {
+        config.target_pid = root["targetPid"].as_u32();
+    }
+
+    // Rules removed from settings go back to their default severity
+    config.lint_severity = parse_lint_severity(&root["lint"]);
+
+    GENERATION.fetch_add(1, Ordering::AcqRel);
+    log_dbg!(NOTIF, "Configuration updated: {:?}", *config);
+}
+
+// What client told us about itself in initialize request
+#[derive(Debug, Clone, Default)]
+pub struct ClientCapabilities {
+    pub pull_diagnostics: bool,
+    // Client handles workspace/diagnostic/refresh request
+    pub diagnostic_refresh_support: bool,
+    // Completion items can have insertTextFormat Snippet
+    pub snippet_support: bool,
+    pub workspace_folders: Vec<String>,
+}
+
+static CLIENT_CAPABILITIES: LazyLock<RwLock<ClientCapabilities>> =
+    LazyLock::new(|| RwLock::new(ClientCapabilities::default()));
+
+pub fn client_capabilities() -> ClientCapabilities {
+    CLIENT_CAPABILITIES.read().unwrap().clone()
+}
+
+pub fn set_client_capabilities(params: &json::JsonValue) {
+    let mut client = CLIENT_CAPABILITIES.write().unwrap();
+
+    client.pull_diagnostics = params["capabilities"]["textDocument"]["diagnostic"].is_object();
+    client.diagnostic_refresh_support = params["capabilities"]["workspace"]["diagnostics"]
+        ["refreshSupport"]
+        .as_bool()
+        .unwrap_or_default();
+    client.snippet_support = params["capabilities"]["textDocument"]["completion"]["completionItem"]
+        ["snippetSupport"]
+        .as_bool()
+        .unwrap_or_default();
+
+    client.workspace_folders.clear();
+    for folder in params["workspaceFolders"].members() {
+        if let Some(uri) = folder["uri"].as_str() {
+            client.workspace_folders.push(uri.to_string());
+        }
+    }
+    if client.workspace_folders.is_empty() {
+        if let Some(uri) = params["rootUri"].as_str() {
+            client.workspace_folders.push(uri.to_string());
+        } else if let Some(path) = params["rootPath"].as_str() {
+            client.workspace_folders.push(format!("file://{}", path));
+        }
+    }
+
+    log_dbg!(NOTIF, "Client capabilities: {:?}", *client);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use json::object;
+
+    #[test]
+    fn test_parse_lint_severity() {
+        let lint = object! { "printf-newline": "off", "prefer-fentry": "hint", "x": "bad" };
+        let severity = parse_lint_severity(&lint);
+        assert_eq!(severity.len(), 2);
+        assert_eq!(severity["printf-newline"], None);
+        assert_eq!(severity["prefer-fentry"], Some(4));
+
+        assert!(parse_lint_severity(&json::JsonValue::Null).is_empty());
+    }
+}
@@ -0,0 +1,770 @@
+use json::object;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::btf_mod;
+use crate::check_mod;
+use crate::config_mod;
+use crate::format_string;
+use crate::lint;
+use crate::log_mod::{self, DIAGN};
+use crate::proc_mod;
+use crate::signature_check;
+use crate::tracepoint;
+use crate::usdt;
+use crate::var_types;
+use crate::{do_bpftrace_diagnostics, do_parser_diagnostics, encode_response, JSON_RPC_VERSION};
+use crate::{log_dbg, log_err, DOCUMENTS_STATE};
+use crate::{DiagnosticsCommand, DiagnosticsResutls, MpscMessage, TextDocument};
+use tree_sitter::Tree;
+
+pub struct DiagnosticsDone {
+    uri: String,
+    version: u64,
+    text_hash: u64,
+    config_generation: u64,
+    diagnostics: json::JsonValue,
+    lints: json::JsonValue,
+}
+
+// Dry-run result depends on the text and on settings like btfSource or targetPid
+struct CheckedText {
+    text_hash: u64,
+    config_generation: u64,
+    diagnostics: json::JsonValue,
+}
+
+// Keeps only the latest requested version for each document and decides when the dry-run can
+// be started: after debounce delay and when less than the limit of dry-runs is running.
+#[derive(Default)]
+struct DiagnosticsScheduler {
+    pending: HashMap<String, Instant>,
+    running: HashSet<String>,
+}
+
+impl DiagnosticsScheduler {
+    fn request(&mut self, uri: String, now: Instant, debounce: Duration) {
+        // Every new request for the document postpones the check
+        self.pending.insert(uri, now + debounce);
+    }
+
+    fn finish(&mut self, uri: &str) {
+        self.running.remove(uri);
+    }
+
+    fn cancel(&mut self, uri: &str) {
+        self.pending.remove(uri);
+    }
+
+    fn take_due(&mut self, now: Instant, max_parallel: usize) -> Vec<String> {
+        let mut due: Vec<(Instant, String)> = self
+            .pending
+            .iter()
+            .filter(|(uri, deadline)| **deadline <= now && !self.running.contains(*uri))
+            .map(|(uri, deadline)| (*deadline, uri.clone()))
+            .collect();
+        due.sort();
+
+        let free = max_parallel.max(1).saturating_sub(self.running.len());
+        due.truncate(free);
+
+        due.into_iter()
+            .map(|(_, uri)| {
+                self.pending.remove(&uri);
+                self.running.insert(uri.clone());
+                uri
+            })
+            .collect()
+    }
+
+    fn next_deadline(&self) -> Option<Instant> {
+        self.pending
+            .iter()
+            .filter(|(uri, _)| !self.running.contains(*uri))
+            .map(|(_, deadline)| *deadline)
+            .min()
+    }
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn spawn_dry_run(
+    uri: String,
+    text_doc: Arc<TextDocument>,
+    text_hash: u64,
+    diag_tx: mpsc::Sender<DiagnosticsCommand>,
+) {
+    let config_generation = config_mod::generation();
+    thread::spawn(move || {
+        let version = text_doc.version;
+        log_dbg!(DIAGN, "Start dry-run for {} version {}", uri, version);
+        let diagnostics = dry_run_diagnostics(&text_doc.text);
+        let lints = static_checks(&uri, &text_doc.text, text_doc.syntax_tree.as_ref());
+
+        let done = DiagnosticsDone {
+            uri,
+            version,
+            text_hash,
+            config_generation,
+            diagnostics,
+            lints,
+        };
+        let _ = diag_tx.send(DiagnosticsCommand::Done(done));
+    });
+}
+
+// bpftrace checks the script against the running kernel. With BTF of another kernel configured,
+// dry-run is skipped or its messages are labelled, so they are not confused with the target.
+fn dry_run_diagnostics(text: &str) -> json::JsonValue {
+    if btf_mod::btf_targets_running_kernel() {
+        return do_bpftrace_diagnostics(text);
+    }
+    if !config_mod::get().dry_run_on_alternate_btf {
+        log_dbg!(DIAGN, "Dry-run skipped, BTF is not for the running kernel");
+        return json::JsonValue::new_array();
+    }
+
+    let mut diagnostics = do_bpftrace_diagnostics(text);
+    for diag in diagnostics.members_mut() {
+        diag["message"] = format!("[running kernel] {}", diag["message"]).into();
+    }
+    diagnostics
+}
+
+// Checks done by the server itself, without bpftrace, on syntax tree of open document or of the
+// text parsed here
+fn static_checks(uri: &str, text: &str, tree: Option<&Tree>) -> json::JsonValue {
+    let parsed;
+    let tree = match tree {
+        Some(tree) => tree,
+        None => match check_mod::parse(text) {
+            Some(tree) => {
+                parsed = tree;
+                &parsed
+            }
+            None => return json::JsonValue::new_array(),
+        },
+    };
+    let root = tree.root_node();
+
+    let mut diagnostics = format_string::check_tree(root, text);
+    for checks in [
+        signature_check::check_tree(root, text),
+        tracepoint::check_tree(root, text),
+        usdt::check_tree(root, text),
+        var_types::check_tree(root, text),
+        lint::lint_tree(uri, root, text),
+    ] {
+        diagnostics = with_lints(diagnostics, checks);
+    }
+    diagnostics
+}
+
+fn with_lints(mut diagnostics: json::JsonValue, lints: json::JsonValue) -> json::JsonValue {
+    for lint in lints.members() {
+        let _ = diagnostics.push(lint.clone());
+    }
+    diagnostics
+}
+
+fn send_results(
+    mpsc_tx: &mpsc::Sender<MpscMessage>,
+    uri: String,
+    version: u64,
+    diagnostics: json::JsonValue,
+) {
+    let diag_msg = DiagnosticsResutls {
+        uri,
+        version,
+        diagnostics,
+    };
+    let _res = mpsc_tx.send(MpscMessage::Diagnostics(diag_msg));
+}
+
+pub fn thread_diagnostics(
+    mpsc_tx: mpsc::Sender<MpscMessage>,
+    diag_tx: mpsc::Sender<DiagnosticsCommand>,
+    diag_rx: mpsc::Receiver<DiagnosticsCommand>,
+) {
+    let mut scheduler = DiagnosticsScheduler::default();
+    let mut checked: HashMap<String, CheckedText> = HashMap::new();
+
+    loop {
+        let msg = match scheduler.next_deadline() {
+            Some(deadline) => {
+                diag_rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => diag_rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+
+        match msg {
+            Ok(DiagnosticsCommand::DiagRequest(diag_req)) => {
+                let debounce = config_mod::get().diagnostics_debounce;
+                log_dbg!(
+                    DIAGN,
+                    "Schedule diagnostics for {} version {} in {:?}",
+                    diag_req.uri,
+                    diag_req.version,
+                    debounce
+                );
+                scheduler.request(diag_req.uri, Instant::now(), debounce);
+            }
+            Ok(DiagnosticsCommand::Done(done)) => {
+                scheduler.finish(&done.uri);
+                send_results(
+                    &mpsc_tx,
+                    done.uri.clone(),
+                    done.version,
+                    with_lints(done.diagnostics.clone(), done.lints),
+                );
+                // Document could be closed while dry-run was running
+                if DOCUMENTS_STATE.get(&done.uri).is_some() {
+                    checked.insert(
+                        done.uri,
+                        CheckedText {
+                            text_hash: done.text_hash,
+                            config_generation: done.config_generation,
+                            diagnostics: done.diagnostics,
+                        },
+                    );
+                }
+            }
+            Ok(DiagnosticsCommand::Close(uri)) => {
+                log_dbg!(DIAGN, "Forget diagnostics of closed {}", uri);
+                scheduler.cancel(&uri);
+                checked.remove(&uri);
+            }
+            Ok(DiagnosticsCommand::Exit) => {
+                log_dbg!(DIAGN, "Exit diagnostics thread");
+                break;
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => {
+                log_err!("Diagnostics MPSC disconnected");
+                break;
+            }
+        }
+
+        let max_parallel = config_mod::get().max_parallel_diagnostics;
+        for uri in scheduler.take_due(Instant::now(), max_parallel) {
+            let Some(text_doc) = DOCUMENTS_STATE.get(&uri) else {
+                log_err!("Can not find document for {uri}");
+                scheduler.finish(&uri);
+                continue;
+            };
+
+            let hash = text_hash(&text_doc.text);
+            let generation = config_mod::generation();
+            if let Some(prev) = checked
+                .get(&uri)
+                .filter(|prev| prev.text_hash == hash && prev.config_generation == generation)
+            {
+                log_dbg!(
+                    DIAGN,
+                    "Text not changed for {} version {}, reuse diagnostics",
+                    uri,
+                    text_doc.version
+                );
+                // Lint settings could change since the last check
+                let lints = static_checks(&uri, &text_doc.text, text_doc.syntax_tree.as_ref());
+                scheduler.finish(&uri);
+                send_results(
+                    &mpsc_tx,
+                    uri,
+                    text_doc.version,
+                    with_lints(prev.diagnostics.clone(), lints),
+                );
+                continue;
+            }
+
+            spawn_dry_run(uri, text_doc, hash, diag_tx.clone());
+        }
+    }
+}
+
+// Diagnostics for pull model (textDocument/diagnostic), last results for each document
+struct CachedDiagnostics {
+    version: u64,
+    result_id: String,
+    diagnostics: json::JsonValue,
+}
+
+static DIAGNOSTICS_CACHE: LazyLock<Mutex<HashMap<String, CachedDiagnostics>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Pull requests waiting for dry-run to finish: request id and uri
+static PENDING_PULLS: LazyLock<Mutex<Vec<(u64, String)>>> =
+    LazyLock::new(|| Mutex::new(Vec::new()));
+
+// Diagnostics depend on the text, on settings, e.g. lint rules or btfSource, and on the target
+// process whose binaries are checked by USDT lints
+fn result_id(text: &str) -> String {
+    format!(
+        "{:016x}-{}-{}",
+        text_hash(text),
+        config_mod::generation(),
+        proc_mod::target_key(text)
+    )
+}
+
+pub fn cache_diagnostics(uri: &str, version: u64, text: &str, diagnostics: &json::JsonValue) {
+    let cached = CachedDiagnostics {
+        version,
+        result_id: result_id(text),
+        diagnostics: diagnostics.clone(),
+    };
+    DIAGNOSTICS_CACHE
+        .lock()
+        .unwrap()
+        .insert(uri.to_string(), cached);
+}
+
+fn encode_report(
+    result_id: &str,
+    previous_result_id: Option<&str>,
+    diagnostics: &json::JsonValue,
+) -> json::JsonValue {
+    if previous_result_id == Some(result_id) {
+        object! {
+            "kind": "unchanged",
+            "resultId": result_id,
+        }
+    } else {
+        object! {
+            "kind": "full",
+            "resultId": result_id,
+            "items": diagnostics.clone(),
+        }
+    }
+}
+
+// Returns None when diagnostics for current document version are not ready yet, the request is
+// then answered by answer_pending_pulls() when dry-run finish.
+pub fn encode_document_diagnostic(id: u64, content: &json::JsonValue) -> Option<json::JsonValue> {
+    let params = &content["params"];
+    let uri = params["textDocument"]["uri"].to_string();
+    let previous_result_id = params["previousResultId"].as_str();
+
+    let empty = json::JsonValue::new_array();
+
+    let Some(text_doc) = DOCUMENTS_STATE.get(&uri) else {
+        return Some(object! { "result": encode_report("", None, &empty) });
+    };
+
+    let current_id = result_id(&text_doc.text);
+    if previous_result_id == Some(&current_id) || text_doc.text.trim().is_empty() {
+        return Some(object! { "result": encode_report(&current_id, previous_result_id, &empty) });
+    }
+
+    if let Some(cached) = DIAGNOSTICS_CACHE.lock().unwrap().get(&uri) {
+        if cached.version == text_doc.version && cached.result_id == current_id {
+            let report = encode_report(&cached.result_id, previous_result_id, &cached.diagnostics);
+            return Some(object! { "result": report });
+        }
+    }
+
+    log_dbg!(DIAGN, "Pull diagnostics for {} deferred", uri);
+    PENDING_PULLS.lock().unwrap().push((id, uri));
+    None
+}
+
+pub fn answer_pending_pulls(uri: &str) -> Vec<String> {
+    let cache = DIAGNOSTICS_CACHE.lock().unwrap();
+    let Some(cached) = cache.get(uri) else {
+        return Vec::new();
+    };
+
+    let mut pending = PENDING_PULLS.lock().unwrap();
+    let mut responses = Vec::new();
+
+    pending.retain(|(id, pending_uri)| {
+        if pending_uri != uri {
+            return true;
+        }
+        let report = encode_report(&cached.result_id, None, &cached.diagnostics);
+        responses.push(encode_response(*id, object! { "result": report }));
+        false
+    });
+
+    responses
+}
+
+// Waiting pull requests of closed document get empty report, there will be no dry-run for them
+pub fn forget_document(uri: &str) -> Vec<String> {
+    DIAGNOSTICS_CACHE.lock().unwrap().remove(uri);
+
+    let mut responses = Vec::new();
+    PENDING_PULLS.lock().unwrap().retain(|(id, pending_uri)| {
+        if pending_uri != uri {
+            return true;
+        }
+        let report = encode_report("", None, &json::JsonValue::new_array());
+        responses.push(encode_response(*id, object! { "result": report }));
+        false
+    });
+    responses
+}
+
+// Server request asking client to pull diagnostics again, e.g. after settings changed
+pub fn encode_refresh_request() -> String {
+    static REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+    let data = object! {
+        "jsonrpc": JSON_RPC_VERSION,
+        "id": REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        "method": "workspace/diagnostic/refresh",
+    };
+    let req = data.dump();
+    format!("Content-Length: {}\r\n\r\n{}", req.len(), req)
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+
+    let bytes = path.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(decoded).ok().map(PathBuf::from)
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut uri = "file://".to_string();
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+const MAX_WORKSPACE_FILES: usize = 1000;
+const MAX_WORKSPACE_DEPTH: usize = 16;
+
+fn find_bt_files(dir: &Path, depth: usize, files: &mut Vec<PathBuf>) {
+    if depth > MAX_WORKSPACE_DEPTH || files.len() >= MAX_WORKSPACE_FILES {
+        return;
+    }
+
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let path = entry.path();
+
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            if name.starts_with('.') || name == "target" || name == "node_modules" {
+                continue;
+            }
+            find_bt_files(&path, depth + 1, files);
+        } else if file_type.is_file() && name.ends_with(".bt") {
+            files.push(path);
+            if files.len() >= MAX_WORKSPACE_FILES {
+                return;
+            }
+        }
+    }
+}
+
+// bpftrace is run only for open documents, so workspace with many scripts does not start
+// hundreds of dry-runs. Other files get parser errors and the server's own checks.
+fn text_diagnostics(text: &str, tree: Option<&Tree>, is_open: bool) -> json::JsonValue {
+    if let Some(tree) = tree.filter(|_| cfg!(feature = "parser_diagnostics")) {
+        if tree.root_node().has_error() {
+            return do_parser_diagnostics(text, &tree.root_node());
+        }
+    }
+
+    match is_open {
+        true => dry_run_diagnostics(text),
+        false => json::JsonValue::new_array(),
+    }
+}
+
+fn workspace_file_report(
+    path: &Path,
+    previous_result_ids: &HashMap<String, String>,
+) -> Option<json::JsonValue> {
+    let uri = path_to_uri(path);
+
+    // Open documents are reported with their editor content
+    let text_doc = DOCUMENTS_STATE.get(&uri);
+    let (text, version) = match &text_doc {
+        Some(text_doc) => (text_doc.text.clone(), Some(text_doc.version)),
+        None => (fs::read_to_string(path).ok()?, None),
+    };
+    let parsed = match &text_doc {
+        Some(_) => None,
+        None => check_mod::parse(&text),
+    };
+    let tree = text_doc
+        .as_ref()
+        .and_then(|doc| doc.syntax_tree.as_ref())
+        .or(parsed.as_ref());
+
+    let current_id = result_id(&text);
+    let previous_id = previous_result_ids.get(&uri).map(|s| s.as_str());
+
+    let mut report = if previous_id == Some(&current_id) || text.trim().is_empty() {
+        encode_report(&current_id, previous_id, &json::JsonValue::new_array())
+    } else {
+        let cached = version.and_then(|version| {
+            let cache = DIAGNOSTICS_CACHE.lock().unwrap();
+            cache
+                .get(&uri)
+                .filter(|cached| cached.version == version && cached.result_id == current_id)
+                .map(|cached| cached.diagnostics.clone())
+        });
+        let diagnostics = cached.unwrap_or_else(|| {
+            with_lints(
+                text_diagnostics(&text, tree, version.is_some()),
+                static_checks(&uri, &text, tree),
+            )
+        });
+        encode_report(&current_id, None, &diagnostics)
+    };
+
+    report["uri"] = uri.into();
+    report["version"] = match version {
+        Some(version) => version.into(),
+        None => json::JsonValue::Null,
+    };
+
+    Some(report)
+}
+
+// Check every .bt file in workspace folders. This can take long, so it is done in separate
+// thread and the response is send to the main thread when ready.
+pub fn workspace_diagnostic(id: u64, content: json::JsonValue, mpsc_tx: mpsc::Sender<MpscMessage>) {
+    thread::spawn(move || {
+        let mut previous_result_ids: HashMap<String, String> = HashMap::new();
+        for prev in content["params"]["previousResultIds"].members() {
+            previous_result_ids.insert(prev["uri"].to_string(), prev["value"].to_string());
+        }
+
+        let mut files: Vec<PathBuf> = Vec::new();
+        for folder in config_mod::client_capabilities().workspace_folders {
+            if let Some(dir) = uri_to_path(&folder) {
+                find_bt_files(&dir, 0, &mut files);
+            }
+        }
+        log_dbg!(DIAGN, "Workspace diagnostics for {} files", files.len());
+
+        let max_parallel = config_mod::get().max_parallel_diagnostics;
+        let mut items = json::JsonValue::new_array();
+
+        for chunk in files.chunks(max_parallel.max(1)) {
+            let reports: Vec<Option<json::JsonValue>> = thread::scope(|scope| {
+                let handles: Vec<_> = chunk
+                    .iter()
+                    .map(|path| scope.spawn(|| workspace_file_report(path, &previous_result_ids)))
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().ok().flatten())
+                    .collect()
+            });
+
+            for report in reports.into_iter().flatten() {
+                let _ = items.push(report);
+            }
+        }
+
+        let data = object! {
+            "result": {
+                "items": items,
+            },
+        };
+        let _ = mpsc_tx.send(MpscMessage::Response(encode_response(id, data)));
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEBOUNCE: Duration = Duration::from_millis(300);
+
+    #[test]
+    fn test_debounce_and_coalesce() {
+        let mut sched = DiagnosticsScheduler::default();
+        let start = Instant::now();
+
+        sched.request("a".to_string(), start, DEBOUNCE);
+        sched.request(
+            "a".to_string(),
+            start + Duration::from_millis(100),
+            DEBOUNCE,
+        );
+
+        assert!(sched.take_due(start + DEBOUNCE, 4).is_empty());
+        assert_eq!(
+            sched.next_deadline(),
+            Some(start + Duration::from_millis(100) + DEBOUNCE)
+        );
+
+        let due = sched.take_due(start + Duration::from_millis(400), 4);
+        assert_eq!(due, vec!["a".to_string()]);
+        assert!(sched.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_parallel_limit() {
+        let mut sched = DiagnosticsScheduler::default();
+        let start = Instant::now();
+
+        for uri in ["a", "b", "c"] {
+            sched.request(uri.to_string(), start, DEBOUNCE);
+        }
+
+        let now = start + DEBOUNCE;
+        assert_eq!(sched.take_due(now, 2).len(), 2);
+        assert!(sched.take_due(now, 2).is_empty());
+
+        let running = sched.running.iter().next().unwrap().clone();
+        sched.finish(&running);
+        assert_eq!(sched.take_due(now, 2), vec!["c".to_string()]);
+    }
+
+    #[test]
+    fn test_no_parallel_runs_for_same_document() {
+        let mut sched = DiagnosticsScheduler::default();
+        let start = Instant::now();
+
+        sched.request("a".to_string(), start, DEBOUNCE);
+        assert_eq!(sched.take_due(start + DEBOUNCE, 4).len(), 1);
+
+        // Edited while dry-run is in progress, wait for the running one to finish
+        sched.request("a".to_string(), start + DEBOUNCE, DEBOUNCE);
+        assert!(sched.take_due(start + DEBOUNCE * 3, 4).is_empty());
+        assert!(sched.next_deadline().is_none());
+
+        sched.finish("a");
+        assert_eq!(sched.take_due(start + DEBOUNCE * 3, 4).len(), 1);
+    }
+
+    #[test]
+    fn test_cancel_closed_document() {
+        let mut sched = DiagnosticsScheduler::default();
+        let start = Instant::now();
+
+        sched.request("a".to_string(), start, DEBOUNCE);
+        sched.request("b".to_string(), start, DEBOUNCE);
+        sched.cancel("a");
+        assert_eq!(sched.take_due(start + DEBOUNCE, 4), vec!["b".to_string()]);
+        assert!(sched.next_deadline().is_none());
+    }
+
+    #[test]
+    fn test_uri_to_path() {
+        let path = uri_to_path("file:///home/user/my%20scripts/a.bt").unwrap();
+        assert_eq!(path, PathBuf::from("/home/user/my scripts/a.bt"));
+        assert_eq!(path_to_uri(&path), "file:///home/user/my%20scripts/a.bt");
+
+        assert!(uri_to_path("untitled:Untitled-1").is_none());
+    }
+
+    #[test]
+    fn test_find_bt_files() {
+        let dir = std::env::temp_dir().join(format!("bpftrace-ls-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join("a.bt"), "begin { }").unwrap();
+        fs::write(dir.join("sub/b.bt"), "end { }").unwrap();
+        fs::write(dir.join("sub/c.txt"), "").unwrap();
+        fs::write(dir.join(".git/d.bt"), "").unwrap();
+
+        let mut files = Vec::new();
+        find_bt_files(&dir, 0, &mut files);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(files, vec![dir.join("a.bt"), dir.join("sub/b.bt")]);
+    }
+
+    #[test]
+    fn test_pull_diagnostics() {
+        let uri = "file:///diag_pull_test.bt".to_string();
+        let text = "kprobe:do_nanosleep { printf(\"%d\\n\", pid) }";
+        DOCUMENTS_STATE.set(uri.clone(), text.to_string(), 3);
+
+        let request = object! {
+            "params": { "textDocument": { "uri": uri.clone() } },
+        };
+
+        // No results yet, request is deferred
+        assert!(encode_document_diagnostic(7, &request).is_none());
+
+        let diagnostics = json::array![object! { "message": "ERROR: test" }];
+        cache_diagnostics(&uri, 3, text, &diagnostics);
+
+        let responses = answer_pending_pulls(&uri);
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].contains(r#""id":7"#));
+        assert!(responses[0].contains("ERROR: test"));
+        assert!(answer_pending_pulls(&uri).is_empty());
+
+        let result = encode_document_diagnostic(8, &request).unwrap();
+        assert_eq!(result["result"]["kind"], "full");
+        assert_eq!(result["result"]["items"].len(), 1);
+
+        let request = object! {
+            "params": {
+                "textDocument": { "uri": uri.clone() },
+                "previousResultId": result["result"]["resultId"].clone(),
+            },
+        };
+        let result = encode_document_diagnostic(9, &request).unwrap();
+        assert_eq!(result["result"]["kind"], "unchanged");
+
+        // Settings changed, cached diagnostics are stale and the request waits for dry-run
+        config_mod::update_from_json(&object! {});
+        assert!(encode_document_diagnostic(10, &request).is_none());
+
+        // Closed before dry-run finished
+        let responses = forget_document(&uri);
+        assert_eq!(responses.len(), 1);
+        assert!(responses[0].contains(r#""id":10"#));
+        assert!(PENDING_PULLS.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_text_hash() {
+        assert_eq!(text_hash("begin { }"), text_hash("begin { }"));
+        assert_ne!(text_hash("begin { }"), text_hash("end { }"));
+    }
+}
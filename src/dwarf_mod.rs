@@ -0,0 +1,600 @@
+// Minimal DWARF reader, only names and types of functions and their parameters are read from
+// .debug_info. DWARF 2 to 5 in 32-bit format is supported, compressed sections are
+// not, elf_mod treats them as missing.
+
+use std::collections::HashMap;
+
+use crate::elf_mod::{read_str, Elf};
+
+const DW_TAG_ARRAY_TYPE: u64 = 0x01;
+const DW_TAG_CLASS_TYPE: u64 = 0x02;
+const DW_TAG_ENUMERATION_TYPE: u64 = 0x04;
+const DW_TAG_FORMAL_PARAMETER: u64 = 0x05;
+const DW_TAG_POINTER_TYPE: u64 = 0x0f;
+const DW_TAG_REFERENCE_TYPE: u64 = 0x10;
+const DW_TAG_STRUCTURE_TYPE: u64 = 0x13;
+const DW_TAG_SUBROUTINE_TYPE: u64 = 0x15;
+const DW_TAG_TYPEDEF: u64 = 0x16;
+const DW_TAG_UNION_TYPE: u64 = 0x17;
+const DW_TAG_BASE_TYPE: u64 = 0x24;
+const DW_TAG_CONST_TYPE: u64 = 0x26;
+const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+const DW_TAG_VOLATILE_TYPE: u64 = 0x35;
+const DW_TAG_RESTRICT_TYPE: u64 = 0x37;
+
+const DW_AT_NAME: u64 = 0x03;
+const DW_AT_DECLARATION: u64 = 0x3c;
+const DW_AT_TYPE: u64 = 0x49;
+const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+
+const DW_FORM_IMPLICIT_CONST: u64 = 0x21;
+const DW_FORM_INDIRECT: u64 = 0x16;
+
+const DW_UT_COMPILE: u8 = 0x01;
+const DW_UT_PARTIAL: u8 = 0x03;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Parameter {
+    pub name: String,
+    pub type_str: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Function {
+    pub name: String,
+    // "void" when function does not return a value
+    pub ret_type: String,
+    pub params: Vec<Parameter>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let b = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn uint(&mut self, size: usize) -> Option<u64> {
+        let bytes = self.data.get(self.pos..self.pos + size)?;
+        self.pos += size;
+        let mut value = 0u64;
+        for i in 0..size {
+            let b = if self.big_endian {
+                bytes[i]
+            } else {
+                bytes[size - 1 - i]
+            };
+            value = (value << 8) | b as u64;
+        }
+        Some(value)
+    }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= ((b & 0x7f) as u64) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Option<i64> {
+        let mut value = 0i64;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 {
+                value |= ((b & 0x7f) as i64) << shift;
+            }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && b & 0x40 != 0 {
+                    value |= -1 << shift;
+                }
+                return Some(value);
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Option<&str> {
+        let s = read_str(self.data.get(self.pos..)?, 0);
+        self.pos += s.len() + 1;
+        Some(s)
+    }
+
+    fn skip(&mut self, size: usize) -> Option<()> {
+        self.pos = self.pos.checked_add(size)?;
+        (self.pos <= self.data.len()).then_some(())
+    }
+}
+
+struct AttrSpec {
+    attr: u64,
+    form: u64,
+}
+
+struct Abbrev {
+    tag: u64,
+    has_children: bool,
+    attrs: Vec<AttrSpec>,
+}
+
+fn parse_abbrevs(reader: &mut Reader) -> Option<HashMap<u64, Abbrev>> {
+    let mut abbrevs = HashMap::new();
+    loop {
+        let code = reader.uleb()?;
+        if code == 0 {
+            return Some(abbrevs);
+        }
+        let tag = reader.uleb()?;
+        let has_children = reader.u8()? != 0;
+        let mut attrs = Vec::new();
+        loop {
+            let attr = reader.uleb()?;
+            let form = reader.uleb()?;
+            if attr == 0 && form == 0 {
+                break;
+            }
+            if form == DW_FORM_IMPLICIT_CONST {
+                reader.sleb()?;
+            }
+            attrs.push(AttrSpec { attr, form });
+        }
+        abbrevs.insert(
+            code,
+            Abbrev {
+                tag,
+                has_children,
+                attrs,
+            },
+        );
+    }
+}
+
+enum AttrValue<'a> {
+    Str(&'a str),
+    StrOffset(u64),
+    LineStrOffset(u64),
+    StrIndex(u64),
+    // Offset of DIE in .debug_info
+    Ref(usize),
+    Int(u64),
+    None,
+}
+
+struct Sections<'a> {
+    info: &'a [u8],
+    abbrev: &'a [u8],
+    str: &'a [u8],
+    line_str: &'a [u8],
+    str_offsets: &'a [u8],
+    big_endian: bool,
+}
+
+struct Unit {
+    offset: usize,
+    version: u16,
+    address_size: usize,
+    str_offsets_base: u64,
+}
+
+fn read_attr<'a>(reader: &mut Reader<'a>, form: u64, unit: &Unit) -> Option<AttrValue<'a>> {
+    let value = match form {
+        0x01 => AttrValue::Int(reader.uint(unit.address_size)?),
+        0x03 => {
+            let len = reader.uint(2)? as usize;
+            reader.skip(len)?;
+            AttrValue::None
+        }
+        0x04 => {
+            let len = reader.uint(4)? as usize;
+            reader.skip(len)?;
+            AttrValue::None
+        }
+        0x05 => AttrValue::Int(reader.uint(2)?),
+        0x06 => AttrValue::Int(reader.uint(4)?),
+        0x07 => AttrValue::Int(reader.uint(8)?),
+        0x08 => {
+            let data = reader.data;
+            let start = reader.pos;
+            let len = reader.cstr()?.len();
+            AttrValue::Str(std::str::from_utf8(&data[start..start + len]).unwrap_or_default())
+        }
+        0x09 | 0x18 => {
+            let len = reader.uleb()? as usize;
+            reader.skip(len)?;
+            AttrValue::None
+        }
+        0x0a => {
+            let len = reader.u8()? as usize;
+            reader.skip(len)?;
+            AttrValue::None
+        }
+        0x0b | 0x0c => AttrValue::Int(reader.uint(1)?),
+        0x0d => AttrValue::Int(reader.sleb()? as u64),
+        0x0e => AttrValue::StrOffset(reader.uint(4)?),
+        0x0f => AttrValue::Int(reader.uleb()?),
+        0x10 => {
+            let size = if unit.version == 2 {
+                unit.address_size
+            } else {
+                4
+            };
+            AttrValue::Ref(reader.uint(size)? as usize)
+        }
+        0x11 => AttrValue::Ref(unit.offset + reader.uint(1)? as usize),
+        0x12 => AttrValue::Ref(unit.offset + reader.uint(2)? as usize),
+        0x13 => AttrValue::Ref(unit.offset + reader.uint(4)? as usize),
+        0x14 => AttrValue::Ref(unit.offset + reader.uint(8)? as usize),
+        0x15 => AttrValue::Ref(unit.offset + reader.uleb()? as usize),
+        DW_FORM_INDIRECT => {
+            let form = reader.uleb()?;
+            return read_attr(reader, form, unit);
+        }
+        // DW_FORM_GNU_ref_alt and DW_FORM_GNU_strp_alt are offsets into .gnu_debugaltlink file
+        0x17 | 0x1c | 0x1d | 0x1f20 | 0x1f21 => AttrValue::Int(reader.uint(4)?),
+        0x19 => AttrValue::Int(1),
+        0x1a => AttrValue::StrIndex(reader.uleb()?),
+        // DW_FORM_GNU_addr_index and DW_FORM_GNU_str_index of split DWARF
+        0x1b | 0x22 | 0x23 | 0x1f01 => AttrValue::Int(reader.uleb()?),
+        0x1f02 => AttrValue::StrIndex(reader.uleb()?),
+        0x1e => {
+            reader.skip(16)?;
+            AttrValue::None
+        }
+        0x1f => AttrValue::LineStrOffset(reader.uint(4)?),
+        0x20 | 0x24 => AttrValue::Int(reader.uint(8)?),
+        DW_FORM_IMPLICIT_CONST => AttrValue::None,
+        0x25 => AttrValue::StrIndex(reader.uint(1)?),
+        0x26 => AttrValue::StrIndex(reader.uint(2)?),
+        0x27 => AttrValue::StrIndex(reader.uint(3)?),
+        0x28 => AttrValue::StrIndex(reader.uint(4)?),
+        0x29 => AttrValue::Int(reader.uint(1)?),
+        0x2a => AttrValue::Int(reader.uint(2)?),
+        0x2b => AttrValue::Int(reader.uint(3)?),
+        0x2c => AttrValue::Int(reader.uint(4)?),
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn attr_str<'a>(value: &AttrValue<'a>, sections: &Sections<'a>, unit: &Unit) -> Option<&'a str> {
+    match value {
+        AttrValue::Str(s) => Some(s),
+        AttrValue::StrOffset(offset) => Some(read_str(sections.str, *offset as usize)),
+        AttrValue::LineStrOffset(offset) => Some(read_str(sections.line_str, *offset as usize)),
+        AttrValue::StrIndex(index) => {
+            let mut reader = Reader {
+                data: sections.str_offsets,
+                pos: (unit.str_offsets_base + index * 4) as usize,
+                big_endian: sections.big_endian,
+            };
+            Some(read_str(sections.str, reader.uint(4)? as usize))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct TypeDie {
+    tag: u64,
+    name: Option<String>,
+    type_ref: Option<usize>,
+}
+
+struct FunctionDie {
+    name: String,
+    type_ref: Option<usize>,
+    params: Vec<(String, Option<usize>)>,
+}
+
+// Unit header, None for units without functions, e.g. type units
+fn parse_unit_header(reader: &mut Reader) -> Option<(Unit, usize, Option<u64>)> {
+    let offset = reader.pos;
+    let unit_length = reader.uint(4)? as usize;
+    if unit_length >= 0xffff_fff0 {
+        // 64-bit DWARF is not supported, the rest of section can't be parsed
+        return None;
+    }
+    let end = reader.pos + unit_length;
+    let version = reader.uint(2)? as u16;
+
+    let (abbrev_offset, address_size, unit_type) = if version >= 5 {
+        let unit_type = reader.u8()?;
+        let address_size = reader.u8()? as usize;
+        (reader.uint(4)?, address_size, unit_type)
+    } else {
+        let abbrev_offset = reader.uint(4)?;
+        (abbrev_offset, reader.u8()? as usize, DW_UT_COMPILE)
+    };
+
+    let unit = Unit {
+        offset,
+        version,
+        address_size,
+        str_offsets_base: 0,
+    };
+    let abbrev_offset = matches!(unit_type, DW_UT_COMPILE | DW_UT_PARTIAL).then_some(abbrev_offset);
+    Some((unit, end, abbrev_offset))
+}
+
+fn parse_unit(
+    reader: &mut Reader,
+    mut unit: Unit,
+    end: usize,
+    abbrevs: &HashMap<u64, Abbrev>,
+    sections: &Sections,
+    types: &mut HashMap<usize, TypeDie>,
+    functions: &mut Vec<FunctionDie>,
+) -> Option<()> {
+    // Tags of parent DIEs, index of function for subprograms
+    let mut parents: Vec<(u64, Option<usize>)> = Vec::new();
+
+    while reader.pos < end {
+        let die_offset = reader.pos;
+        let code = reader.uleb()?;
+        if code == 0 {
+            parents.pop();
+            continue;
+        }
+        let abbrev = abbrevs.get(&code)?;
+
+        let mut name = None;
+        let mut type_ref = None;
+        let mut is_declaration = false;
+        for spec in abbrev.attrs.iter() {
+            let value = read_attr(reader, spec.form, &unit)?;
+            match (spec.attr, &value) {
+                (DW_AT_NAME, _) => name = attr_str(&value, sections, &unit),
+                (DW_AT_TYPE, AttrValue::Ref(offset)) => type_ref = Some(*offset),
+                (DW_AT_DECLARATION, AttrValue::Int(flag)) => is_declaration = *flag != 0,
+                (DW_AT_STR_OFFSETS_BASE, AttrValue::Int(base)) => unit.str_offsets_base = *base,
+                _ => {}
+            }
+        }
+
+        let mut function = None;
+        match abbrev.tag {
+            DW_TAG_SUBPROGRAM => {
+                if let Some(name) = name.filter(|_| !is_declaration) {
+                    function = Some(functions.len());
+                    functions.push(FunctionDie {
+                        name: name.to_string(),
+                        type_ref,
+                        params: Vec::new(),
+                    });
+                }
+            }
+            DW_TAG_FORMAL_PARAMETER => {
+                if let Some((DW_TAG_SUBPROGRAM, Some(index))) = parents.last() {
+                    let name = name.unwrap_or_default().to_string();
+                    functions[*index].params.push((name, type_ref));
+                }
+            }
+            DW_TAG_ARRAY_TYPE
+            | DW_TAG_CLASS_TYPE
+            | DW_TAG_ENUMERATION_TYPE
+            | DW_TAG_POINTER_TYPE
+            | DW_TAG_REFERENCE_TYPE
+            | DW_TAG_STRUCTURE_TYPE
+            | DW_TAG_SUBROUTINE_TYPE
+            | DW_TAG_TYPEDEF
+            | DW_TAG_UNION_TYPE
+            | DW_TAG_BASE_TYPE
+            | DW_TAG_CONST_TYPE
+            | DW_TAG_VOLATILE_TYPE
+            | DW_TAG_RESTRICT_TYPE => {
+                types.insert(
+                    die_offset,
+                    TypeDie {
+                        tag: abbrev.tag,
+                        name: name.map(|n| n.to_string()),
+                        type_ref,
+                    },
+                );
+            }
+            _ => {}
+        }
+
+        if abbrev.has_children {
+            parents.push((abbrev.tag, function));
+        }
+    }
+    Some(())
+}
+
+// C like name of type, e.g. "const char *"
+// Keyword is added only to C names, Rust and C++ names like &[u8] or std::string are kept as they are
+fn tagged_name(keyword: &str, name: &str) -> String {
+    if name.is_empty() {
+        keyword.to_string()
+    } else if name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        format!("{} {}", keyword, name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn type_name(types: &HashMap<usize, TypeDie>, type_ref: Option<usize>, depth: usize) -> String {
+    let Some(die) = type_ref.and_then(|offset| types.get(&offset)) else {
+        return "void".to_string();
+    };
+    if depth > 16 {
+        return "...".to_string();
+    }
+
+    let name = die.name.clone().unwrap_or_default();
+    let target = || type_name(types, die.type_ref, depth + 1);
+    match die.tag {
+        DW_TAG_STRUCTURE_TYPE => tagged_name("struct", &name),
+        DW_TAG_UNION_TYPE => tagged_name("union", &name),
+        DW_TAG_CLASS_TYPE => tagged_name("class", &name),
+        DW_TAG_ENUMERATION_TYPE => tagged_name("enum", &name),
+        DW_TAG_POINTER_TYPE => format!("{} *", target()).replace("* *", "**"),
+        DW_TAG_REFERENCE_TYPE => format!("{} &", target()),
+        DW_TAG_CONST_TYPE => format!("const {}", target()),
+        DW_TAG_VOLATILE_TYPE => format!("volatile {}", target()),
+        DW_TAG_RESTRICT_TYPE => target(),
+        DW_TAG_ARRAY_TYPE => format!("{} []", target()),
+        DW_TAG_SUBROUTINE_TYPE => "func".to_string(),
+        _ => name,
+    }
+}
+
+// Functions defined in the binary, the first definition wins for functions with the same name
+pub fn read_functions(elf: &Elf) -> HashMap<String, Function> {
+    let mut result = HashMap::new();
+    let (Some(info), Some(abbrev)) = (
+        elf.section_data(".debug_info"),
+        elf.section_data(".debug_abbrev"),
+    ) else {
+        return result;
+    };
+    let sections = Sections {
+        info,
+        abbrev,
+        str: elf.section_data(".debug_str").unwrap_or_default(),
+        line_str: elf.section_data(".debug_line_str").unwrap_or_default(),
+        str_offsets: elf.section_data(".debug_str_offsets").unwrap_or_default(),
+        big_endian: elf.is_big_endian(),
+    };
+
+    let mut types = HashMap::new();
+    let mut functions = Vec::new();
+    let mut abbrevs_cache: HashMap<u64, HashMap<u64, Abbrev>> = HashMap::new();
+
+    let mut reader = Reader {
+        data: sections.info,
+        pos: 0,
+        big_endian: sections.big_endian,
+    };
+    while reader.pos < sections.info.len() {
+        let Some((unit, end, abbrev_offset)) = parse_unit_header(&mut reader) else {
+            break;
+        };
+        if let Some(abbrev_offset) = abbrev_offset {
+            let abbrevs = abbrevs_cache.entry(abbrev_offset).or_insert_with(|| {
+                let mut abbrev_reader = Reader {
+                    data: sections.abbrev,
+                    pos: abbrev_offset as usize,
+                    big_endian: sections.big_endian,
+                };
+                parse_abbrevs(&mut abbrev_reader).unwrap_or_default()
+            });
+            // Broken unit is skipped, others can still be read
+            let _ = parse_unit(
+                &mut reader,
+                unit,
+                end,
+                abbrevs,
+                &sections,
+                &mut types,
+                &mut functions,
+            );
+        }
+        reader.pos = end;
+    }
+
+    for function in functions {
+        if result.contains_key(&function.name) {
+            continue;
+        }
+        let params = function
+            .params
+            .iter()
+            .map(|(name, type_ref)| Parameter {
+                name: name.clone(),
+                type_str: type_name(&types, *type_ref, 0),
+            })
+            .collect();
+        result.insert(
+            function.name.clone(),
+            Function {
+                ret_type: type_name(&types, function.type_ref, 0),
+                name: function.name,
+                params,
+            },
+        );
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leb128() {
+        let mut reader = Reader {
+            data: &[0xe5, 0x8e, 0x26, 0x7f, 0x80, 0x7f],
+            pos: 0,
+            big_endian: false,
+        };
+        assert_eq!(reader.uleb(), Some(624485));
+        assert_eq!(reader.sleb(), Some(-1));
+        assert_eq!(reader.sleb(), Some(-128));
+        assert_eq!(reader.uleb(), None);
+    }
+
+    #[test]
+    fn test_gnu_forms() {
+        let unit = Unit {
+            offset: 0,
+            version: 4,
+            address_size: 8,
+            str_offsets_base: 0,
+        };
+        // addr_index 0x81 0x01, str_index 0x02, ref_alt and strp_alt are 4 bytes each
+        let mut reader = Reader {
+            data: &[0x81, 0x01, 0x02, 1, 0, 0, 0, 2, 0, 0, 0],
+            pos: 0,
+            big_endian: false,
+        };
+        assert!(matches!(
+            read_attr(&mut reader, 0x1f01, &unit),
+            Some(AttrValue::Int(129))
+        ));
+        assert!(matches!(
+            read_attr(&mut reader, 0x1f02, &unit),
+            Some(AttrValue::StrIndex(2))
+        ));
+        assert!(matches!(
+            read_attr(&mut reader, 0x1f20, &unit),
+            Some(AttrValue::Int(1))
+        ));
+        assert!(matches!(
+            read_attr(&mut reader, 0x1f21, &unit),
+            Some(AttrValue::Int(2))
+        ));
+        assert_eq!(reader.pos, reader.data.len());
+    }
+
+    #[test]
+    fn test_read_own_functions() {
+        let data = std::fs::read("/proc/self/exe").unwrap();
+        let elf = Elf::parse(&data).unwrap();
+        let functions = read_functions(&elf);
+
+        let function = &functions["read_str"];
+        let params: Vec<&str> = function.params.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(params, vec!["table", "offset"]);
+        assert_eq!(function.params[0].type_str, "&[u8]");
+        assert_eq!(function.params[1].type_str, "usize");
+        assert_eq!(function.ret_type, "&str");
+
+        assert_eq!(tagged_name("struct", "task_struct"), "struct task_struct");
+        assert_eq!(tagged_name("union", ""), "union");
+    }
+}
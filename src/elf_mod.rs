@@ -0,0 +1,415 @@
+// Minimal ELF reader, only what is needed to get sections, function symbols and USDT notes out
+// of vmlinux and user space binaries. Both 32 and 64 bit, little and big endian files are supported.
+// Compressed sections (SHF_COMPRESSED) are treated as missing, they would need zlib or zstd.
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2MSB: u8 = 2;
+const STT_FUNC: u8 = 2;
+const STT_GNU_IFUNC: u8 = 10;
+const SHN_UNDEF: u16 = 0;
+const SHF_COMPRESSED: u64 = 0x800;
+const NT_STAPSDT: u32 = 3;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct Section {
+    pub name: String,
+    pub offset: u64,
+    pub size: u64,
+    pub compressed: bool,
+}
+
+// USDT probe from .note.stapsdt section
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UsdtProbe {
+    pub provider: String,
+    pub name: String,
+    // Location specs of arguments, e.g. -4@%edi
+    pub args: Vec<String>,
+}
+
+pub struct Elf<'a> {
+    data: &'a [u8],
+    is_64: bool,
+    big_endian: bool,
+    pub sections: Vec<Section>,
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Elf<'a>> {
+        if !is_elf(data) || data.len() < 16 {
+            return None;
+        }
+
+        let mut elf = Elf {
+            data,
+            is_64: data[4] == ELFCLASS64,
+            big_endian: data[5] == ELFDATA2MSB,
+            sections: Vec::new(),
+        };
+
+        let (shoff, shentsize, shnum, shstrndx) = if elf.is_64 {
+            (
+                elf.u64(0x28)?,
+                elf.u16(0x3a)?,
+                elf.u16(0x3c)?,
+                elf.u16(0x3e)?,
+            )
+        } else {
+            (
+                elf.u32(0x20)? as u64,
+                elf.u16(0x2e)?,
+                elf.u16(0x30)?,
+                elf.u16(0x32)?,
+            )
+        };
+
+        let mut name_offsets = Vec::new();
+        for i in 0..shnum as u64 {
+            let base = (shoff + i * shentsize as u64) as usize;
+            let section = if elf.is_64 {
+                Section {
+                    offset: elf.u64(base + 0x18)?,
+                    size: elf.u64(base + 0x20)?,
+                    compressed: elf.u64(base + 0x08)? & SHF_COMPRESSED != 0,
+                    ..Default::default()
+                }
+            } else {
+                Section {
+                    offset: elf.u32(base + 0x10)? as u64,
+                    size: elf.u32(base + 0x14)? as u64,
+                    compressed: elf.u32(base + 0x08)? as u64 & SHF_COMPRESSED != 0,
+                    ..Default::default()
+                }
+            };
+            name_offsets.push(elf.u32(base)? as usize);
+            elf.sections.push(section);
+        }
+
+        let names = elf
+            .sections
+            .get(shstrndx as usize)
+            .and_then(|s| elf.section_bytes(s))
+            .unwrap_or_default();
+        for (section, name_offset) in elf.sections.iter_mut().zip(name_offsets) {
+            section.name = read_str(names, name_offset).to_string();
+        }
+
+        Some(elf)
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    pub fn section_bytes(&self, section: &Section) -> Option<&'a [u8]> {
+        let start = section.offset as usize;
+        let end = start.checked_add(section.size as usize)?;
+        self.data.get(start..end)
+    }
+
+    // Data of uncompressed section
+    pub fn section_data(&self, name: &str) -> Option<&'a [u8]> {
+        let section = self.section(name).filter(|s| !s.compressed)?;
+        self.section_bytes(section)
+    }
+
+    // Build ID from .note.gnu.build-id in hex, used to find separate debug info file
+    pub fn build_id(&self) -> Option<String> {
+        let note = self.section_data(".note.gnu.build-id")?;
+        let offset = self.section(".note.gnu.build-id")?.offset as usize;
+        let (namesz, descsz, note_type) = (
+            self.u32(offset)?,
+            self.u32(offset + 4)?,
+            self.u32(offset + 8)?,
+        );
+        if note_type != NT_GNU_BUILD_ID || read_str(note.get(12..)?, 0) != "GNU" {
+            return None;
+        }
+        let desc_start = 12 + ((namesz as usize + 3) & !3);
+        let desc = note.get(desc_start..desc_start + descsz as usize)?;
+        Some(desc.iter().map(|b| format!("{:02x}", b)).collect())
+    }
+
+    // File name of separate debug info from .gnu_debuglink
+    pub fn debuglink(&self) -> Option<&'a str> {
+        let name = read_str(self.section_data(".gnu_debuglink")?, 0);
+        (!name.is_empty()).then_some(name)
+    }
+
+    pub fn is_big_endian(&self) -> bool {
+        self.big_endian
+    }
+
+    // Names of functions defined in the binary, from .symtab and .dynsym. Version of symbol,
+    // e.g. @@GLIBC_2.14, is removed.
+    pub fn function_symbols(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for (symtab, strtab) in [(".symtab", ".strtab"), (".dynsym", ".dynstr")] {
+            let (Some(section), Some(strings)) = (self.section(symtab), self.section_data(strtab))
+            else {
+                continue;
+            };
+            let entsize = if self.is_64 { 24 } else { 16 };
+            for i in 0..section.size / entsize {
+                let base = (section.offset + i * entsize) as usize;
+                let (info, shndx) = if self.is_64 {
+                    (self.data.get(base + 4), self.u16(base + 6))
+                } else {
+                    (self.data.get(base + 12), self.u16(base + 14))
+                };
+                let (Some(info), Some(shndx), Some(name)) = (info, shndx, self.u32(base)) else {
+                    break;
+                };
+                if !matches!(info & 0xf, STT_FUNC | STT_GNU_IFUNC) || shndx == SHN_UNDEF {
+                    continue;
+                }
+                let name = read_str(strings, name as usize);
+                let name = name.split('@').next().unwrap_or_default();
+                if !name.is_empty() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort_unstable();
+        names.dedup();
+        names
+    }
+
+    // Notes of USDT probes, each one has name "stapsdt" and description with addresses of probe,
+    // base and semaphore followed by provider, name and arguments
+    pub fn usdt_probes(&self) -> Vec<UsdtProbe> {
+        let mut probes = Vec::new();
+        let Some(section) = self.section(".note.stapsdt") else {
+            return probes;
+        };
+        let align = |n: u64| (n + 3) & !3;
+        let addr_size = if self.is_64 { 8 } else { 4 };
+
+        let mut offset = section.offset;
+        while offset + 12 <= section.offset + section.size {
+            let base = offset as usize;
+            let (Some(namesz), Some(descsz), Some(note_type)) =
+                (self.u32(base), self.u32(base + 4), self.u32(base + 8))
+            else {
+                break;
+            };
+            let name_start = offset + 12;
+            let desc_start = name_start + align(namesz as u64);
+            offset = desc_start + align(descsz as u64);
+
+            let name = self
+                .data
+                .get(name_start as usize..desc_start as usize)
+                .map(|name| read_str(name, 0))
+                .unwrap_or_default();
+            let Some(desc) = self
+                .data
+                .get(desc_start as usize..desc_start as usize + descsz as usize)
+            else {
+                break;
+            };
+            if note_type != NT_STAPSDT || name != "stapsdt" || desc.len() < 3 * addr_size {
+                continue;
+            }
+
+            let strings = &desc[3 * addr_size..];
+            let provider = read_str(strings, 0);
+            let probe_name = read_str(strings, provider.len() + 1);
+            let args = read_str(strings, provider.len() + probe_name.len() + 2);
+            probes.push(UsdtProbe {
+                provider: provider.to_string(),
+                name: probe_name.to_string(),
+                args: args.split_whitespace().map(|a| a.to_string()).collect(),
+            });
+        }
+        probes
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data.get(offset..offset + N)?.try_into().ok()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b = self.bytes::<2>(offset)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(b)
+        } else {
+            u16::from_le_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b = self.bytes::<4>(offset)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Option<u64> {
+        let b = self.bytes::<8>(offset)?;
+        Some(if self.big_endian {
+            u64::from_be_bytes(b)
+        } else {
+            u64::from_le_bytes(b)
+        })
+    }
+}
+
+// NUL terminated string at offset of a string table
+pub fn read_str(table: &[u8], offset: usize) -> &str {
+    let Some(bytes) = table.get(offset..) else {
+        return "";
+    };
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).unwrap_or_default()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // ELF64 little endian file with given sections, enough for Elf::parse()
+    pub fn build_elf(sections: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..4].copy_from_slice(ELF_MAGIC);
+        data[4] = ELFCLASS64;
+        data[5] = 1;
+
+        let mut shstrtab = vec![0u8];
+        let mut headers: Vec<(u32, u64, u64)> = vec![(0, 0, 0)];
+        for (name, bytes) in sections.iter().chain([(".shstrtab", &[][..])].iter()) {
+            let name_offset = shstrtab.len() as u32;
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+            let bytes = if *name == ".shstrtab" {
+                &shstrtab[..]
+            } else {
+                bytes
+            };
+            headers.push((name_offset, data.len() as u64, bytes.len() as u64));
+            data.extend_from_slice(bytes);
+        }
+
+        let shoff = data.len() as u64;
+        for (name, offset, size) in headers.iter() {
+            let mut header = vec![0u8; 64];
+            header[0..4].copy_from_slice(&name.to_le_bytes());
+            header[0x18..0x20].copy_from_slice(&offset.to_le_bytes());
+            header[0x20..0x28].copy_from_slice(&size.to_le_bytes());
+            data.extend_from_slice(&header);
+        }
+
+        data[0x28..0x30].copy_from_slice(&shoff.to_le_bytes());
+        data[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        data[0x3c..0x3e].copy_from_slice(&(headers.len() as u16).to_le_bytes());
+        data[0x3e..0x40].copy_from_slice(&(headers.len() as u16 - 1).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let data = build_elf(&[(".BTF", b"btf data"), (".text", b"\x90\x90")]);
+        let elf = Elf::parse(&data).unwrap();
+
+        assert_eq!(elf.section_data(".BTF").unwrap(), b"btf data");
+        assert_eq!(elf.section_data(".text").unwrap(), b"\x90\x90");
+        assert!(elf.section_data(".data").is_none());
+
+        assert!(Elf::parse(b"not an elf file").is_none());
+    }
+
+    #[test]
+    fn test_debug_info_references() {
+        let mut note = Vec::new();
+        note.extend_from_slice(&4u32.to_le_bytes());
+        note.extend_from_slice(&4u32.to_le_bytes());
+        note.extend_from_slice(&NT_GNU_BUILD_ID.to_le_bytes());
+        note.extend_from_slice(b"GNU\0\xab\xcd\x01\x23");
+        let data = build_elf(&[
+            (".note.gnu.build-id", &note),
+            (".gnu_debuglink", b"bash.debug\0\0\xde\xad\xbe\xef"),
+        ]);
+
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(elf.build_id().as_deref(), Some("abcd0123"));
+        assert_eq!(elf.debuglink(), Some("bash.debug"));
+    }
+
+    #[test]
+    fn test_parse_own_binary() {
+        let data = std::fs::read("/proc/self/exe").unwrap();
+        let elf = Elf::parse(&data).unwrap();
+        assert!(elf.section(".text").is_some());
+        assert!(elf.function_symbols().contains(&"main".to_string()));
+    }
+
+    // Note of USDT probe for .note.stapsdt section, addresses are zero
+    pub fn stapsdt_note(provider: &str, name: &str, args: &str) -> Vec<u8> {
+        let mut desc = vec![0u8; 24];
+        for field in [provider, name, args] {
+            desc.extend_from_slice(field.as_bytes());
+            desc.push(0);
+        }
+
+        let mut note = Vec::new();
+        note.extend_from_slice(&8u32.to_le_bytes());
+        note.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        note.extend_from_slice(&NT_STAPSDT.to_le_bytes());
+        note.extend_from_slice(b"stapsdt\0");
+        note.extend_from_slice(&desc);
+        note.resize((note.len() + 3) & !3, 0);
+        note
+    }
+
+    #[test]
+    fn test_usdt_probes() {
+        let mut notes = stapsdt_note("libc", "setjmp", "-4@%edi 8@%rsi");
+        notes.extend(stapsdt_note("python", "gc__start", ""));
+        let data = build_elf(&[(".note.stapsdt", &notes)]);
+
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(
+            elf.usdt_probes(),
+            vec![
+                UsdtProbe {
+                    provider: "libc".to_string(),
+                    name: "setjmp".to_string(),
+                    args: vec!["-4@%edi".to_string(), "8@%rsi".to_string()],
+                },
+                UsdtProbe {
+                    provider: "python".to_string(),
+                    name: "gc__start".to_string(),
+                    args: Vec::new(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_function_symbols() {
+        let symbol = |name: u32, info: u8, shndx: u16| {
+            let mut sym = vec![0u8; 24];
+            sym[0..4].copy_from_slice(&name.to_le_bytes());
+            sym[4] = info;
+            sym[6..8].copy_from_slice(&shndx.to_le_bytes());
+            sym
+        };
+        let strtab = b"\0readline\0malloc@GLIBC_2.2.5\0counter\0";
+        let mut symtab = symbol(0, 0, 0);
+        symtab.extend(symbol(1, STT_FUNC, 14));
+        symtab.extend(symbol(10, 0x10 | STT_FUNC, SHN_UNDEF));
+        symtab.extend(symbol(30, 1, 22));
+        let data = build_elf(&[(".symtab", &symtab), (".strtab", strtab)]);
+
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(elf.function_symbols(), vec!["readline"]);
+    }
+}
@@ -0,0 +1,533 @@
+use tree_sitter::{Node, Point};
+
+use crate::check_mod::{
+    call_arguments, encode_diag, encode_range_diag, node_text, probes_for_node, SEVERITY_ERROR,
+    SEVERITY_WARNING,
+};
+use crate::completion::resolve_args_type;
+use crate::gen::signatures::stdlib_signature;
+
+// Functions taking printf-like format string as first argument
+const FORMAT_FUNCTIONS: &[&str] = &["printf", "system", "cat", "errorf", "warnf", "fail"];
+
+pub const FORMAT_SPECIFIERS: &[(&str, &str)] = &[
+    ("d", "Signed decimal integer"),
+    ("i", "Signed decimal integer"),
+    ("u", "Unsigned decimal integer"),
+    ("x", "Unsigned hexadecimal integer"),
+    ("X", "Unsigned hexadecimal integer, uppercase"),
+    ("o", "Unsigned octal integer"),
+    ("c", "Character"),
+    ("s", "String, also symbols, stacks, addresses and enums"),
+    ("p", "Pointer"),
+    ("lld", "Signed 64 bit integer"),
+    ("llu", "Unsigned 64 bit integer"),
+    ("llx", "Unsigned 64 bit integer in hexadecimal"),
+    ("r", "Buffer as hex-formatted string, see buf()"),
+    ("rh", "Buffer as hex bytes separated by spaces, see buf()"),
+    ("rx", "Buffer as hex string without spaces, see buf()"),
+];
+
+// Types of values as far as format specifiers are concerned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValueType {
+    Integer,
+    Pointer,
+    String,
+    Buffer,
+    Record,
+    Unknown,
+}
+
+impl ValueType {
+    fn describe(&self) -> &'static str {
+        match self {
+            ValueType::Integer => "an integer",
+            ValueType::Pointer => "a pointer",
+            ValueType::String => "a string",
+            ValueType::Buffer => "a buffer",
+            ValueType::Record => "a struct",
+            ValueType::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FormatSpecifier {
+    // Byte offset and length within the string literal content
+    pub offset: usize,
+    pub len: usize,
+    pub expected: ValueType,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FormatError {
+    pub offset: usize,
+    pub len: usize,
+    pub message: String,
+}
+
+// Parse %[flags][width][.precision][length]conversion specifiers, %% is a plain percent sign
+pub fn parse_format(content: &str) -> Result<Vec<FormatSpecifier>, FormatError> {
+    let bytes = content.as_bytes();
+    let mut specifiers = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] != b'%' {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        i += 1;
+        if bytes.get(i) == Some(&b'%') {
+            i += 1;
+            continue;
+        }
+
+        while i < bytes.len() && b"-+ #0".contains(&bytes[i]) {
+            i += 1;
+        }
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            while i < bytes.len() && bytes[i].is_ascii_digit() {
+                i += 1;
+            }
+        }
+        while i < bytes.len() && b"hlzjt".contains(&bytes[i]) {
+            i += 1;
+        }
+
+        let expected = match bytes.get(i) {
+            Some(b'd' | b'i' | b'u' | b'o' | b'x' | b'X' | b'c' | b'p') => ValueType::Integer,
+            Some(b's') => ValueType::String,
+            Some(b'r') => {
+                if matches!(bytes.get(i + 1), Some(b'h' | b'x')) {
+                    i += 1;
+                }
+                ValueType::Buffer
+            }
+            _ => {
+                let end = (i + 1).min(bytes.len());
+                return Err(FormatError {
+                    offset: start,
+                    len: end - start,
+                    message: format!(
+                        "Invalid format specifier '{}'",
+                        content.get(start..end).unwrap_or("%")
+                    ),
+                });
+            }
+        };
+        i += 1;
+
+        specifiers.push(FormatSpecifier {
+            offset: start,
+            len: i - start,
+            expected,
+        });
+    }
+
+    Ok(specifiers)
+}
+
+fn is_compatible(expected: ValueType, value: ValueType) -> bool {
+    match (expected, value) {
+        (_, ValueType::Unknown) => true,
+        (ValueType::Integer, ValueType::Integer | ValueType::Pointer) => true,
+        (expected, value) => expected == value,
+    }
+}
+
+// Types used in stdlib signatures
+fn stdlib_type_to_value_type(type_name: &str) -> ValueType {
+    match type_name {
+        "string" | "ksym_t" | "usym_t" | "kstack_t" | "ustack_t" | "inet" | "macaddr_t"
+        | "cgroup_path_t" | "char *" => ValueType::String,
+        "buffer" => ValueType::Buffer,
+        t if t.starts_with("int") || t.starts_with("uint") || t.starts_with("bool") => {
+            ValueType::Integer
+        }
+        t if t.ends_with('*') => ValueType::Pointer,
+        _ => ValueType::Unknown,
+    }
+}
+
+fn is_integer_type_name(name: &str) -> bool {
+    let integer_words = [
+        "char", "short", "int", "long", "unsigned", "signed", "_Bool", "bool",
+    ];
+    if name.split(' ').all(|word| integer_words.contains(&word)) {
+        return true;
+    }
+
+    // int64, uint8 and similar bpftrace types
+    let bits = name
+        .strip_prefix("uint")
+        .or_else(|| name.strip_prefix("int"));
+    if bits.is_some_and(|bits| ["8", "16", "32", "64"].contains(&bits)) {
+        return true;
+    }
+
+    // u8, s32, __u64 and similar kernel typedefs
+    let short = name.trim_start_matches("__");
+    if let Some(bits) = short.strip_prefix('u').or_else(|| short.strip_prefix('s')) {
+        if ["8", "16", "32", "64"].contains(&bits) {
+            return true;
+        }
+    }
+
+    [
+        "size_t", "ssize_t", "pid_t", "uid_t", "gid_t", "loff_t", "umode_t", "dev_t", "gfp_t",
+    ]
+    .contains(&name)
+}
+
+// Types resolved from BTF, e.g. ["const", "char", "*"] or ["struct", "path"]
+pub fn btf_type_vec_to_value_type(type_vec: &[String]) -> ValueType {
+    let Some(last) = type_vec.last() else {
+        return ValueType::Unknown;
+    };
+
+    if last.starts_with('*') {
+        return ValueType::Pointer;
+    }
+    if last == "[]" {
+        if type_vec.iter().any(|t| t == "char") {
+            return ValueType::String;
+        }
+        return ValueType::Unknown;
+    }
+    if type_vec.iter().any(|t| t == "struct" || t == "union") {
+        return ValueType::Record;
+    }
+
+    let name: Vec<&str> = type_vec
+        .iter()
+        .map(|t| t.as_str())
+        .filter(|t| *t != "const" && *t != "volatile")
+        .collect();
+    if is_integer_type_name(&name.join(" ")) {
+        return ValueType::Integer;
+    }
+
+    ValueType::Unknown
+}
+
+fn builtin_value_type(name: &str) -> ValueType {
+    match name {
+        "pid" | "tid" | "uid" | "gid" | "nsecs" | "elapsed" | "cpu" | "numaid" | "rand"
+        | "cgroup" | "jiffies" | "cpid" | "ncpus" | "usermode" => ValueType::Integer,
+        "curtask" => ValueType::Pointer,
+        "comm" | "func" | "probe" | "username" | "pcomm" | "probetype" | "kstack" | "ustack" => {
+            ValueType::String
+        }
+        n if n.starts_with("arg") && n[3..].parse::<u32>().is_ok() => ValueType::Integer,
+        _ => ValueType::Unknown,
+    }
+}
+
+fn call_value_type(call: &Node, text: &str) -> ValueType {
+    let Some(function) = call.child_by_field_name("function") else {
+        return ValueType::Unknown;
+    };
+
+    match node_text(&function, text) {
+        // Documented as timestamp, but only strftime() is printed as string
+        "nsecs" => ValueType::Integer,
+        "strftime" => ValueType::String,
+        name => stdlib_signature(name)
+            .and_then(|signature| signature.return_type())
+            .map(stdlib_type_to_value_type)
+            .unwrap_or(ValueType::Unknown),
+    }
+}
+
+pub fn value_type(node: &Node, text: &str, probes: &[String]) -> ValueType {
+    match node.kind() {
+        "integer_literal" | "boolean_literal" => ValueType::Integer,
+        "string_literal" => ValueType::String,
+        "identifier" => builtin_value_type(node_text(node, text)),
+        "call_expression" => call_value_type(node, text),
+        "cast_expression" => {
+            let type_str = node
+                .child_by_field_name("type")
+                .map(|t| node_text(&t, text))
+                .unwrap_or_default();
+            if type_str.contains('*') {
+                ValueType::Pointer
+            } else if is_integer_type_name(type_str)
+                || type_str.starts_with("int")
+                || type_str.starts_with("uint")
+            {
+                ValueType::Integer
+            } else {
+                ValueType::Unknown
+            }
+        }
+        "field_expression" | "args_keyword" | "retval_identifier" => {
+            let chain = node_text(node, text);
+            if !chain.starts_with("args") && !chain.starts_with("retval") {
+                return ValueType::Unknown;
+            }
+            resolve_args_type(probes, chain)
+                .map(|type_vec| btf_type_vec_to_value_type(&type_vec))
+                .unwrap_or(ValueType::Unknown)
+        }
+        "binary_expression" => ValueType::Integer,
+        "unary_expression" => {
+            let op = node
+                .child(0)
+                .map(|op| node_text(&op, text))
+                .unwrap_or_default();
+            if ["-", "!", "~"].contains(&op) {
+                ValueType::Integer
+            } else {
+                ValueType::Unknown
+            }
+        }
+        "parenthesized_expression" => node
+            .named_child(0)
+            .map(|inner| value_type(&inner, text, probes))
+            .unwrap_or(ValueType::Unknown),
+        _ => ValueType::Unknown,
+    }
+}
+
+fn format_call_function<'a>(call: &Node, text: &'a str) -> Option<&'a str> {
+    let function = call.child_by_field_name("function")?;
+    let name = node_text(&function, text);
+    FORMAT_FUNCTIONS.contains(&name).then_some(name)
+}
+
+// Position of byte offset within the string literal content, the literal can't span lines
+fn literal_point(literal: &Node, offset: usize) -> Point {
+    let start = literal.start_position();
+    Point::new(start.row, start.column + 1 + offset)
+}
+
+fn check_format_call(call: &Node, text: &str, diagnostics: &mut json::JsonValue) {
+    let Some(function) = format_call_function(call, text) else {
+        return;
+    };
+    let args = call_arguments(call);
+    let Some(literal) = args.first().filter(|arg| arg.kind() == "string_literal") else {
+        return;
+    };
+
+    let literal_str = node_text(literal, text);
+    let content = literal_str
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(literal_str);
+
+    let specifiers = match parse_format(content) {
+        Ok(specifiers) => specifiers,
+        Err(err) => {
+            let diag = encode_range_diag(
+                text,
+                literal_point(literal, err.offset),
+                literal_point(literal, err.offset + err.len),
+                SEVERITY_ERROR,
+                format!("{}: {}", function, err.message),
+            );
+            let _ = diagnostics.push(diag);
+            return;
+        }
+    };
+
+    let values = &args[1..];
+    if specifiers.len() != values.len() {
+        let diag = encode_diag(
+            call,
+            text,
+            SEVERITY_ERROR,
+            format!(
+                "{}: format string expects {} arguments, {} supplied",
+                function,
+                specifiers.len(),
+                values.len()
+            ),
+        );
+        let _ = diagnostics.push(diag);
+    }
+
+    let probes = probes_for_node(call, text);
+    for (spec, value) in specifiers.iter().zip(values.iter()) {
+        let value_type = value_type(value, text, &probes);
+        if is_compatible(spec.expected, value_type) {
+            continue;
+        }
+
+        // bpftrace may still accept the value, so mismatched types are not errors
+        let diag = encode_diag(
+            value,
+            text,
+            SEVERITY_WARNING,
+            format!(
+                "{}: {} expects {}, but {} is {}",
+                function,
+                &content[spec.offset..spec.offset + spec.len],
+                spec.expected.describe(),
+                node_text(value, text),
+                value_type.describe()
+            ),
+        );
+        let _ = diagnostics.push(diag);
+    }
+}
+
+fn check_node(node: Node, text: &str, diagnostics: &mut json::JsonValue) {
+    if node.kind() == "call_expression" {
+        check_format_call(&node, text, diagnostics);
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        check_node(child, text, diagnostics);
+    }
+}
+
+pub fn check_tree(root: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+    check_node(root, text, &mut diagnostics);
+    diagnostics
+}
+
+// Check if position is inside format string of printf-like function
+pub fn is_in_format_string(node: &Node, text: &str, line_nr: usize, char_nr: usize) -> bool {
+    let pos = Point::new(line_nr, char_nr);
+    let mut current = node.descendant_for_point_range(pos, pos);
+
+    while let Some(n) = current {
+        if n.kind() == "string_literal" {
+            let Some(call) = n.parent().and_then(|args| args.parent()) else {
+                return false;
+            };
+            if format_call_function(&call, text).is_none() {
+                return false;
+            }
+            return call_arguments(&call).first() == Some(&n);
+        }
+        current = n.parent();
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_mod;
+
+    fn check(text: &str) -> Vec<String> {
+        let tree = check_mod::parse(text).unwrap();
+        check_tree(tree.root_node(), text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let specs = parse_format("%s %-5llu %% %rh %p").unwrap();
+        let expected: Vec<ValueType> = specs.iter().map(|s| s.expected).collect();
+        assert_eq!(
+            expected,
+            vec![
+                ValueType::String,
+                ValueType::Integer,
+                ValueType::Buffer,
+                ValueType::Integer
+            ]
+        );
+        assert_eq!((specs[1].offset, specs[1].len), (3, 6));
+
+        let err = parse_format("ok %f").unwrap_err();
+        assert_eq!((err.offset, err.len), (3, 2));
+    }
+
+    #[test]
+    fn test_argument_count() {
+        let text = r#"begin { printf("%d %d\n", pid); system("echo", pid); printf("%%\n"); }"#;
+        assert_eq!(
+            check(text),
+            vec![
+                "printf: format string expects 2 arguments, 1 supplied",
+                "system: format string expects 0 arguments, 1 supplied"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_argument_types() {
+        let text =
+            r#"begin { printf("%s %d %s %d %s\n", pid, comm, str(arg0), (uint64)arg1, kstack); }"#;
+        assert_eq!(
+            check(text),
+            vec![
+                "printf: %s expects a string, but pid is an integer",
+                "printf: %d expects an integer, but comm is a string"
+            ]
+        );
+
+        let text = r#"begin { printf("%r %d %s\n", buf(arg0, 8), $x + 1, @m); }"#;
+        assert!(check(text).is_empty());
+
+        let text = r#"begin { printf("%d %s %d\n", curtask, curtask, comm); }"#;
+        let tree = check_mod::parse(text).unwrap();
+        let diagnostics = check_tree(tree.root_node(), text);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(
+            diagnostics[0]["message"],
+            "printf: %s expects a string, but curtask is a pointer"
+        );
+        assert!(diagnostics
+            .members()
+            .all(|diag| diag["severity"] == check_mod::SEVERITY_WARNING));
+    }
+
+    #[test]
+    fn test_btf_argument_types() {
+        let text = r#"kfunc:vmlinux:do_sys_openat2 { printf("%s %d %s\n", args.filename, args.dfd, str(args.filename)); }"#;
+        assert_eq!(
+            check(text),
+            vec!["printf: %s expects a string, but args.filename is a pointer"]
+        );
+    }
+
+    #[test]
+    fn test_btf_value_types() {
+        let to_vec = |v: &[&str]| -> Vec<String> { v.iter().map(|s| s.to_string()).collect() };
+
+        let t = btf_type_vec_to_value_type(&to_vec(&["const", "char", "*"]));
+        assert_eq!(t, ValueType::Pointer);
+        let t = btf_type_vec_to_value_type(&to_vec(&["char", "[]"]));
+        assert_eq!(t, ValueType::String);
+        let t = btf_type_vec_to_value_type(&to_vec(&["struct", "path"]));
+        assert_eq!(t, ValueType::Record);
+        let t = btf_type_vec_to_value_type(&to_vec(&["long unsigned int"]));
+        assert_eq!(t, ValueType::Integer);
+        let t = btf_type_vec_to_value_type(&to_vec(&["__u32"]));
+        assert_eq!(t, ValueType::Integer);
+        let t = btf_type_vec_to_value_type(&to_vec(&["size_t"]));
+        assert_eq!(t, ValueType::Integer);
+        let t = btf_type_vec_to_value_type(&to_vec(&["atomic_t"]));
+        assert_eq!(t, ValueType::Unknown);
+    }
+
+    #[test]
+    fn test_is_in_format_string() {
+        let text = r#"begin { printf("%d\n", 1); print("%") }"#;
+        let tree = check_mod::parse(text).unwrap();
+        let root = tree.root_node();
+
+        assert!(is_in_format_string(&root, text, 0, 16));
+        assert!(!is_in_format_string(&root, text, 0, 10));
+        assert!(!is_in_format_string(&root, text, 0, 34));
+    }
+}
@@ -0,0 +1,224 @@
+// Usage history of probes, counted when scripts are saved and kept in cache directory, so
+// probe completion can rank functions and events the user attaches to often. Probe is counted
+// once per document, when it is first saved in it, so saving one script again and again does
+// not inflate its probes. Probes are keyed
+// by provider and the last part, e.g. k:vfs_read and fentry:vmlinux:vfs_read are kprobe:vfs_read
+// and fentry:vfs_read.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{LazyLock, Mutex};
+
+use tree_sitter::{Node, Parser};
+
+use crate::btf_cache_mod::cache_dir;
+use crate::log_mod::{self, COMPL};
+use crate::{log_dbg, log_err};
+
+static HISTORY: LazyLock<Mutex<HashMap<String, u32>>> =
+    LazyLock::new(|| Mutex::new(load_history()));
+
+// Keys already counted for each document uri
+static RECORDED: LazyLock<Mutex<HashMap<String, HashSet<String>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+fn history_path() -> Option<PathBuf> {
+    Some(cache_dir()?.join("probe_history"))
+}
+
+// Short names and return probes share history with the probe they are alias of
+pub fn canonical_provider(provider: &str) -> &str {
+    match provider {
+        "k" | "kr" | "kretprobe" => "kprobe",
+        "f" | "fr" | "fexit" | "kfunc" | "kretfunc" => "fentry",
+        "u" | "ur" | "uretprobe" => "uprobe",
+        "t" => "tracepoint",
+        "rt" => "rawtracepoint",
+        "U" => "usdt",
+        "s" => "software",
+        "h" => "hardware",
+        "it" => "iter",
+        provider => provider,
+    }
+}
+
+pub fn history_key(provider: &str, name: &str) -> String {
+    format!("{}:{}", canonical_provider(provider), name)
+}
+
+fn probe_key(probe: &str) -> Option<String> {
+    let (provider, rest) = probe.split_once(':')?;
+    let name = rest.rsplit(':').next()?;
+    if name.is_empty() || name.contains(['*', '?']) {
+        return None;
+    }
+    Some(history_key(provider, name))
+}
+
+// Lines are "count\tkey"
+pub fn parse_history(text: &str) -> HashMap<String, u32> {
+    text.lines()
+        .filter_map(|line| {
+            let (count, key) = line.split_once('\t')?;
+            Some((key.to_string(), count.parse().ok()?))
+        })
+        .collect()
+}
+
+pub fn format_history(history: &HashMap<String, u32>) -> String {
+    let mut entries: Vec<(&String, &u32)> = history.iter().collect();
+    entries.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    entries
+        .iter()
+        .map(|(key, count)| format!("{}\t{}\n", count, key))
+        .collect()
+}
+
+fn load_history() -> HashMap<String, u32> {
+    history_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .map(|text| parse_history(&text))
+        .unwrap_or_default()
+}
+
+fn collect_probes(node: Node, text: &str, probes: &mut Vec<String>) {
+    if node.kind() == "probe" {
+        if let Ok(probe) = node.utf8_text(text.as_bytes()) {
+            probes.push(probe.to_string());
+        }
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_probes(child, text, probes);
+    }
+}
+
+pub fn probes_in_text(text: &str) -> Vec<String> {
+    let mut probes = Vec::new();
+    let mut parser = Parser::new();
+    if let Err(e) = parser.set_language(&tree_sitter_bpftrace::LANGUAGE.into()) {
+        log_err!("Failed to set bpftrace language {}", e);
+        return probes;
+    }
+    if let Some(tree) = parser.parse(text, None) {
+        collect_probes(tree.root_node(), text, &mut probes);
+    }
+    probes
+}
+
+// Keys of probes in the text which were not counted for the document yet
+fn new_keys(recorded: &mut HashSet<String>, text: &str) -> Vec<String> {
+    let mut keys: Vec<String> = probes_in_text(text)
+        .iter()
+        .filter_map(|probe| probe_key(probe))
+        .filter(|key| recorded.insert(key.clone()))
+        .collect();
+    keys.sort();
+    keys
+}
+
+// Probes added to the document since its previous saves are counted
+pub fn record_script(uri: &str, text: &str) {
+    let keys = new_keys(
+        RECORDED.lock().unwrap().entry(uri.to_string()).or_default(),
+        text,
+    );
+    if keys.is_empty() {
+        return;
+    }
+
+    let mut history = HISTORY.lock().unwrap();
+    for key in keys {
+        *history.entry(key).or_default() += 1;
+    }
+    log_dbg!(COMPL, "Probe history has {} entries", history.len());
+
+    let Some(path) = history_path() else {
+        return;
+    };
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, format_history(&history)));
+    if let Err(err) = result {
+        log_err!("Failed to write probe history {:?}: {}", path, err);
+    }
+}
+
+pub fn usage_count(provider: &str, name: &str) -> u32 {
+    HISTORY
+        .lock()
+        .unwrap()
+        .get(&history_key(provider, name))
+        .copied()
+        .unwrap_or_default()
+}
+
+// Names used with the provider, e.g. vfs_read for kprobe
+pub fn used_names(provider: &str) -> Vec<(String, u32)> {
+    let prefix = history_key(provider, "");
+    HISTORY
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|(key, count)| Some((key.strip_prefix(&prefix)?.to_string(), *count)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_keys() {
+        let text =
+            "k:vfs_read, fentry:vmlinux:tcp_sendmsg { }\nkretprobe:vfs_read { }\nt:sched:* { }";
+        let probes = probes_in_text(text);
+        assert_eq!(
+            probes,
+            vec![
+                "k:vfs_read",
+                "fentry:vmlinux:tcp_sendmsg",
+                "kretprobe:vfs_read",
+                "t:sched:*"
+            ]
+        );
+
+        let keys: Vec<String> = probes.iter().filter_map(|p| probe_key(p)).collect();
+        assert_eq!(
+            keys,
+            vec!["kprobe:vfs_read", "fentry:tcp_sendmsg", "kprobe:vfs_read"]
+        );
+    }
+
+    #[test]
+    fn test_new_keys() {
+        let mut recorded = HashSet::new();
+        assert_eq!(
+            new_keys(&mut recorded, "k:vfs_read { }\nkr:vfs_read { }"),
+            vec!["kprobe:vfs_read"]
+        );
+        // Saved again without changes and with one new probe
+        assert!(new_keys(&mut recorded, "k:vfs_read { }").is_empty());
+        assert_eq!(
+            new_keys(&mut recorded, "k:vfs_read { }\nt:sched:sched_switch { }"),
+            vec!["tracepoint:sched_switch"]
+        );
+        // Removed and added back is not counted again
+        assert!(new_keys(&mut recorded, "t:sched:sched_switch { }").is_empty());
+        assert!(new_keys(&mut recorded, "k:vfs_read { }").is_empty());
+    }
+
+    #[test]
+    fn test_history_file_format() {
+        let history = HashMap::from([
+            ("kprobe:vfs_read".to_string(), 3),
+            ("fentry:tcp_sendmsg".to_string(), 7),
+        ]);
+        let text = format_history(&history);
+        assert_eq!(text, "7\tfentry:tcp_sendmsg\n3\tkprobe:vfs_read\n");
+        assert_eq!(parse_history(&text), history);
+        assert!(parse_history("x\tbad\n").is_empty());
+    }
+}
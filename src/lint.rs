@@ -0,0 +1,462 @@
+use json::object;
+use std::collections::{HashMap, HashSet};
+use tree_sitter::{Node, Point};
+
+use crate::btf_mod::btf_find_func_module;
+use crate::check_mod::{
+    call_arguments, encode_range, node_text, probes_for_node, SEVERITY_HINT, SEVERITY_INFORMATION,
+    SEVERITY_WARNING,
+};
+use crate::completion::kernel_func_has_btf;
+use crate::config_mod;
+use crate::format_string::{btf_type_vec_to_value_type, value_type, ValueType};
+use crate::log_dbg;
+use crate::log_mod::{self, DIAGN};
+use crate::parser;
+use crate::var_types;
+
+// Opinionated checks on top of bpftrace errors. Severity of each rule can be changed or the rule
+// turned off with "lint" setting, single line can be excluded with the comment:
+// // bpftrace-ls: ignore <rule>[, <rule>...]
+pub struct LintRule {
+    pub id: &'static str,
+    pub default_severity: u32,
+}
+
+pub const PREFER_FENTRY: &str = "prefer-fentry";
+pub const PRINTF_NEWLINE: &str = "printf-newline";
+pub const UNBOUNDED_TID_MAP: &str = "unbounded-tid-map";
+pub const STR_NON_POINTER: &str = "str-non-pointer";
+pub const INTERVAL_WITHOUT_EXIT: &str = "interval-without-exit";
+
+pub const LINT_RULES: &[LintRule] = &[
+    LintRule {
+        id: PREFER_FENTRY,
+        default_severity: SEVERITY_HINT,
+    },
+    LintRule {
+        id: PRINTF_NEWLINE,
+        default_severity: SEVERITY_INFORMATION,
+    },
+    LintRule {
+        id: UNBOUNDED_TID_MAP,
+        default_severity: SEVERITY_WARNING,
+    },
+    LintRule {
+        id: STR_NON_POINTER,
+        default_severity: SEVERITY_WARNING,
+    },
+    LintRule {
+        id: INTERVAL_WITHOUT_EXIT,
+        default_severity: SEVERITY_WARNING,
+    },
+];
+
+const SUPPRESS_PREFIX: &str = "bpftrace-ls: ignore";
+
+// Builtins which are always integers, str() of those reads from a bogus address. argN are
+// integers too, but they usually hold pointers.
+const INTEGER_BUILTINS: &[&str] = &[
+    "pid", "tid", "uid", "gid", "nsecs", "elapsed", "cpu", "numaid", "rand", "cgroup", "jiffies",
+];
+
+struct LintFinding {
+    rule: &'static str,
+    start: Point,
+    end: Point,
+    message: String,
+}
+
+impl LintFinding {
+    fn new(rule: &'static str, node: &Node, message: String) -> Self {
+        LintFinding {
+            rule,
+            start: node.start_position(),
+            end: node.end_position(),
+            message,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LintState<'t> {
+    findings: Vec<LintFinding>,
+    has_exit: bool,
+    interval_probes: Vec<Node<'t>>,
+    // First assignment of each map keyed by tid
+    tid_maps: Vec<(String, Node<'t>)>,
+    deleted_maps: HashSet<String>,
+}
+
+fn map_name<'a>(map: &Node, text: &'a str) -> &'a str {
+    let map_str = node_text(map, text);
+    map_str.split('[').next().unwrap_or(map_str).trim()
+}
+
+fn lint_probe(probe: &Node, text: &str, state: &mut LintState) {
+    let probe_str = node_text(probe, text);
+    let tokens: Vec<&str> = probe_str.split(':').map(|t| t.trim()).collect();
+
+    let replacement = match tokens[0] {
+        "kprobe" => "fentry",
+        "kretprobe" => "fexit",
+        _ => return,
+    };
+
+    // kprobe:func or kprobe:module:func, wildcards and offsets are not supported by fentry
+    let (module, func) = match tokens.len() {
+        2 => (None, tokens[1]),
+        3 => (Some(tokens[1]), tokens[2]),
+        _ => return,
+    };
+    if func.is_empty() || func.contains(['*', '?', '+']) {
+        return;
+    }
+
+    let module = match module {
+        Some(module) => Some(module.to_string()),
+        None => btf_find_func_module(func),
+    };
+    if module.is_some_and(|module| kernel_func_has_btf(&module, func)) {
+        let message = format!(
+            "{} can be replaced by {}:{}, which has typed arguments and lower overhead",
+            probe_str, replacement, func
+        );
+        state
+            .findings
+            .push(LintFinding::new(PREFER_FENTRY, probe, message));
+    }
+}
+
+fn lint_call<'t>(call: &Node<'t>, text: &str, state: &mut LintState<'t>) {
+    let Some(function) = call.child_by_field_name("function") else {
+        return;
+    };
+    let args = call_arguments(call);
+
+    match node_text(&function, text) {
+        "printf" => {
+            let Some(format) = args.first().filter(|arg| arg.kind() == "string_literal") else {
+                return;
+            };
+            if !node_text(format, text).ends_with("\\n\"") {
+                let message = "printf format does not end with \\n, output of consecutive calls is joined on one line".to_string();
+                state
+                    .findings
+                    .push(LintFinding::new(PRINTF_NEWLINE, format, message));
+            }
+        }
+        "str" => {
+            let Some(arg) = args.first() else {
+                return;
+            };
+            let arg_str = node_text(arg, text);
+            let arg_type = match arg.kind() {
+                "identifier" if INTEGER_BUILTINS.contains(&arg_str) => ValueType::Integer,
+                // Literals, and arguments or retval typed by BTF
+                "integer_literal" | "field_expression" | "args_keyword" | "retval_identifier" => {
+                    value_type(arg, text, &probes_for_node(arg, text))
+                }
+                "scratch_variable" => var_types::var_type_at(arg, text)
+                    .map(|var| btf_type_vec_to_value_type(&var.type_vec))
+                    .unwrap_or(ValueType::Unknown),
+                _ => ValueType::Unknown,
+            };
+            if arg_type == ValueType::Integer {
+                let message = format!("str() expects a pointer, but {} is an integer", arg_str);
+                state
+                    .findings
+                    .push(LintFinding::new(STR_NON_POINTER, arg, message));
+            }
+        }
+        "delete" | "clear" => {
+            if let Some(map) = args.first().filter(|arg| arg.kind() == "map_variable") {
+                state.deleted_maps.insert(map_name(map, text).to_string());
+            }
+        }
+        "exit" => state.has_exit = true,
+        _ => {}
+    }
+}
+
+fn is_keyed_by_tid(map: &Node, text: &str) -> bool {
+    let Some(indexes_list) = map.child(0) else {
+        return false;
+    };
+    let mut cursor = indexes_list.walk();
+    let keyed = indexes_list
+        .named_children(&mut cursor)
+        .any(|idx| idx.kind() == "identifier" && node_text(&idx, text) == "tid");
+    keyed
+}
+
+fn lint_node<'t>(node: Node<'t>, text: &str, state: &mut LintState<'t>) {
+    match node.kind() {
+        "probe" => {
+            if node_text(&node, text).starts_with("interval") {
+                state.interval_probes.push(node);
+            }
+            lint_probe(&node, text, state);
+        }
+        "call_expression" => lint_call(&node, text, state),
+        "assignment_statement" => {
+            if let Some(map) = node
+                .child_by_field_name("left")
+                .filter(|left| left.kind() == "map_variable")
+            {
+                let name = map_name(&map, text);
+                if is_keyed_by_tid(&map, text) && !state.tid_maps.iter().any(|(n, _)| n == name) {
+                    state.tid_maps.push((name.to_string(), map));
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        lint_node(child, text, state);
+    }
+}
+
+// Scripts which are expected to finish by themselves
+fn is_test_script(uri: &str) -> bool {
+    let path = uri.rsplit_once("://").map_or(uri, |(_, path)| path);
+    let mut components: Vec<&str> = path.split('/').collect();
+    let file_name = components.pop().unwrap_or_default();
+    let stem = file_name.split('.').next().unwrap_or_default();
+
+    // test.bt, test_x.bt, x_test.bt, x-test.bt, ... but not latest.bt
+    let is_test_name = stem == "test"
+        || stem.starts_with("test_")
+        || stem.starts_with("test-")
+        || stem.ends_with("_test")
+        || stem.ends_with("-test");
+    is_test_name || components.iter().any(|c| *c == "test" || *c == "tests")
+}
+
+// Line number to suppressed rules, empty list means all rules
+fn find_suppressions(text: &str, root_node: &Node) -> HashMap<usize, Vec<String>> {
+    let mut suppressions: HashMap<usize, Vec<String>> = HashMap::new();
+
+    for comment in parser::find_line_comments(text, root_node) {
+        let comment_str = node_text(&comment, text).trim_start_matches('/').trim();
+        let Some(rules) = comment_str.strip_prefix(SUPPRESS_PREFIX) else {
+            continue;
+        };
+
+        let start = comment.start_position();
+        let line_before_comment = text.lines().nth(start.row).unwrap_or_default();
+        let own_line = line_before_comment
+            .get(..start.column)
+            .is_some_and(|s| s.trim().is_empty());
+
+        // Comment on its own line applies to the next line, trailing comment to its line
+        let line_nr = if own_line { start.row + 1 } else { start.row };
+
+        suppressions.entry(line_nr).or_default().extend(
+            rules
+                .split([',', ' '])
+                .filter(|rule| !rule.is_empty())
+                .map(|rule| rule.to_string()),
+        );
+    }
+
+    suppressions
+}
+
+fn is_suppressed(suppressions: &HashMap<usize, Vec<String>>, finding: &LintFinding) -> bool {
+    (finding.start.row..=finding.end.row).any(|line_nr| {
+        suppressions
+            .get(&line_nr)
+            .is_some_and(|rules| rules.is_empty() || rules.iter().any(|r| r == finding.rule))
+    })
+}
+
+fn rule_severity(config: &config_mod::Config, rule: &LintRule) -> Option<u32> {
+    match config.lint_severity.get(rule.id) {
+        Some(severity) => *severity,
+        None => Some(rule.default_severity),
+    }
+}
+
+pub fn lint_tree(uri: &str, root_node: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+
+    let mut state = LintState::default();
+    lint_node(root_node, text, &mut state);
+
+    for (name, map) in &state.tid_maps {
+        if !state.deleted_maps.contains(name) {
+            let message = format!(
+                "{} is keyed by tid, but never deleted, entries of exited threads are kept forever",
+                name
+            );
+            state
+                .findings
+                .push(LintFinding::new(UNBOUNDED_TID_MAP, map, message));
+        }
+    }
+
+    if !state.has_exit && is_test_script(uri) {
+        for probe in &state.interval_probes {
+            let message = "interval probe without exit(), the test script never finishes".into();
+            state
+                .findings
+                .push(LintFinding::new(INTERVAL_WITHOUT_EXIT, probe, message));
+        }
+    }
+
+    let config = config_mod::get();
+    let suppressions = find_suppressions(text, &root_node);
+
+    for finding in state.findings {
+        let Some(rule) = LINT_RULES.iter().find(|rule| rule.id == finding.rule) else {
+            continue;
+        };
+        let Some(severity) = rule_severity(&config, rule) else {
+            continue;
+        };
+        if is_suppressed(&suppressions, &finding) {
+            log_dbg!(DIAGN, "Lint {} suppressed", finding.rule);
+            continue;
+        }
+
+        let diag = object! {
+            "range": encode_range(text, finding.start, finding.end),
+            "severity": severity,
+            "code": finding.rule,
+            "source": "bpftrace-ls",
+            "message": finding.message,
+        };
+        let _ = diagnostics.push(diag);
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_mod;
+
+    fn lint_text(uri: &str, text: &str) -> json::JsonValue {
+        let tree = check_mod::parse(text).unwrap();
+        lint_tree(uri, tree.root_node(), text)
+    }
+
+    fn lint_codes(uri: &str, text: &str) -> Vec<String> {
+        lint_text(uri, text)
+            .members()
+            .map(|diag| diag["code"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_printf_newline() {
+        let text = r#"begin { printf("%d", pid); printf("%d\n", pid); }"#;
+        assert_eq!(lint_codes("file:///a.bt", text), vec![PRINTF_NEWLINE]);
+    }
+
+    #[test]
+    fn test_prefer_fentry() {
+        let text = "kprobe:do_nanosleep { } kretprobe:vfs_* { } kprobe:no_such_function { }";
+        assert_eq!(lint_codes("file:///a.bt", text), vec![PREFER_FENTRY]);
+    }
+
+    #[test]
+    fn test_str_non_pointer() {
+        let text = "kprobe:a* { print(str(pid)); print(str(arg0)); }";
+        assert_eq!(lint_codes("file:///a.bt", text), vec![STR_NON_POINTER]);
+
+        let text = "kprobe:a* { $x = 1; $p = (uint8 *)arg0; print(str($x)); print(str($p)); }";
+        assert_eq!(lint_codes("file:///a.bt", text), vec![STR_NON_POINTER]);
+
+        // Types of arguments from BTF
+        let text = "fentry:vfs_open { print(str(args.path->dentry->d_name.name)); print(str(args.file->f_flags)); }";
+        let messages: Vec<String> = lint_text("file:///a.bt", text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect();
+        if crate::btf_mod::btf_setup_module("vmlinux").is_some() {
+            assert_eq!(
+                messages,
+                vec!["str() expects a pointer, but args.file->f_flags is an integer"]
+            );
+        }
+    }
+
+    #[test]
+    fn test_unbounded_tid_map() {
+        let text = "kprobe:a* { @start[tid] = nsecs; @x[tid] = 1; }\nkretprobe:a* { delete(@start[tid]); }";
+        let diags = lint_text("file:///a.bt", text);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0]["code"], UNBOUNDED_TID_MAP);
+        assert!(diags[0]["message"].to_string().starts_with("@x "));
+    }
+
+    #[test]
+    fn test_interval_without_exit() {
+        let text = "interval:s:1 { print(1) }";
+        assert_eq!(
+            lint_codes("file:///work/tests/a.bt", text),
+            vec![INTERVAL_WITHOUT_EXIT]
+        );
+        assert!(lint_codes("file:///work/a.bt", text).is_empty());
+
+        let text = "interval:s:1 { exit() }";
+        assert!(lint_codes("file:///work/tests/a.bt", text).is_empty());
+    }
+
+    #[test]
+    fn test_is_test_script() {
+        assert!(is_test_script("file:///work/tests/a.bt"));
+        assert!(is_test_script("file:///work/test/a.bt"));
+        assert!(is_test_script("file:///work/test_open.bt"));
+        assert!(is_test_script("file:///work/open-test.bt"));
+        assert!(is_test_script("file:///work/test.bt"));
+
+        assert!(!is_test_script("file:///work/latest.bt"));
+        assert!(!is_test_script("file:///work/contest/x.bt"));
+        assert!(!is_test_script("file:///work/testing.bt"));
+    }
+
+    #[test]
+    fn test_suppression() {
+        let text = r#"
+begin {
+  // bpftrace-ls: ignore printf-newline
+  printf("a");
+  printf("b"); // bpftrace-ls: ignore str-non-pointer
+  printf("c"); // bpftrace-ls: ignore
+  printf("d"); // bpftrace-ls: ignore str-non-pointer, printf-newline
+}
+"#;
+        let diags = lint_text("file:///a.bt", text);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0]["range"]["start"]["line"], 4);
+    }
+
+    #[test]
+    fn test_rule_severity_override() {
+        let mut config = config_mod::Config::default();
+        config
+            .lint_severity
+            .insert(PRINTF_NEWLINE.to_string(), Some(1));
+        config.lint_severity.insert(PREFER_FENTRY.to_string(), None);
+
+        let severity = |id| rule_severity(&config, LINT_RULES.iter().find(|r| r.id == id).unwrap());
+        assert_eq!(severity(PRINTF_NEWLINE), Some(1));
+        assert_eq!(severity(PREFER_FENTRY), None);
+        assert_eq!(severity(STR_NON_POINTER), Some(SEVERITY_WARNING));
+    }
+
+    #[test]
+    fn test_suppressions_lines() {
+        let text = "// bpftrace-ls: ignore a b\nbegin { } // bpftrace-ls: ignore c\n";
+        let tree = check_mod::parse(text).unwrap();
+
+        let suppressions = find_suppressions(text, &tree.root_node());
+        assert_eq!(suppressions[&1], vec!["a", "b", "c"]);
+    }
+}
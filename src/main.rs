@@ -8,14 +8,32 @@ use std::{
     io::{self, Read, Write},
     sync::{mpsc, Arc, LazyLock, RwLock},
     thread,
-    time::{Duration, Instant},
+    time::Instant,
 };
 
+mod btf_cache_mod;
 pub mod btf_mod;
+mod btf_source_mod;
+mod check_mod;
 mod cmd_mod;
 mod completion;
+mod config_mod;
+mod diag_mod;
+mod dwarf_mod;
+mod elf_mod;
+mod format_string;
 pub mod gen;
+mod history_mod;
+mod lint;
 pub mod parser;
+mod proc_mod;
+mod signature_check;
+mod trace_index_mod;
+mod trace_list_mod;
+mod tracepoint;
+mod uprobe;
+mod usdt;
+mod var_types;
 
 #[macro_use]
 pub mod log_mod;
@@ -60,6 +78,11 @@ impl DocumentsState {
         read_guard.map.get(uri).cloned()
     }
 
+    fn uris(&self) -> Vec<String> {
+        let read_guard = self.0.read().unwrap();
+        read_guard.map.keys().cloned().collect()
+    }
+
     fn set(&self, uri: String, text: String, version: u64) {
         let mut write_guard = self.0.write().unwrap();
 
@@ -72,6 +95,11 @@ impl DocumentsState {
         });
         write_guard.map.insert(uri, text_doc);
     }
+
+    fn remove(&self, uri: &str) {
+        let mut write_guard = self.0.write().unwrap();
+        write_guard.map.remove(uri);
+    }
 }
 
 #[derive(Debug)]
@@ -85,6 +113,8 @@ enum NotificationAction {
     None,
     Exit,
     SendDiagnostics(String),
+    RefreshDiagnostics,
+    CloseDocument(String),
 }
 
 struct LspClientMessage {
@@ -109,10 +139,13 @@ struct DiagnosticsRequest {
 enum MpscMessage {
     ClientMessage(LspClientMessage),
     Diagnostics(DiagnosticsResutls),
+    Response(String),
 }
 
 enum DiagnosticsCommand {
     DiagRequest(DiagnosticsRequest),
+    Done(diag_mod::DiagnosticsDone),
+    Close(String),
     Exit,
 }
 
@@ -146,8 +179,22 @@ fn handle_notification(method: String, content: json::JsonValue) -> Notification
         "textDocument/didSave" => {
             let text_document = &content["params"]["textDocument"];
             let uri = text_document["uri"].to_string();
+            if let Some(text_doc) = DOCUMENTS_STATE.get(&uri) {
+                history_mod::record_script(&uri, &text_doc.text);
+            }
             return NotificationAction::SendDiagnostics(uri);
         }
+        "textDocument/didClose" => {
+            let uri = content["params"]["textDocument"]["uri"].to_string();
+            DOCUMENTS_STATE.remove(&uri);
+            log_dbg!(NOTIF, "Close: textDocument: {}", uri);
+            return NotificationAction::CloseDocument(uri);
+        }
+        "workspace/didChangeConfiguration" => {
+            config_mod::update_from_json(&content["params"]["settings"]);
+            // Lint severities, BTF source or target process could change
+            return NotificationAction::RefreshDiagnostics;
+        }
         "exit" => {
             return NotificationAction::Exit;
         }
@@ -162,16 +209,23 @@ fn handle_notification(method: String, content: json::JsonValue) -> Notification
     NotificationAction::None
 }
 
-fn encode_initalize_result() -> json::JsonValue {
+fn encode_initalize_result(content: json::JsonValue) -> json::JsonValue {
+    config_mod::update_from_json(&content["params"]["initializationOptions"]);
+    config_mod::set_client_capabilities(&content["params"]);
+
     let capabilities = object! {
         "textDocumentSync": 1,
         "hoverProvider": true,
+        "inlayHintProvider": true,
         "definitionProvider": true,
         // "codeActionProvider": true,
         "completionProvider": {
-            "triggerCharacters": [":", ".", ">", "$", "@"],
-            // TODO "resolveProvider": true,
-
+            "triggerCharacters": [":", ".", ">", "$", "@", "%"],
+            "resolveProvider": true,
+        },
+        "diagnosticProvider": {
+            "interFileDependencies": false,
+            "workspaceDiagnostics": true,
         },
     };
 
@@ -415,13 +469,27 @@ fn bpftrace_diag_definitions_error(
     Ok(diag)
 }
 
+fn diag_timed_out() -> json::JsonValue {
+    let timeout = config_mod::get().dry_run_timeout;
+
+    object! {
+        "range": { "start": { "line": 0, "character": 0}, "end": {"line": 0, "character": 0, }, },
+        "severity": 2,
+        "source": "bpftrace-ls",
+        "message": format!("Diagnostics timed out, bpftrace dry-run did not finish in {} ms", timeout.as_millis()),
+    }
+}
+
 fn do_bpftrace_diagnostics(text: &str) -> json::JsonValue {
     let mut diagnostics = json::JsonValue::new_array();
 
-    let output = if let Ok(ok_output) = cmd_mod::bpftrace_dry_run_command(text) {
-        ok_output
-    } else {
-        return diagnostics;
+    let output = match cmd_mod::bpftrace_dry_run_command(text) {
+        Ok(ok_output) => ok_output,
+        Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+            let _ = diagnostics.push(diag_timed_out());
+            return diagnostics;
+        }
+        Err(_) => return diagnostics,
     };
 
     let output = if let Ok(ok_output) = String::from_utf8(output.stderr) {
@@ -474,15 +542,15 @@ fn send_diag_command(uri: String, version: u64, diag_tx: &mpsc::Sender<Diagnosti
     let _ = diag_tx.send(DiagnosticsCommand::DiagRequest(diag_req));
 }
 
-fn do_diagnostics(uri: String, diag_tx: &mpsc::Sender<DiagnosticsCommand>) -> Option<String> {
+fn do_diagnostics(uri: String, diag_tx: &mpsc::Sender<DiagnosticsCommand>) -> Vec<String> {
     let Some(text_doc) = DOCUMENTS_STATE.get(&uri) else {
         log_dbg!(DIAGN, "No text document for {}", uri);
-        return None;
+        return Vec::new();
     };
 
     if text_doc.text.trim().is_empty() {
         log_dbg!(DIAGN, "No diagnostics for empty text {}", text_doc.text);
-        return None;
+        return Vec::new();
     }
 
     let version = text_doc.version;
@@ -497,20 +565,21 @@ fn do_diagnostics(uri: String, diag_tx: &mpsc::Sender<DiagnosticsCommand>) -> Op
                 diagnostics,
             };
 
-            return publish_diagnostics(diag_results);
+            return diagnostics_ready(diag_results);
         }
     }
 
     // Otherwise send command to diffrent thread to do bpftrace --dry-run for diagnostics
     send_diag_command(uri, version, diag_tx);
-    None
+    Vec::new()
 }
 
 fn send_diag_exit(diag_tx: &mpsc::Sender<DiagnosticsCommand>) {
     let _ = diag_tx.send(DiagnosticsCommand::Exit);
 }
 
-fn publish_diagnostics(diag_results: DiagnosticsResutls) -> Option<String> {
+// Cache results for pull diagnostics and either answer waiting pull requests or publish them
+fn diagnostics_ready(diag_results: DiagnosticsResutls) -> Vec<String> {
     let uri = &diag_results.uri;
     log_dbg!(
         DIAGN,
@@ -519,7 +588,9 @@ fn publish_diagnostics(diag_results: DiagnosticsResutls) -> Option<String> {
         diag_results.version
     );
 
-    let text_doc = DOCUMENTS_STATE.get(uri)?;
+    let Some(text_doc) = DOCUMENTS_STATE.get(uri) else {
+        return Vec::new();
+    };
 
     if text_doc.version != diag_results.version {
         log_dbg!(
@@ -528,11 +599,22 @@ fn publish_diagnostics(diag_results: DiagnosticsResutls) -> Option<String> {
             text_doc.version,
             diag_results.version
         );
-        return None;
+        return Vec::new();
     }
 
     log_vdbg!(DIAGN, "Text: \n{}\n", &text_doc.text);
 
+    diag_mod::cache_diagnostics(
+        uri,
+        text_doc.version,
+        &text_doc.text,
+        &diag_results.diagnostics,
+    );
+
+    if config_mod::client_capabilities().pull_diagnostics {
+        return diag_mod::answer_pending_pulls(uri);
+    }
+
     let params = object! {
         "uri": uri.to_string(),
         "version": text_doc.version,
@@ -546,28 +628,33 @@ fn publish_diagnostics(diag_results: DiagnosticsResutls) -> Option<String> {
     };
 
     let resp = data.dump();
-    Some(format!(
+    vec![format!(
         "Content-Length: {}\r\n\r\n{}\r\n",
         resp.len(),
         resp
-    ))
+    )]
 }
 
 fn encode_message(id: u64, method: &str, content: json::JsonValue) -> String {
-    let mut data = match method {
-        "initialize" => encode_initalize_result(),
+    let data = match method {
+        "initialize" => encode_initalize_result(content),
         "shutdown" => encode_shutdown(),
         "textDocument/hover" => completion::encode_hover(content),
         "textDocument/definition" => encode_definition(content),
         "textDocument/codeAction" => encode_code_action(content),
         "textDocument/completion" => completion::encode_completion(content),
         "completionItem/resolve" => completion::encode_completion_resolve(content),
+        "textDocument/inlayHint" => completion::encode_inlay_hints(content),
         unhandled_method => {
             log_dbg!(PROTO, "No handler for method: {}", unhandled_method);
             object! {}
         }
     };
 
+    encode_response(id, data)
+}
+
+fn encode_response(id: u64, mut data: json::JsonValue) -> String {
     data["id"] = id.into();
     data["jasonrpc"] = JSON_RPC_VERSION.into();
 
@@ -721,62 +808,36 @@ fn thread_input(mpsc_tx: mpsc::Sender<MpscMessage>) {
     }
 }
 
-fn thread_diagnostics(
-    mpsc_tx: mpsc::Sender<MpscMessage>,
-    diag_rx: mpsc::Receiver<DiagnosticsCommand>,
-) {
-    loop {
-        match diag_rx.recv() {
-            Ok(diag_msg) => match diag_msg {
-                DiagnosticsCommand::DiagRequest(diag_req) => {
-                    let uri = diag_req.uri;
-                    let version = diag_req.version;
-
-                    // Skip diagnostics if file is edited
-                    // TODO: check if 300ms is more or less good heuristics
-                    thread::sleep(Duration::from_millis(300));
-
-                    let option = DOCUMENTS_STATE.get(&uri);
-                    if option.is_none() {
-                        log_err!("Can not find document for {uri}");
-                        continue;
-                    }
-                    let text_doc = option.unwrap();
-
-                    if text_doc.version != diag_req.version {
-                        log_dbg!(
-                            DIAGN,
-                            "Skip diagnostics for old version {}, version is {}",
-                            diag_req.version,
-                            text_doc.version
-                        );
-                        continue;
-                    }
-
-                    let diagnostics = do_bpftrace_diagnostics(&text_doc.text);
-
-                    let diag_msg = DiagnosticsResutls {
-                        uri,
-                        version,
-                        diagnostics,
-                    };
-                    let _res = mpsc_tx.send(MpscMessage::Diagnostics(diag_msg));
-                }
-                DiagnosticsCommand::Exit => {
-                    log_dbg!(DIAGN, "Exit diagnostics thread");
-                    break;
-                }
-            },
-            Err(e) => {
-                log_err!("Diagnostics MPSC error {}", e);
-                break;
+fn handle_request(
+    id: u64,
+    method: &str,
+    content: json::JsonValue,
+    mpsc_tx: &mpsc::Sender<MpscMessage>,
+    diag_tx: &mpsc::Sender<DiagnosticsCommand>,
+) -> Option<String> {
+    match method {
+        "textDocument/diagnostic" => {
+            if let Some(data) = diag_mod::encode_document_diagnostic(id, &content) {
+                return Some(encode_response(id, data));
             }
+            // Make sure dry-run is scheduled, answer will be send when it's done
+            let uri = content["params"]["textDocument"]["uri"].to_string();
+            if let Some(text_doc) = DOCUMENTS_STATE.get(&uri) {
+                send_diag_command(uri, text_doc.version, diag_tx);
+            }
+            None
+        }
+        "workspace/diagnostic" => {
+            diag_mod::workspace_diagnostic(id, content, mpsc_tx.clone());
+            None
         }
+        _ => Some(encode_message(id, method, content)),
     }
 }
 
 fn handle_client_msg(
     lsp_client_msg: LspClientMessage,
+    mpsc_tx: &mpsc::Sender<MpscMessage>,
     diag_tx: &mpsc::Sender<DiagnosticsCommand>,
 ) -> bool {
     let LspClientMessage {
@@ -789,7 +850,10 @@ fn handle_client_msg(
 
     match msg_type {
         LspMessageType::Request => {
-            let s = encode_message(id, &method, content);
+            let Some(s) = handle_request(id, &method, content, mpsc_tx, diag_tx) else {
+                log_dbg!(PROTO, "Response for {} with id {} deferred", method, id);
+                return false;
+            };
             let time_diff = start_time.elapsed();
             log_dbg!(PROTO, "Response time {:?}", time_diff);
             log_vdbg!(PROTO, "Answer:\n{}", s);
@@ -806,11 +870,28 @@ fn handle_client_msg(
             // TODO consider moving this to handle notification
             match notif_action {
                 NotificationAction::SendDiagnostics(uri) => {
-                    if let Some(s) = do_diagnostics(uri, diag_tx) {
+                    for s in do_diagnostics(uri, diag_tx) {
                         log_dbg!(DIAGN, "Send diagnostics: {}", s);
                         send_message(s);
                     }
                 }
+                NotificationAction::RefreshDiagnostics => {
+                    let client = config_mod::client_capabilities();
+                    if client.pull_diagnostics && client.diagnostic_refresh_support {
+                        send_message(diag_mod::encode_refresh_request());
+                    }
+                    for uri in DOCUMENTS_STATE.uris() {
+                        for s in do_diagnostics(uri, diag_tx) {
+                            send_message(s);
+                        }
+                    }
+                }
+                NotificationAction::CloseDocument(uri) => {
+                    for s in diag_mod::forget_document(&uri) {
+                        send_message(s);
+                    }
+                    let _ = diag_tx.send(DiagnosticsCommand::Close(uri));
+                }
                 NotificationAction::Exit => {
                     log_dbg!(PROTO, "Exiting");
                     send_diag_exit(diag_tx);
@@ -833,16 +914,19 @@ fn main() {
 
     let completion_init = thread::spawn(completion::init_available_traces);
     let command_init = thread::spawn(cmd_mod::init_bpftrace_dry_run);
+    thread::spawn(btf_cache_mod::init_vmlinux_index);
 
     let (mpsc_tx, mpsc_rx) = mpsc::channel::<MpscMessage>();
     let diag_mpsc_tx = mpsc_tx.clone();
+    let resp_mpsc_tx = mpsc_tx.clone();
     thread::spawn(move || thread_input(mpsc_tx));
 
     let (diag_tx, diag_rx) = mpsc::channel::<DiagnosticsCommand>();
+    let diag_sched_tx = diag_tx.clone();
     thread::spawn(move || {
         let _ = completion_init.join();
         let _ = command_init.join();
-        thread_diagnostics(diag_mpsc_tx, diag_rx)
+        diag_mod::thread_diagnostics(diag_mpsc_tx, diag_sched_tx, diag_rx)
     });
 
     loop {
@@ -850,17 +934,21 @@ fn main() {
             Ok(mpsc_msg) => {
                 match mpsc_msg {
                     MpscMessage::ClientMessage(client_msg) => {
-                        let do_exit = handle_client_msg(client_msg, &diag_tx);
+                        let do_exit = handle_client_msg(client_msg, &resp_mpsc_tx, &diag_tx);
                         if do_exit {
                             break;
                         }
                     }
                     MpscMessage::Diagnostics(diag_results) => {
-                        if let Some(s) = publish_diagnostics(diag_results) {
+                        for s in diagnostics_ready(diag_results) {
                             log_dbg!(DIAGN, "Send diagnostics: {}", s);
                             send_message(s);
                         }
                     }
+                    MpscMessage::Response(s) => {
+                        log_vdbg!(PROTO, "Answer:\n{}", s);
+                        send_message(s);
+                    }
                 };
             }
             Err(err) => {
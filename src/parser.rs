@@ -226,6 +226,33 @@ pub fn find_all_map_variables<'t>(text: &str, root_node: &Node<'t>) -> Vec<Node<
     results
 }
 
+pub fn find_line_comments<'t>(text: &str, root_node: &Node<'t>) -> Vec<Node<'t>> {
+    let query_str = r#"
+        (line_comment) @line_comment
+    "#;
+
+    let query = match Query::new(&tree_sitter_bpftrace::LANGUAGE.into(), query_str) {
+        Ok(q) => q,
+        Err(e) => {
+            log_err!("Tree-sitter error: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut query_cursor = QueryCursor::new();
+    let mut matches = query_cursor.matches(&query, *root_node, text.as_bytes());
+
+    let mut results: Vec<Node> = vec![];
+
+    while let Some(m) = matches.next() {
+        for cap in m.captures {
+            results.push(cap.node);
+        }
+    }
+
+    results
+}
+
 fn probes_list_to_vec(probes_list: &Node, text: &str) -> Vec<String> {
     let mut probes_vec: Vec<String> = Vec::with_capacity(probes_list.child_count());
     for i in 0..probes_list.child_count() {
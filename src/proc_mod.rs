@@ -0,0 +1,146 @@
+// Target process of script run with `bpftrace -p PID`, set by targetPid setting or by comment:
+//
+// // bpftrace-ls: pid=<n>
+//
+// Binaries of uprobe and usdt probes are then the objects mapped by the process, opened through
+// /proc/<pid>/root to see the mount namespace of the process.
+use std::fs;
+use std::path::Path;
+
+use crate::config_mod;
+
+const PID_PREFIX: &str = "bpftrace-ls: pid=";
+
+pub fn pid_from_comment(text: &str) -> Option<u32> {
+    text.lines()
+        .filter_map(|line| line.trim().strip_prefix("//"))
+        .find_map(|comment| comment.trim().strip_prefix(PID_PREFIX))
+        .and_then(|pid| pid.trim().parse().ok())
+}
+
+// Comment in the script takes precedence over the setting
+pub fn target_pid(text: &str) -> Option<u32> {
+    pid_from_comment(text).or(config_mod::get().target_pid)
+}
+
+// Start time of process in clock ticks after boot, tells apart processes with the same pid
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // Name of the process can contain spaces and parentheses, starttime is the 22nd field
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+// Target process in keys of cached results, changes with the setting, the comment and also when
+// the process is restarted
+pub fn target_key(text: &str) -> String {
+    match target_pid(text) {
+        Some(pid) => format!("{}@{}", pid, process_start_time(pid).unwrap_or_default()),
+        None => String::new(),
+    }
+}
+
+// Executable mappings backed by files, in order of first appearance
+pub fn parse_maps(maps: &str) -> Vec<String> {
+    let mut objects: Vec<String> = Vec::new();
+    for line in maps.lines() {
+        // address perms offset dev inode path
+        let fields: Vec<&str> = line.splitn(6, char::is_whitespace).collect();
+        let [_, perms, _, _, _, path] = fields[..] else {
+            continue;
+        };
+        let path = path.trim();
+        if !perms.contains('x') || !path.starts_with('/') || path.ends_with(" (deleted)") {
+            continue;
+        }
+        if !objects.iter().any(|o| o == path) {
+            objects.push(path.to_string());
+        }
+    }
+    objects
+}
+
+pub fn mapped_objects(pid: u32) -> Vec<String> {
+    fs::read_to_string(format!("/proc/{}/maps", pid))
+        .map(|maps| parse_maps(&maps))
+        .unwrap_or_default()
+}
+
+// Path as seen by the process, falls back to the path itself when the root is not accessible
+pub fn process_path(pid: Option<u32>, path: &str) -> String {
+    if let Some(pid) = pid.filter(|_| path.starts_with('/')) {
+        let rooted = format!("/proc/{}/root{}", pid, path);
+        if Path::new(&rooted).exists() {
+            return rooted;
+        }
+    }
+    path.to_string()
+}
+
+// Library given by name instead of path, e.g. uprobe:libc:malloc with -p PID
+pub fn find_mapped_object(pid: u32, name: &str) -> Option<String> {
+    mapped_objects(pid).into_iter().find(|object| {
+        let file_name = object.rsplit('/').next().unwrap_or_default();
+        file_name == name
+            || file_name.starts_with(&format!("{}.so", name))
+            || file_name.starts_with(&format!("{}-", name))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid_from_comment() {
+        assert_eq!(
+            pid_from_comment("// bpftrace-ls: pid=1234\nbegin {}"),
+            Some(1234)
+        );
+        assert_eq!(
+            pid_from_comment("begin {}\n  //bpftrace-ls: pid= 42 "),
+            Some(42)
+        );
+        assert_eq!(pid_from_comment("// bpftrace-ls: pid=abc\nbegin {}"), None);
+        assert_eq!(pid_from_comment("begin {}"), None);
+    }
+
+    #[test]
+    fn test_parse_maps() {
+        let maps = "\
+55d0c0a00000-55d0c0a2e000 r--p 00000000 fd:01 1234     /usr/bin/bash
+55d0c0a2e000-55d0c0b0e000 r-xp 0002e000 fd:01 1234     /usr/bin/bash
+7f1e2c028000-7f1e2c1bd000 r-xp 00028000 fd:01 5678     /usr/lib/x86_64-linux-gnu/libc.so.6
+7f1e2c200000-7f1e2c201000 r-xp 00000000 fd:01 9999     /tmp/old.so (deleted)
+7f1e2c300000-7f1e2c321000 rw-p 00000000 00:00 0        [heap]
+7ffd1b1f0000-7ffd1b1f2000 r-xp 00000000 00:00 0        [vdso]
+";
+        assert_eq!(
+            parse_maps(maps),
+            vec!["/usr/bin/bash", "/usr/lib/x86_64-linux-gnu/libc.so.6"]
+        );
+    }
+
+    #[test]
+    fn test_mapped_objects() {
+        let pid = std::process::id();
+        let exe = fs::read_link("/proc/self/exe").unwrap();
+        let exe = exe.to_str().unwrap();
+        assert!(mapped_objects(pid).iter().any(|o| o == exe));
+
+        let rooted = process_path(Some(pid), exe);
+        assert_eq!(rooted, format!("/proc/{}/root{}", pid, exe));
+        assert_eq!(process_path(None, exe), exe);
+    }
+
+    #[test]
+    fn test_target_key() {
+        let pid = std::process::id();
+        let start = process_start_time(pid).unwrap();
+        assert!(start > 0);
+
+        let text = format!("// bpftrace-ls: pid={}\nbegin {{}}", pid);
+        assert_eq!(target_key(&text), format!("{}@{}", pid, start));
+        assert_eq!(target_key("// bpftrace-ls: pid=0\nbegin {}"), "0@0");
+    }
+}
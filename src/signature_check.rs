@@ -0,0 +1,282 @@
+use tree_sitter::Node;
+
+use crate::check_mod::{call_arguments, encode_diag, node_text, probes_for_node, SEVERITY_ERROR};
+use crate::completion::find_enum_constant;
+use crate::gen::signatures::{stdlib_signature, Signature};
+
+// Short and alias probe names to the provider names used in signatures
+fn normalize_provider(provider: &str) -> String {
+    let provider = provider.trim().to_lowercase();
+    let long = match provider.as_str() {
+        "k" => "kprobe",
+        "kr" => "kretprobe",
+        "u" => "uprobe",
+        "ur" => "uretprobe",
+        "t" => "tracepoint",
+        "rt" => "rawtracepoint",
+        "f" | "kfunc" => "fentry",
+        "fr" | "kretfunc" => "fexit",
+        "i" => "interval",
+        "p" => "profile",
+        "h" => "hardware",
+        "s" => "software",
+        "it" => "iter",
+        _ => return provider,
+    };
+    long.to_string()
+}
+
+// Names defined by #define in C preprocessor lines of the script
+fn is_defined_macro(node: &Node, text: &str, name: &str) -> bool {
+    let mut source_file = *node;
+    while let Some(parent) = source_file.parent() {
+        source_file = parent;
+    }
+
+    // C preprocessor lines are part of the preamble before the probes
+    let mut cursor = source_file.walk();
+    let Some(preamble) = source_file
+        .named_children(&mut cursor)
+        .find(|n| n.kind() == "preamble")
+    else {
+        return false;
+    };
+
+    let mut cursor = preamble.walk();
+    let found = preamble
+        .named_children(&mut cursor)
+        .filter(|n| n.kind() == "c_preproc")
+        .any(|n| {
+            let mut words = node_text(&n, text).split_whitespace();
+            words.next() == Some("#define")
+                && words
+                    .next()
+                    .is_some_and(|macro_name| macro_name.split('(').next() == Some(name))
+        });
+    found
+}
+
+// Constants folded by bpftrace are fine too, e.g. lhist(x, 0, 1024 * 1024, 1), enum constants
+// from BTF like HRTIMER_MODE_REL or values of #define
+fn is_literal_expr(node: &Node, text: &str) -> bool {
+    match node.kind() {
+        "integer_literal" | "string_literal" | "boolean_literal" | "positional_parameter" => true,
+        "identifier" => {
+            let name = node_text(node, text);
+            is_defined_macro(node, text, name) || find_enum_constant("vmlinux", name).is_some()
+        }
+        "unary_expression" | "binary_expression" | "parenthesized_expression" => {
+            let mut cursor = node.walk();
+            let all_literals = node
+                .named_children(&mut cursor)
+                .all(|child| is_literal_expr(&child, text));
+            all_literals
+        }
+        _ => false,
+    }
+}
+
+fn arity_str(signature: &Signature) -> String {
+    let mut counts: Vec<String> = signature
+        .overloads
+        .iter()
+        .map(|o| match o.max_args() {
+            None => format!("at least {}", o.min_args()),
+            Some(max) if max == o.min_args() => max.to_string(),
+            Some(max) => format!("{} to {}", o.min_args(), max),
+        })
+        .collect();
+    counts.dedup();
+    counts.join(" or ")
+}
+
+fn check_probe_context(
+    signature: &Signature,
+    node: &Node,
+    text: &str,
+    diagnostics: &mut json::JsonValue,
+) {
+    if signature.allowed_probes.is_empty() && signature.disallowed_probes.is_empty() {
+        return;
+    }
+
+    for probe in probes_for_node(node, text) {
+        let provider = normalize_provider(probe.split(':').next().unwrap_or_default());
+
+        let allowed = (signature.allowed_probes.is_empty()
+            || signature.allowed_probes.contains(&provider.as_str()))
+            && !signature.disallowed_probes.contains(&provider.as_str());
+        if !allowed {
+            let message = format!("{}() can not be used in {} probe", signature.name, provider);
+            let _ = diagnostics.push(encode_diag(node, text, SEVERITY_ERROR, message));
+            return;
+        }
+    }
+}
+
+fn check_call(call: &Node, text: &str, diagnostics: &mut json::JsonValue) {
+    let Some(function) = call.child_by_field_name("function") else {
+        return;
+    };
+    let Some(signature) = stdlib_signature(node_text(&function, text)) else {
+        return;
+    };
+
+    let args = call_arguments(call);
+    let matching: Vec<_> = signature
+        .overloads
+        .iter()
+        .filter(|o| o.accepts(args.len()))
+        .collect();
+
+    if matching.is_empty() {
+        let message = format!(
+            "{}() expects {} arguments, {} supplied",
+            signature.name,
+            arity_str(signature),
+            args.len()
+        );
+        let _ = diagnostics.push(encode_diag(call, text, SEVERITY_ERROR, message));
+    }
+
+    for (idx, arg) in args.iter().enumerate() {
+        let literal_only = !matching.is_empty()
+            && matching
+                .iter()
+                .all(|o| o.params.get(idx).is_some_and(|p| p.literal));
+        if literal_only && !is_literal_expr(arg, text) {
+            let param = matching[0].params[idx].name;
+            let message = format!(
+                "{}() expects a literal for parameter {}, {} is not a literal",
+                signature.name,
+                param,
+                node_text(arg, text)
+            );
+            let _ = diagnostics.push(encode_diag(arg, text, SEVERITY_ERROR, message));
+            continue;
+        }
+
+        let choices = matching
+            .iter()
+            .find_map(|o| o.params.get(idx).map(|p| p.choices))
+            .unwrap_or_default();
+        let arg_str = node_text(arg, text);
+        if !choices.is_empty() && !choices.contains(&arg_str) {
+            let message = format!(
+                "{}() expects {}, {} is not one of them",
+                signature.name,
+                choices.join(" or "),
+                arg_str
+            );
+            let _ = diagnostics.push(encode_diag(arg, text, SEVERITY_ERROR, message));
+        }
+    }
+
+    check_probe_context(signature, call, text, diagnostics);
+}
+
+fn check_node(node: Node, text: &str, diagnostics: &mut json::JsonValue) {
+    match node.kind() {
+        "call_expression" => check_call(&node, text, diagnostics),
+        // Builtins like kstack can be used without parentheses
+        "identifier" => {
+            let is_call = node
+                .parent()
+                .is_some_and(|parent| parent.kind() == "call_expression");
+            if let Some(signature) = stdlib_signature(node_text(&node, text)).filter(|_| !is_call) {
+                check_probe_context(signature, &node, text, diagnostics);
+            }
+        }
+        _ => {}
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        check_node(child, text, diagnostics);
+    }
+}
+
+pub fn check_tree(root: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+    check_node(root, text, &mut diagnostics);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_mod;
+
+    fn check(text: &str) -> Vec<String> {
+        let tree = check_mod::parse(text).unwrap();
+        check_tree(tree.root_node(), text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_arity() {
+        let text = "begin { exit(1, 2); @a = count(1); print(@a); delete(@a); }";
+        assert_eq!(
+            check(text),
+            vec![
+                "exit() expects 0 to 1 arguments, 2 supplied",
+                "count() expects 0 arguments, 1 supplied"
+            ]
+        );
+
+        let text = r#"begin { printf(); }"#;
+        assert_eq!(
+            check(text),
+            vec!["printf() expects at least 1 arguments, 0 supplied"]
+        );
+    }
+
+    #[test]
+    fn test_literal_parameters() {
+        let text = "#define MAX 100\nkprobe:vfs_read { $max = 100; @a = lhist(arg2, 0, $max, 10); @b = lhist(arg2, 0, 1024 * 4, MAX); @c = lhist(arg2, 0, HRTIMER_MODE_REL, 1); @d = lhist(arg2, 0, NOT_DEFINED, 1); }";
+        assert_eq!(
+            check(text),
+            vec![
+                "lhist() expects a literal for parameter max, $max is not a literal",
+                "lhist() expects a literal for parameter max, NOT_DEFINED is not a literal"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parameter_choices() {
+        let signature = stdlib_signature("pid").unwrap();
+        let param = &signature.overloads[0].params[0];
+        assert_eq!(param.name, "curr_ns");
+        assert_eq!(param.choices, ["curr_ns", "init"]);
+
+        let text = "begin { print(pid(init)); print(pid(curr_ns)); print(tid(host)); }";
+        assert_eq!(
+            check(text),
+            vec!["tid() expects curr_ns or init, host is not one of them"]
+        );
+    }
+
+    #[test]
+    fn test_probe_context() {
+        let text = "begin { print(kstack); @[kstack()] = count(); }\nkprobe:vfs_read { @[kstack] = count(); print(reg(\"ip\")); }\nfentry:vfs_read { print(reg(\"ip\")) }";
+        assert_eq!(
+            check(text),
+            vec![
+                "kstack() can not be used in begin probe",
+                "kstack() can not be used in begin probe",
+                "reg() can not be used in fentry probe"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_normalize_provider() {
+        assert_eq!(normalize_provider("BEGIN"), "begin");
+        assert_eq!(normalize_provider("kr"), "kretprobe");
+        assert_eq!(normalize_provider("kfunc"), "fentry");
+        assert_eq!(normalize_provider("usdt"), "usdt");
+    }
+}
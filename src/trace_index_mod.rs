@@ -0,0 +1,358 @@
+// Attach points in format of `bpftrace -l` grouped by provider, e.g. kprobe:vfs_read is stored as
+// vfs_read under kprobe. Each provider is sorted, so prefix queries are binary searches and all
+// traces sharing a prefix are next to each other.
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub struct TraceIndex {
+    providers: HashMap<String, Vec<String>>,
+}
+
+// Next token of matched traces, e.g. sched for tracepoint:sch
+#[derive(Debug, PartialEq)]
+pub struct TraceToken<'a> {
+    pub token: &'a str,
+    // Token is the end of the trace, not a module or category
+    pub is_last: bool,
+}
+
+impl TraceIndex {
+    pub fn from_list(list: &str) -> TraceIndex {
+        let mut index = TraceIndex::default();
+        index.extend(list);
+        index
+    }
+
+    // Adds traces, one per line, duplicates are dropped
+    pub fn extend(&mut self, list: &str) {
+        for line in list.lines() {
+            let Some((provider, target)) = line.trim().split_once(':') else {
+                continue;
+            };
+            if target.is_empty() {
+                continue;
+            }
+            self.providers
+                .entry(provider.to_string())
+                .or_default()
+                .push(target.to_string());
+        }
+        for targets in self.providers.values_mut() {
+            targets.sort_unstable();
+            targets.dedup();
+        }
+    }
+
+    pub fn has_provider(&self, provider: &str) -> bool {
+        self.providers.contains_key(provider)
+    }
+
+    pub fn len(&self) -> usize {
+        self.providers.values().map(|targets| targets.len()).sum()
+    }
+
+    fn targets(&self, provider: &str) -> &[String] {
+        self.providers
+            .get(provider)
+            .map(|targets| targets.as_slice())
+            .unwrap_or_default()
+    }
+
+    // All traces of provider starting with prefix
+    pub fn with_prefix(&self, provider: &str, prefix: &str) -> &[String] {
+        let targets = self.targets(provider);
+        let start = targets.partition_point(|t| t.as_str() < prefix);
+        let len = targets[start..].partition_point(|t| t.starts_with(prefix));
+        &targets[start..start + len]
+    }
+
+    // Distinct tokens following the last ':' of query, e.g. for "sched:sched_s" it is
+    // "sched_switch", "sched_stat_runtime", ... Traces under one token are skipped at once, so
+    // the cost depends on number of distinct tokens, not number of traces.
+    pub fn next_tokens(
+        &self,
+        provider: &str,
+        query: &str,
+        max_count: usize,
+    ) -> Vec<TraceToken<'_>> {
+        let targets = self.with_prefix(provider, query);
+        let complete_len = query.rfind(':').map_or(0, |i| i + 1);
+
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < targets.len() && tokens.len() < max_count {
+            let rest = &targets[i][complete_len..];
+            let (token, is_last) = match rest.find(':') {
+                Some(end) => (&rest[..end], false),
+                None => (rest, true),
+            };
+            tokens.push(TraceToken { token, is_last });
+
+            // The trace itself, or all traces under the token, e.g. sched:*. Traces like
+            // accept4 or foo.isra.0 sort between accept and accept:*, so they are not skipped.
+            i += match is_last {
+                true => 1,
+                false => {
+                    let skip = format!("{}:", &targets[i][..complete_len + token.len()]);
+                    targets[i..].partition_point(|t| t.starts_with(&skip))
+                }
+            };
+        }
+        tokens
+    }
+
+    // Traces matching pattern with * and ?, literal part before the first wildcard is searched
+    // as prefix. Traces without the longest literal part of the rest are dropped before
+    // matching, which helps with patterns starting with *. Matches are produced lazily, so the
+    // caller decides when there are enough.
+    pub fn wildcard<'a>(
+        &'a self,
+        provider: &str,
+        pattern: &'a str,
+    ) -> impl Iterator<Item = &'a str> + 'a {
+        let literal_len = pattern.find(['*', '?']).unwrap_or(pattern.len());
+        let required = pattern[literal_len..]
+            .split(['*', '?'])
+            .max_by_key(|part| part.len())
+            .unwrap_or_default();
+        self.with_prefix(provider, &pattern[..literal_len])
+            .iter()
+            .filter(move |t| t[literal_len..].contains(required) && glob_match(pattern, t))
+            .map(|t| t.as_str())
+    }
+
+    // Distinct tokens after complete part of query (up to the last ':') containing pattern as
+    // subsequence, best first, ties in sorted order
+    pub fn fuzzy(
+        &self,
+        provider: &str,
+        complete: &str,
+        pattern: &str,
+        max_count: usize,
+    ) -> Vec<(&str, i32)> {
+        let mut matches: Vec<(&str, i32)> = Vec::new();
+        for target in self.with_prefix(provider, complete) {
+            let rest = &target[complete.len()..];
+            let token = rest.split(':').next().unwrap_or_default();
+            if matches.last().is_some_and(|(last, _)| *last == token) {
+                continue;
+            }
+            if let Some(score) = fuzzy_score(pattern, token) {
+                matches.push((token, score));
+            }
+        }
+        matches.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        matches.dedup_by(|a, b| a.0 == b.0);
+        matches.truncate(max_count);
+        matches
+    }
+}
+
+// Matched on bytes, ? and backtracking after * move by whole characters
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let (p, t) = (pattern.as_bytes(), text.as_bytes());
+    let next_char = |i: usize| -> usize {
+        let mut next = i + 1;
+        while !text.is_char_boundary(next) {
+            next += 1;
+        }
+        next
+    };
+    let (mut pi, mut ti) = (0, 0);
+    // Position after the last * and text position it was matched at
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && p[pi] == b'?' {
+            pi += 1;
+            ti = next_char(ti);
+        } else if pi < p.len() && p[pi] == t[ti] {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi + 1, ti));
+            pi += 1;
+        } else if let Some((star_pi, star_ti)) = star {
+            pi = star_pi;
+            ti = next_char(star_ti);
+            star = Some((star_pi, ti));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+// Score of query matched as subsequence of text, None when it does not match. Consecutive
+// characters and matches at start of words (after _ or :) score more, long texts a bit less.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let text = text.as_bytes();
+    let mut score = 0;
+    let mut ti = 0;
+    let mut prev_match: Option<usize> = None;
+    for q in query.bytes() {
+        let q = q.to_ascii_lowercase();
+        let pos = ti
+            + text[ti..]
+                .iter()
+                .position(|c| c.to_ascii_lowercase() == q)?;
+
+        score += 1;
+        if prev_match.is_some_and(|prev| prev + 1 == pos) {
+            score += 5;
+        }
+        if pos == 0 || matches!(text[pos - 1], b'_' | b':' | b'.') {
+            score += 3;
+        }
+        prev_match = Some(pos);
+        ti = pos + 1;
+    }
+    Some(score * 16 - text.len() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACES: &str = "\
+kprobe:vfs_read
+kprobe:vfs_readv
+kprobe:vfs_write
+kprobe:tcp_retransmit_skb
+kprobe:tcp_sendmsg
+tracepoint:sched:sched_switch
+tracepoint:sched:sched_wakeup
+tracepoint:sched_ext:sched_ext_dump
+tracepoint:syscalls:sys_enter_read
+fentry:vmlinux:vfs_read
+fentry:xfs:xfs_read
+";
+
+    fn wildcard<'a>(index: &'a TraceIndex, provider: &str, pattern: &'a str) -> Vec<&'a str> {
+        index.wildcard(provider, pattern).collect()
+    }
+
+    fn tokens<'a>(index: &'a TraceIndex, provider: &str, query: &str) -> Vec<&'a str> {
+        index
+            .next_tokens(provider, query, 200)
+            .iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_next_tokens() {
+        let index = TraceIndex::from_list(TRACES);
+        assert_eq!(index.len(), 11);
+        assert!(index.has_provider("fentry"));
+        assert!(!index.has_provider("kfunc"));
+
+        assert_eq!(
+            tokens(&index, "kprobe", "vfs_"),
+            ["vfs_read", "vfs_readv", "vfs_write"]
+        );
+        assert_eq!(
+            tokens(&index, "tracepoint", ""),
+            ["sched", "sched_ext", "syscalls"]
+        );
+        assert_eq!(
+            tokens(&index, "tracepoint", "sched:"),
+            ["sched_switch", "sched_wakeup"]
+        );
+        assert_eq!(tokens(&index, "fentry", ""), ["vmlinux", "xfs"]);
+        assert!(tokens(&index, "kprobe", "xyz").is_empty());
+
+        let sched = index.next_tokens("tracepoint", "sch", 200);
+        assert_eq!(
+            sched[0],
+            TraceToken {
+                token: "sched",
+                is_last: false
+            }
+        );
+        assert_eq!(index.next_tokens("kprobe", "", 2).len(), 2);
+    }
+
+    #[test]
+    fn test_next_tokens_sorted_before_separator() {
+        // '4' and '.' sort before ':', such traces are between accept and accept:*
+        let index = TraceIndex::from_list(
+            "kprobe:accept\nkprobe:accept4\nkprobe:foo\nkprobe:foo.isra.0\nkprobe:foo_bar\n\
+             tracepoint:net:net_dev_xmit\ntracepoint:net.x:a\ntracepoint:net4:b\n",
+        );
+        assert_eq!(
+            tokens(&index, "kprobe", ""),
+            ["accept", "accept4", "foo", "foo.isra.0", "foo_bar"]
+        );
+        assert_eq!(tokens(&index, "kprobe", "acc"), ["accept", "accept4"]);
+        assert_eq!(
+            tokens(&index, "kprobe", "foo"),
+            ["foo", "foo.isra.0", "foo_bar"]
+        );
+        // In order of the traces, net:* sorts after net.x:* and net4:*
+        assert_eq!(tokens(&index, "tracepoint", ""), ["net.x", "net4", "net"]);
+    }
+
+    #[test]
+    fn test_wildcard_and_fuzzy() {
+        let index = TraceIndex::from_list(TRACES);
+
+        assert_eq!(
+            wildcard(&index, "kprobe", "vfs_*"),
+            ["vfs_read", "vfs_readv", "vfs_write"]
+        );
+        assert_eq!(wildcard(&index, "kprobe", "*read?"), ["vfs_readv"]);
+        assert_eq!(
+            wildcard(&index, "kprobe", "*_re*"),
+            ["tcp_retransmit_skb", "vfs_read", "vfs_readv"]
+        );
+        assert_eq!(
+            wildcard(&index, "tracepoint", "sched*:*"),
+            [
+                "sched:sched_switch",
+                "sched:sched_wakeup",
+                "sched_ext:sched_ext_dump"
+            ]
+        );
+
+        let fuzzy = index.fuzzy("kprobe", "", "retrans", 200);
+        assert_eq!(fuzzy.len(), 1);
+        assert_eq!(fuzzy[0].0, "tcp_retransmit_skb");
+
+        // Match at start of words is preferred
+        let fuzzy = index.fuzzy("kprobe", "", "vr", 200);
+        assert_eq!(fuzzy[0].0, "vfs_read");
+
+        // Only the next token is matched
+        let fuzzy = index.fuzzy("tracepoint", "", "sext", 200);
+        let tokens: Vec<&str> = fuzzy.iter().map(|(token, _)| *token).collect();
+        assert_eq!(tokens, ["sched_ext"]);
+        let fuzzy = index.fuzzy("tracepoint", "sched:", "swch", 200);
+        assert_eq!(fuzzy[0].0, "sched_switch");
+
+        assert!(glob_match("a*b?c", "axxbyc"));
+        assert!(!glob_match("a*b?c", "axxbc"));
+        assert!(glob_match("ž?u*ť", "žluťoučký kůň ť"));
+        assert!(glob_match("*ů?", "kůň"));
+        assert!(!glob_match("?", "ůň"));
+    }
+
+    #[test]
+    fn test_large_index() {
+        let mut list = String::new();
+        for i in 0..100_000 {
+            list.push_str(&format!("kprobe:func_{:06}\n", i));
+        }
+        let index = TraceIndex::from_list(&list);
+        assert_eq!(index.len(), 100_000);
+
+        assert_eq!(index.next_tokens("kprobe", "func_0999", 200).len(), 100);
+        assert_eq!(index.next_tokens("kprobe", "", 200).len(), 200);
+        assert_eq!(wildcard(&index, "kprobe", "func_09999?").len(), 10);
+        assert_eq!(index.wildcard("kprobe", "*_0999*").count(), 100);
+    }
+}
@@ -0,0 +1,328 @@
+// Available traces in format of `bpftrace -l`, read directly from kernel interfaces, so it works
+// without root:
+//  - kprobes from available_filter_functions, or /proc/kallsyms when tracefs is not readable
+//  - fentry functions from BTF of vmlinux and modules, or from their on-disk BTF index
+//  - iterators from bpf_iter__<name> context structs
+//  - raw tracepoints from btf_trace_<name> typedefs
+//  - tracepoints from tracefs events/ directory
+//  - software and hardware events from the table below, as bpftrace has them built in
+// For BTF of another kernel ("btfSource" setting), kprobes are functions of that BTF and there
+// are no tracepoints, as tracefs and kallsyms belong to the running kernel.
+use std::fs;
+use std::path::Path;
+use std::time::Instant;
+
+use std::cell::OnceCell;
+use std::sync::Arc;
+
+use btf_rs::Btf;
+
+use crate::btf_cache_mod::{self, first_module_type_id, BtfIndex};
+use crate::btf_mod::{
+    btf_func_names, btf_raw_tracepoint_names, btf_setup_module, btf_struct_names,
+};
+use crate::btf_source_mod::BtfSource;
+use crate::log_dbg;
+use crate::log_mod::{self, COMPL};
+use crate::tracepoint::TRACEFS_DIRS;
+
+pub const SOFTWARE_EVENTS: &[&str] = &[
+    "alignment-faults",
+    "bpf-output",
+    "context-switches",
+    "cpu-clock",
+    "cpu-migrations",
+    "dummy",
+    "emulation-faults",
+    "major-faults",
+    "minor-faults",
+    "page-faults",
+    "task-clock",
+];
+
+pub const HARDWARE_EVENTS: &[&str] = &[
+    "backend-stalls",
+    "branch-instructions",
+    "branch-misses",
+    "bus-cycles",
+    "cache-misses",
+    "cache-references",
+    "cpu-cycles",
+    "frontend-stalls",
+    "instructions",
+    "ref-cycles",
+];
+
+fn kprobe_line(name: &str, module: Option<&str>) -> String {
+    match module {
+        Some(module) => format!("kprobe:{}:{}\n", module, name),
+        None => format!("kprobe:{}\n", name),
+    }
+}
+
+fn strip_module(field: Option<&str>) -> Option<&str> {
+    field.and_then(|m| m.strip_prefix('[')?.strip_suffix(']'))
+}
+
+// Lines are "function" or "function [module]"
+pub fn parse_filter_functions(text: &str) -> String {
+    let mut traces = String::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let Some(name) = fields.next() else {
+            continue;
+        };
+        traces.push_str(&kprobe_line(name, strip_module(fields.next())));
+    }
+    traces
+}
+
+// Lines are "address type name [module]", only text symbols are functions
+pub fn parse_kallsyms(text: &str) -> String {
+    let mut traces = String::new();
+    for line in text.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(_addr), Some(kind), Some(name)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // __pfx_ are padding before functions, .cold parts can't be probed on their own
+        if !kind.eq_ignore_ascii_case("t") || name.starts_with("__pfx_") || name.contains(".cold") {
+            continue;
+        }
+        traces.push_str(&kprobe_line(name, strip_module(fields.next())));
+    }
+    traces
+}
+
+fn kprobes_list() -> String {
+    for dir in TRACEFS_DIRS {
+        let path = Path::new(dir).join("available_filter_functions");
+        if let Ok(text) = fs::read_to_string(path) {
+            if !text.is_empty() {
+                return parse_filter_functions(&text);
+            }
+        }
+    }
+    fs::read_to_string("/proc/kallsyms")
+        .map(|text| parse_kallsyms(&text))
+        .unwrap_or_default()
+}
+
+// Categories and events are directories, other entries are control files
+fn tracepoints_list() -> String {
+    let mut traces = String::new();
+    let subdirs = |dir: &Path| -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().is_dir())
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    };
+
+    for dir in TRACEFS_DIRS {
+        let events = Path::new(dir).join("events");
+        for category in subdirs(&events) {
+            for event in subdirs(&events.join(&category)) {
+                traces.push_str(&format!("tracepoint:{}:{}\n", category, event));
+            }
+        }
+        if !traces.is_empty() {
+            break;
+        }
+    }
+    traces
+}
+
+fn push_module_traces(
+    module: &str,
+    funcs: &[&str],
+    structs: &[&str],
+    raw_tracepoints: &[&str],
+    with_kprobes: bool,
+    traces: &mut String,
+) {
+    for func in funcs {
+        if with_kprobes {
+            let module = Some(module).filter(|m| *m != "vmlinux");
+            traces.push_str(&kprobe_line(func, module));
+        }
+        traces.push_str(&format!("fentry:{}:{}\n", module, func));
+    }
+    // Iterator has context struct bpf_iter__<name>, functions like bpf_iter_num_new are
+    // open-coded iterators for BPF programs, not attach points
+    for iter in structs.iter().filter_map(|s| s.strip_prefix("bpf_iter__")) {
+        traces.push_str(&format!("iter:{}\n", iter));
+    }
+    for name in raw_tracepoints {
+        traces.push_str(&format!("rawtracepoint:{}:{}\n", module, name));
+    }
+}
+
+fn btf_traces(module: &str, btf: &Btf, first_id: u32, with_kprobes: bool, traces: &mut String) {
+    let funcs = btf_func_names(btf, first_id);
+    let structs = btf_struct_names(btf, first_id);
+    let raw_tracepoints = btf_raw_tracepoint_names(btf, first_id);
+    push_module_traces(
+        module,
+        &funcs.iter().map(String::as_str).collect::<Vec<_>>(),
+        &structs.iter().map(String::as_str).collect::<Vec<_>>(),
+        &raw_tracepoints
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+        with_kprobes,
+        traces,
+    );
+}
+
+fn index_traces(module: &str, index: &BtfIndex, with_kprobes: bool, traces: &mut String) {
+    push_module_traces(
+        module,
+        &index.func_names().collect::<Vec<_>>(),
+        &index.struct_names().collect::<Vec<_>>(),
+        &index.raw_tracepoint_names().collect::<Vec<_>>(),
+        with_kprobes,
+        traces,
+    );
+}
+
+// Names are read from BTF index when there is one on disk. Otherwise BTF is parsed just for the
+// names, without keeping module BTF in the registry, and the index is built in background for
+// the next start.
+fn btf_traces_list(source: &BtfSource) -> String {
+    let mut traces = String::new();
+    let with_kprobes = !source.is_running();
+    let base: OnceCell<Option<Arc<Btf>>> = OnceCell::new();
+    let mut missing = Vec::new();
+
+    let modules = source.module_names();
+    let first_id = modules
+        .get(1)
+        .map_or(1, |first_module| first_module_type_id(first_module));
+    for module in modules.iter() {
+        if let Some(index) = btf_cache_mod::load(module) {
+            index_traces(module, &index, with_kprobes, &mut traces);
+            continue;
+        }
+
+        let Some(base) = base.get_or_init(|| btf_setup_module("vmlinux")) else {
+            return traces;
+        };
+        if module == "vmlinux" {
+            btf_traces(module, base, 1, with_kprobes, &mut traces);
+        } else if let Some(bytes) = source.module_bytes(module) {
+            if let Ok(btf) = Btf::from_split_bytes(&bytes, base) {
+                btf_traces(module, &btf, first_id, with_kprobes, &mut traces);
+            }
+        }
+        missing.push(module.clone());
+    }
+
+    btf_cache_mod::build_in_background(missing);
+    traces
+}
+
+fn events_list(provider: &str, events: &[&str]) -> String {
+    events
+        .iter()
+        .map(|event| format!("{}:{}\n", provider, event))
+        .collect()
+}
+
+pub fn native_traces_list(source: &BtfSource) -> String {
+    let start = Instant::now();
+
+    let mut traces = String::new();
+    if source.is_running() {
+        traces.push_str(&kprobes_list());
+        traces.push_str(&tracepoints_list());
+    }
+    traces.push_str(&btf_traces_list(source));
+    traces.push_str(&events_list("software", SOFTWARE_EVENTS));
+    traces.push_str(&events_list("hardware", HARDWARE_EVENTS));
+
+    log_dbg!(
+        COMPL,
+        "Listed {} traces from kernel after {:?}",
+        traces.lines().count(),
+        start.elapsed()
+    );
+    traces
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_lists() {
+        let filter_functions = "vfs_read\nrt2800_link_tuner [rt2800lib]\n";
+        assert_eq!(
+            parse_filter_functions(filter_functions),
+            "kprobe:vfs_read\nkprobe:rt2800lib:rt2800_link_tuner\n"
+        );
+
+        let kallsyms = "\
+ffffffff81000000 T _stext
+ffffffff81001000 t __pfx_vfs_read
+ffffffff81001010 t vfs_read
+ffffffff81001100 t tcp_sendmsg.cold
+ffffffff82000000 D jiffies
+ffffffffc0a01000 t rt2800_link_tuner\t[rt2800lib]
+";
+        assert_eq!(
+            parse_kallsyms(kallsyms),
+            "kprobe:_stext\nkprobe:vfs_read\nkprobe:rt2800lib:rt2800_link_tuner\n"
+        );
+    }
+
+    #[test]
+    fn test_native_traces_list() {
+        let traces = native_traces_list(&BtfSource::Running);
+        assert!(traces.contains("software:cpu-clock\n"));
+        assert!(traces.contains("hardware:cpu-cycles\n"));
+
+        if Path::new("/sys/kernel/btf/vmlinux").exists() {
+            assert!(traces.contains("fentry:vmlinux:vfs_read\n"));
+            assert!(traces.contains("rawtracepoint:vmlinux:sched_switch\n"));
+            assert!(traces.contains("iter:task\n"));
+            assert!(traces.contains("iter:bpf_map_elem\n"));
+            // Open-coded iterator functions, e.g. bpf_iter_num_new, are not iterator probes
+            assert!(!traces.contains("iter:num_new\n"));
+        }
+        if fs::read_to_string("/proc/kallsyms").is_ok_and(|k| k.contains(" vfs_read\n")) {
+            assert!(traces.contains("kprobe:vfs_read\n"));
+        }
+    }
+
+    #[test]
+    fn test_btf_kprobes() {
+        let Some(btf) = btf_setup_module("vmlinux") else {
+            return;
+        };
+
+        let mut traces = String::new();
+        btf_traces("vmlinux", &btf, 1, true, &mut traces);
+        assert!(traces.contains("kprobe:vfs_read\n"));
+        assert!(traces.contains("fentry:vmlinux:vfs_read\n"));
+        assert!(traces.contains("iter:task\n"));
+        assert!(!traces.contains("iter:num_new\n"));
+
+        // Split BTF of module starts after types of vmlinux, which are not repeated
+        let Some(btf) = btf_setup_module("mac80211") else {
+            return;
+        };
+        let mut traces = String::new();
+        let first_id = first_module_type_id("mac80211");
+        btf_traces("mac80211", &btf, first_id, true, &mut traces);
+        assert!(traces.contains("fentry:mac80211:ieee80211_register_hw\n"));
+        assert!(!traces.contains("vfs_read"));
+    }
+}
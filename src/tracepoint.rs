@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, LazyLock, Mutex};
+
+use btf_rs::Btf;
+use tree_sitter::Node;
+
+use crate::btf_cache_mod::first_module_type_id;
+use crate::btf_mod::{
+    btf_find_event_struct_id, btf_iterate_over_type_chain, btf_registry, btf_resolve_struct_layout,
+    btf_setup_module, MemberLayout, ResolvedVariable, StructLayout,
+};
+use crate::check_mod::{encode_diag, node_text, SEVERITY_WARNING};
+use crate::log_dbg;
+use crate::log_mod::{self, COMPL};
+use crate::parser;
+use crate::var_types::field_expression_base;
+
+pub const TRACEFS_DIRS: &[&str] = &["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+// Layout of args keyed by BTF source and "category:event". Unknown tracepoints are not kept, the
+// module could be loaded later.
+static TRACEPOINT_LAYOUTS: LazyLock<Mutex<HashMap<String, StructLayout>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Module and id of raw struct of event in BTF, keyed the same way
+static EVENT_STRUCTS: LazyLock<Mutex<HashMap<String, (String, u32)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Category and event of tracepoint probe without wildcards, e.g. tracepoint:sched:sched_switch
+pub fn parse_tracepoint(probe: &str) -> Option<(String, String)> {
+    let mut parts = probe.split(':');
+    if !matches!(parts.next(), Some("tracepoint") | Some("t")) {
+        return None;
+    }
+    let category = parts.next()?;
+    let event = parts.next()?;
+    if parts.next().is_some() || probe.contains(['*', '?', '{']) {
+        return None;
+    }
+    Some((category.to_string(), event.to_string()))
+}
+
+// Common fields of all events are not available as args
+pub fn is_common_field(name: &str) -> bool {
+    name.starts_with("common_") || name == "ent" || name == "__data"
+}
+
+// Type and name of field declaration, e.g. ("char", "prev_comm[16]") for "char prev_comm[16]"
+fn parse_field_decl(decl: &str) -> Option<(String, String)> {
+    let decl = decl.trim().trim_end_matches(';');
+    let name_end = decl
+        .find('[')
+        .filter(|_| decl.ends_with(']'))
+        .unwrap_or(decl.len());
+    let name_start = decl[..name_end]
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map(|i| i + 1)
+        .unwrap_or_default();
+    let type_str = decl[..name_start].trim();
+    if name_start == name_end || type_str.is_empty() {
+        return None;
+    }
+    Some((type_str.to_string(), decl[name_start..].to_string()))
+}
+
+// Name of field without array dimensions
+pub fn field_name(member: &MemberLayout) -> &str {
+    member.name.split('[').next().unwrap_or_default()
+}
+
+// Parse format file of event, e.g. /sys/kernel/tracing/events/sched/sched_switch/format
+//  name: sched_switch
+//  ID: 316
+//  format:
+//          field:unsigned short common_type;       offset:0;       size:2; signed:0;
+//          ...
+//          field:char prev_comm[16];       offset:8;       size:16;        signed:0;
+pub fn parse_format(format: &str) -> Option<StructLayout> {
+    let mut members = Vec::new();
+    let mut size = 0;
+
+    for line in format.lines() {
+        let mut decl = None;
+        let mut offset = None;
+        let mut field_size = None;
+        for item in line.split(';') {
+            let Some((key, value)) = item.trim().split_once(':') else {
+                continue;
+            };
+            match key {
+                "field" => decl = parse_field_decl(value),
+                "offset" => offset = value.trim().parse::<u32>().ok(),
+                "size" => field_size = value.trim().parse::<usize>().ok(),
+                _ => {}
+            }
+        }
+        let (Some((type_str, name)), Some(offset), Some(field_size)) = (decl, offset, field_size)
+        else {
+            continue;
+        };
+
+        size = size.max(offset as usize + field_size);
+        members.push(MemberLayout {
+            name,
+            type_str,
+            bit_offset: offset * 8,
+            bitfield_size: None,
+            size: field_size,
+            from_anonymous: None,
+        });
+    }
+
+    if members.is_empty() {
+        return None;
+    }
+    Some(StructLayout {
+        name: "args".to_string(),
+        is_union: false,
+        size,
+        members,
+    })
+}
+
+fn read_format(category: &str, event: &str) -> Option<String> {
+    TRACEFS_DIRS.iter().find_map(|dir| {
+        fs::read_to_string(format!("{}/events/{}/{}/format", dir, category, event)).ok()
+    })
+}
+
+fn event_key(category: &str, event: &str) -> String {
+    format!(
+        "{} {}:{}",
+        btf_registry().source().describe(),
+        category,
+        event
+    )
+}
+
+// Raw struct of event in BTF. Tracepoints of modules are looked for in the module of the same
+// name as the category, e.g. kvm:kvm_exit, then in vmlinux.
+fn event_struct(category: &str, event: &str) -> Option<(Arc<Btf>, u32)> {
+    let key = event_key(category, event);
+    let found = EVENT_STRUCTS.lock().unwrap().get(&key).cloned();
+    if let Some((module, id)) = found {
+        return Some((btf_setup_module(&module)?, id));
+    }
+
+    for module in [category, "vmlinux"] {
+        let Some(btf) = btf_setup_module(module) else {
+            continue;
+        };
+        let first_id = first_module_type_id(module);
+        if let Some(id) = btf_find_event_struct_id(&btf, event, first_id) {
+            let value = (module.to_string(), id);
+            EVENT_STRUCTS.lock().unwrap().insert(key, value);
+            return Some((btf, id));
+        }
+    }
+    None
+}
+
+// Raw struct of event in BTF as fields of format file: without ent header and __data array and
+// with __data_loc_<name> members named <name>
+fn event_struct_fields(mut layout: StructLayout) -> StructLayout {
+    layout
+        .members
+        .retain(|m| !["ent", "__data"].contains(&field_name(m)));
+    for member in &mut layout.members {
+        if let Some(name) = member.name.strip_prefix("__data_loc_") {
+            member.name = name.to_string();
+        }
+    }
+    layout
+}
+
+// Fields of tracepoint from tracefs, BTF is used when tracefs is not readable or when BTF is of
+// another kernel
+pub fn tracepoint_args_layout(probe: &str) -> Option<StructLayout> {
+    let (category, event) = parse_tracepoint(probe)?;
+    let key = event_key(&category, &event);
+    if let Some(layout) = TRACEPOINT_LAYOUTS.lock().unwrap().get(&key) {
+        return Some(layout.clone());
+    }
+
+    let format = read_format(&category, &event).filter(|_| btf_registry().source().is_running());
+    let layout = match format.and_then(|f| parse_format(&f)) {
+        Some(layout) => layout,
+        None => {
+            log_dbg!(COMPL, "No format file for tracepoint {}, trying BTF", key);
+            let (btf, id) = event_struct(&category, &event)?;
+            event_struct_fields(btf_resolve_struct_layout(&btf, id)?)
+        }
+    };
+    TRACEPOINT_LAYOUTS
+        .lock()
+        .unwrap()
+        .insert(key, layout.clone());
+    Some(layout)
+}
+
+// Nested members, e.g. ".prev_comm" or ".skaddr->sk_socket", need raw struct of the event in BTF
+pub fn resolve_tracepoint_chain(probe: &str, chain: &str) -> Option<ResolvedVariable> {
+    let (category, event) = parse_tracepoint(probe)?;
+    let (btf, id) = event_struct(&category, &event)?;
+    btf_iterate_over_type_chain(&btf, id, false, chain)
+}
+
+// Check args.field on all tracepoints of the action, nested members only if raw struct is in BTF
+fn check_args_expression(
+    node: &Node,
+    text: &str,
+    probes: &[String],
+    diagnostics: &mut json::JsonValue,
+) {
+    let base = field_expression_base(node);
+    if base.kind() != "args_keyword" {
+        return;
+    }
+
+    // From innermost access to the outermost
+    let mut levels = Vec::new();
+    let mut level = base.parent();
+    while let Some(l) = level.filter(|l| l.kind() == "field_expression") {
+        levels.push(l);
+        if l == *node {
+            break;
+        }
+        level = l.parent();
+    }
+    let Some(first) = levels.first() else {
+        return;
+    };
+    let Some(field) = first.child_by_field_name("field") else {
+        return;
+    };
+    let field_name = node_text(&field, text);
+
+    for probe in probes {
+        let Some(layout) = tracepoint_args_layout(probe) else {
+            continue;
+        };
+        if !layout
+            .members
+            .iter()
+            .any(|m| self::field_name(m) == field_name)
+        {
+            let message = format!("{} has no field {}", probe, field_name);
+            let _ = diagnostics.push(encode_diag(&field, text, SEVERITY_WARNING, message));
+            return;
+        }
+
+        for level in levels.iter().skip(1) {
+            let chain: String = text[base.end_byte()..level.end_byte()]
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            let Some(field) = level.child_by_field_name("field") else {
+                return;
+            };
+            let field_name = node_text(&field, text);
+            let parent_chain = &chain[..chain.len() - field_name.len()];
+            if chain.contains('[') || resolve_tracepoint_chain(probe, &chain).is_some() {
+                continue;
+            }
+            let Some(parent) = resolve_tracepoint_chain(probe, parent_chain) else {
+                break;
+            };
+
+            let parent_type = parent.var.type_vec.join(" ").replace(" *", "");
+            let message = format!("{} has no member {}", parent_type.trim(), field_name);
+            let _ = diagnostics.push(encode_diag(&field, text, SEVERITY_WARNING, message));
+            return;
+        }
+    }
+}
+
+fn check_node(node: Node, text: &str, probes: &[String], diagnostics: &mut json::JsonValue) {
+    let is_outermost = node.kind() == "field_expression"
+        && node
+            .parent()
+            .is_none_or(|parent| parent.kind() != "field_expression");
+    if is_outermost && !probes.is_empty() {
+        check_args_expression(&node, text, probes, diagnostics);
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == "action" {
+            let probes: Vec<String> = parser::find_probes_for_action(&child, text)
+                .into_iter()
+                .filter(|probe| parse_tracepoint(probe).is_some())
+                .collect();
+            check_node(child, text, &probes, diagnostics);
+        } else {
+            check_node(child, text, probes, diagnostics);
+        }
+    }
+}
+
+// Diagnostics for args of tracepoints
+pub fn check_tree(root: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+    check_node(root, text, &[], &mut diagnostics);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_mod;
+
+    const SCHED_SWITCH_FORMAT: &str = "name: sched_switch
+ID: 316
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char prev_comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t prev_pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:int prev_prio;\toffset:28;\tsize:4;\tsigned:1;
+\tfield:long prev_state;\toffset:32;\tsize:8;\tsigned:1;
+\tfield:__data_loc char[] name;\toffset:40;\tsize:4;\tsigned:0;
+
+print fmt: \"prev_comm=%s prev_pid=%d\", REC->prev_comm, REC->prev_pid
+";
+
+    fn check(text: &str) -> Vec<String> {
+        let tree = check_mod::parse(text).unwrap();
+        check_tree(tree.root_node(), text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let layout = parse_format(SCHED_SWITCH_FORMAT).unwrap();
+        let fields: Vec<(&str, &str, u32, usize)> = layout
+            .members
+            .iter()
+            .filter(|m| !is_common_field(&m.name))
+            .map(|m| (field_name(m), m.type_str.as_str(), m.bit_offset / 8, m.size))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("prev_comm", "char", 8, 16),
+                ("prev_pid", "pid_t", 24, 4),
+                ("prev_prio", "int", 28, 4),
+                ("prev_state", "long", 32, 8),
+                ("name", "__data_loc char[]", 40, 4),
+            ]
+        );
+        assert_eq!(layout.members[4].name, "prev_comm[16]");
+        assert_eq!(
+            parse_field_decl("__data_loc char[] name"),
+            Some(("__data_loc char[]".to_string(), "name".to_string()))
+        );
+        assert_eq!(layout.size, 44);
+
+        assert_eq!(
+            parse_tracepoint("t:sched:sched_switch"),
+            Some(("sched".to_string(), "sched_switch".to_string()))
+        );
+        assert_eq!(parse_tracepoint("tracepoint:sched:*"), None);
+        assert_eq!(parse_tracepoint("kprobe:sched_switch"), None);
+    }
+
+    #[test]
+    fn test_tracepoint_btf() {
+        let var = resolve_tracepoint_chain("tracepoint:sched:sched_switch", ".prev_pid").unwrap();
+        assert_eq!(var.var.type_vec, vec!["pid_t"]);
+
+        // sched_wakeup is defined by DEFINE_EVENT of sched_wakeup_template class
+        let var = resolve_tracepoint_chain("tracepoint:sched:sched_wakeup", ".target_cpu").unwrap();
+        assert_eq!(var.var.type_vec, vec!["int"]);
+        let layout = tracepoint_args_layout("tracepoint:sched:sched_wakeup").unwrap();
+        assert!(layout.members.iter().any(|m| m.name == "prio"));
+        assert!(tracepoint_args_layout("tracepoint:sched:Xblabla713h").is_none());
+
+        // Fields of the raw struct as in format file
+        let (btf, id) = event_struct("sched", "sched_process_exec").unwrap();
+        let layout = event_struct_fields(btf_resolve_struct_layout(&btf, id).unwrap());
+        let names: Vec<&str> = layout.members.iter().map(field_name).collect();
+        assert_eq!(names, vec!["filename", "pid", "old_pid"]);
+
+        let text = "tracepoint:sched:sched_switch { print(args.prev_pid); print(args.no_such); }\nkprobe:f { print(args.no_such); }";
+        assert_eq!(
+            check(text),
+            vec!["tracepoint:sched:sched_switch has no field no_such"]
+        );
+        let tree = check_mod::parse(text).unwrap();
+        let diagnostics = check_tree(tree.root_node(), text);
+        assert_eq!(diagnostics[0]["severity"], check_mod::SEVERITY_WARNING);
+    }
+}
@@ -0,0 +1,391 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Instant, SystemTime};
+
+use btf_rs::Btf;
+
+use crate::btf_mod::{
+    btf_iterate_over_names_chain, btf_resolve_func, ResolvedBtfItem, ResolvedVariable,
+};
+use crate::dwarf_mod::{self, Function};
+use crate::elf_mod::{Elf, UsdtProbe};
+use crate::log_dbg;
+use crate::log_mod::{self, COMPL};
+use crate::proc_mod;
+
+// Function symbols, USDT probes and debug info of user space binary
+pub struct UserBinary {
+    pub functions: Vec<String>,
+    pub usdt: Vec<UsdtProbe>,
+    btf: Option<Btf>,
+    dwarf: HashMap<String, Function>,
+}
+
+// Binary with its modification time, it is reloaded when modified, and time of last use
+struct LoadedBinary {
+    modified: SystemTime,
+    used: Instant,
+    binary: Option<Arc<UserBinary>>,
+}
+
+// Each target process adds its own paths under /proc/<pid>/root, the least recently used
+// binaries are dropped above this count
+const MAX_USER_BINARIES: usize = 64;
+
+static USER_BINARIES: LazyLock<Mutex<HashMap<String, LoadedBinary>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Root of process in path opened through /proc/<pid>/root and the path inside of it
+fn split_process_root(path: &str) -> (&str, &str) {
+    let inner = path
+        .strip_prefix("/proc/")
+        .and_then(|rest| rest.split_once('/'))
+        .and_then(|(_pid, rest)| rest.strip_prefix("root"))
+        .filter(|inner| inner.starts_with('/'));
+    match inner {
+        Some(inner) => (&path[..path.len() - inner.len()], inner),
+        None => ("", path),
+    }
+}
+
+// Separate debug info files, by build ID and by .gnu_debuglink in the same places as gdb looks
+pub fn debug_file_candidates(path: &str, elf: &Elf) -> Vec<String> {
+    let (root, path) = split_process_root(path);
+    let mut candidates = Vec::new();
+    if let Some(build_id) = elf.build_id().filter(|id| id.len() > 2) {
+        candidates.push(format!(
+            "{}/usr/lib/debug/.build-id/{}/{}.debug",
+            root,
+            &build_id[..2],
+            &build_id[2..]
+        ));
+    }
+    if let Some(link) = elf.debuglink() {
+        let dir = path.rsplit_once('/').map_or("", |(dir, _)| dir);
+        for dir in [
+            dir.to_string(),
+            format!("{}/.debug", dir),
+            format!("/usr/lib/debug{}", dir),
+        ] {
+            candidates.push(format!("{}{}/{}", root, dir, link));
+        }
+    }
+    candidates
+}
+
+// DWARF of binary whose debug info was stripped to a separate file, e.g. by distribution
+// debuginfo packages
+fn read_separate_dwarf(path: &str, elf: &Elf) -> HashMap<String, Function> {
+    for candidate in debug_file_candidates(path, elf) {
+        let Ok(data) = fs::read(&candidate) else {
+            continue;
+        };
+        let Some(debug_elf) = Elf::parse(&data) else {
+            continue;
+        };
+        let functions = dwarf_mod::read_functions(&debug_elf);
+        if !functions.is_empty() {
+            log_dbg!(COMPL, "Debug info of {} read from {}", path, candidate);
+            return functions;
+        }
+    }
+    HashMap::new()
+}
+
+fn load_binary(path: &str) -> Option<UserBinary> {
+    let data = fs::read(path).ok()?;
+    let elf = Elf::parse(&data)?;
+
+    let btf = elf
+        .section_data(".BTF")
+        .and_then(|bytes| Btf::from_bytes(bytes).ok());
+    // DWARF is not needed when there is BTF
+    let dwarf = match btf {
+        Some(_) => HashMap::new(),
+        None => {
+            let dwarf = dwarf_mod::read_functions(&elf);
+            if dwarf.is_empty() {
+                read_separate_dwarf(path, &elf)
+            } else {
+                dwarf
+            }
+        }
+    };
+
+    let binary = UserBinary {
+        functions: elf.function_symbols(),
+        usdt: elf.usdt_probes(),
+        btf,
+        dwarf,
+    };
+    log_dbg!(
+        COMPL,
+        "Loaded {} with {} functions, {} USDT probes, BTF: {}, DWARF for {} functions",
+        path,
+        binary.functions.len(),
+        binary.usdt.len(),
+        binary.btf.is_some(),
+        binary.dwarf.len()
+    );
+    Some(binary)
+}
+
+// Makes room for new binary, binaries of exited processes are not accessible any more
+fn evict_binaries(binaries: &mut HashMap<String, LoadedBinary>, max_count: usize) {
+    binaries.retain(|path, _| Path::new(path).exists());
+    while binaries.len() > max_count {
+        let Some(oldest) = binaries
+            .iter()
+            .min_by_key(|(_, loaded)| loaded.used)
+            .map(|(path, _)| path.clone())
+        else {
+            break;
+        };
+        binaries.remove(&oldest);
+    }
+}
+
+pub fn user_binary(path: &str) -> Option<Arc<UserBinary>> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+
+    if let Some(loaded) = USER_BINARIES.lock().unwrap().get_mut(path) {
+        if loaded.modified == modified {
+            loaded.used = Instant::now();
+            return loaded.binary.clone();
+        }
+    }
+
+    // Large binaries take a while to parse, other requests must not wait for the lock meanwhile
+    let binary = load_binary(path).map(Arc::new);
+    let mut binaries = USER_BINARIES.lock().unwrap();
+    evict_binaries(&mut binaries, MAX_USER_BINARIES - 1);
+    binaries.insert(
+        path.to_string(),
+        LoadedBinary {
+            modified,
+            used: Instant::now(),
+            binary: binary.clone(),
+        },
+    );
+    binary
+}
+
+// Binary as seen by target process, it can be also given by name of library mapped by it
+pub fn process_binary(path: &str, pid: Option<u32>) -> Option<Arc<UserBinary>> {
+    let path = match pid {
+        Some(pid) if !path.contains('/') => proc_mod::find_mapped_object(pid, path)?,
+        _ => path.to_string(),
+    };
+    user_binary(&proc_mod::process_path(pid, &path))
+}
+
+impl UserBinary {
+    // Arguments of function, from BTF when the binary has it, otherwise from DWARF
+    pub fn resolve_func(&self, func: &str, need_retval: bool) -> Option<ResolvedBtfItem> {
+        if let Some(btf) = &self.btf {
+            return btf_resolve_func(btf, func, need_retval);
+        }
+
+        let function = self.dwarf.get(func)?;
+        let type_vec = |type_str: &str| -> Vec<String> {
+            type_str.split_whitespace().map(|t| t.to_string()).collect()
+        };
+        let mut item = ResolvedBtfItem {
+            name: function.name.clone(),
+            type_vec: vec!["func".to_string()],
+            ..Default::default()
+        };
+        for param in function.params.iter() {
+            item.children_vec.push(ResolvedBtfItem {
+                name: param.name.clone(),
+                type_vec: type_vec(&param.type_str),
+                ..Default::default()
+            });
+        }
+        if need_retval && function.ret_type != "void" {
+            item.children_vec.push(ResolvedBtfItem {
+                name: "retval".to_string(),
+                type_vec: type_vec(&function.ret_type),
+                ..Default::default()
+            });
+        }
+        Some(item)
+    }
+
+    // Member accesses on arguments, e.g. args.ctx->buf, only for binaries with BTF
+    pub fn resolve_args_chain(
+        &self,
+        func: &ResolvedBtfItem,
+        chain: &str,
+    ) -> Option<ResolvedVariable> {
+        btf_iterate_over_names_chain(self.btf.as_ref()?, func, chain)
+    }
+}
+
+// Binary, function and if it is uretprobe, e.g. uprobe:/bin/bash:readline
+pub fn parse_uprobe(probe: &str) -> Option<(String, String, bool)> {
+    let (provider, target) = probe.split_once(':')?;
+    let is_ret = match provider {
+        "uprobe" | "u" => false,
+        "uretprobe" | "ur" => true,
+        _ => return None,
+    };
+    let (path, func) = target.rsplit_once(':')?;
+    let func = func.split('+').next().unwrap_or_default();
+    if path.is_empty() || func.is_empty() || probe.contains(['*', '?']) {
+        return None;
+    }
+    Some((path.to_string(), func.to_string(), is_ret))
+}
+
+// Directories, executables and shared libraries for partially typed path, with flag if it is
+// a directory. Relative paths are relative to directory of the script, absolute ones to root of
+// the target process.
+pub fn path_completions(
+    partial: &str,
+    script_dir: Option<&Path>,
+    pid: Option<u32>,
+) -> Vec<(String, bool)> {
+    let (dir, prefix) = match partial.rfind('/') {
+        Some(i) => (&partial[..=i], &partial[i + 1..]),
+        None => ("", partial),
+    };
+    let dir = match script_dir {
+        Some(script_dir) if !dir.starts_with('/') => script_dir.join(dir),
+        _ => Path::new(&proc_mod::process_path(pid, dir)).to_path_buf(),
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".").to_path_buf()
+    } else {
+        dir
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut paths = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+        // Symlinks are followed, e.g. /lib -> /usr/lib
+        let Ok(metadata) = fs::metadata(entry.path()) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            paths.push((name, true));
+        } else if metadata.permissions().mode() & 0o111 != 0 || name.contains(".so") {
+            paths.push((name, false));
+        }
+    }
+    paths.sort();
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf_mod::tests::build_elf;
+
+    #[test]
+    fn test_parse_uprobe() {
+        assert_eq!(
+            parse_uprobe("uprobe:/bin/bash:readline"),
+            Some(("/bin/bash".to_string(), "readline".to_string(), false))
+        );
+        assert_eq!(
+            parse_uprobe("ur:./a.out:main+16"),
+            Some(("./a.out".to_string(), "main".to_string(), true))
+        );
+        assert_eq!(parse_uprobe("uprobe:/bin/bash:read*"), None);
+        assert_eq!(parse_uprobe("kprobe:vfs_read"), None);
+    }
+
+    #[test]
+    fn test_evict_binaries() {
+        let now = Instant::now();
+        let mut binaries = HashMap::new();
+        for (i, path) in ["/proc/self/exe", "/nonexistent/app", "/proc/self/maps", "/"]
+            .iter()
+            .enumerate()
+        {
+            let loaded = LoadedBinary {
+                modified: SystemTime::UNIX_EPOCH,
+                used: now + std::time::Duration::from_secs(i as u64),
+                binary: None,
+            };
+            binaries.insert(path.to_string(), loaded);
+        }
+
+        evict_binaries(&mut binaries, 2);
+        let mut paths: Vec<&String> = binaries.keys().collect();
+        paths.sort();
+        assert_eq!(paths, vec!["/", "/proc/self/maps"]);
+    }
+
+    #[test]
+    fn test_debug_file_candidates() {
+        assert_eq!(
+            split_process_root("/proc/42/root/usr/bin/app"),
+            ("/proc/42/root", "/usr/bin/app")
+        );
+        assert_eq!(split_process_root("/proc/42/exe"), ("", "/proc/42/exe"));
+
+        let mut note = Vec::new();
+        for field in [4u32, 2, 3] {
+            note.extend_from_slice(&field.to_le_bytes());
+        }
+        note.extend_from_slice(b"GNU\0\x12\x34");
+        let data = build_elf(&[
+            (".note.gnu.build-id", &note),
+            (".gnu_debuglink", b"app.debug\0"),
+        ]);
+        let elf = Elf::parse(&data).unwrap();
+        assert_eq!(
+            debug_file_candidates("/proc/42/root/usr/bin/app", &elf),
+            vec![
+                "/proc/42/root/usr/lib/debug/.build-id/12/34.debug",
+                "/proc/42/root/usr/bin/app.debug",
+                "/proc/42/root/usr/bin/.debug/app.debug",
+                "/proc/42/root/usr/lib/debug/usr/bin/app.debug",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_binary() {
+        let exe = fs::read_link("/proc/self/exe").unwrap();
+        let binary = user_binary(exe.to_str().unwrap()).unwrap();
+        assert!(binary.functions.contains(&"main".to_string()));
+
+        let func = binary.resolve_func("read_str", true).unwrap();
+        let args: Vec<&str> = func.children_vec.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(args, vec!["table", "offset", "retval"]);
+
+        let dir = exe.parent().unwrap().to_str().unwrap().to_string();
+        let name = exe.file_name().unwrap().to_str().unwrap();
+        let paths = path_completions(&format!("{}/{}", dir, &name[..4]), None, None);
+        assert!(paths.contains(&(name.to_string(), false)));
+        assert!(path_completions("/nonexistent/dir/", None, None).is_empty());
+
+        // Relative to the script directory, not to the working directory of the server
+        let script_dir = exe.parent();
+        let paths = path_completions(&name[..4], script_dir, None);
+        assert!(paths.contains(&(name.to_string(), false)));
+        let parent = exe.parent().unwrap().file_name().unwrap().to_str().unwrap();
+        let paths = path_completions(&format!("../{}/{}", parent, &name[..4]), script_dir, None);
+        assert!(paths.contains(&(name.to_string(), false)));
+
+        // Under root of the target process
+        let pid = Some(std::process::id());
+        let paths = path_completions(&format!("{}/{}", dir, &name[..4]), None, pid);
+        assert!(paths.contains(&(name.to_string(), false)));
+
+        assert!(process_binary(exe.to_str().unwrap(), pid).is_some());
+        assert!(process_binary(name, pid).is_some());
+    }
+}
@@ -0,0 +1,220 @@
+use tree_sitter::Node;
+
+use crate::check_mod::{encode_diag, SEVERITY_ERROR};
+use crate::elf_mod::UsdtProbe;
+use crate::parser;
+use crate::proc_mod;
+use crate::uprobe::process_binary;
+
+// Binary, provider and name of USDT probe, provider can be omitted, e.g. usdt:/bin/app:start
+pub fn parse_usdt(probe: &str) -> Option<(String, Option<String>, String)> {
+    let mut parts = probe.split(':');
+    if !matches!(parts.next(), Some("usdt") | Some("U")) || probe.contains(['*', '?']) {
+        return None;
+    }
+    let parts: Vec<&str> = parts.collect();
+    match parts[..] {
+        [path, name] => Some((path.to_string(), None, name.to_string())),
+        [path, provider, name] => Some((
+            path.to_string(),
+            Some(provider.to_string()),
+            name.to_string(),
+        )),
+        _ => None,
+    }
+}
+
+pub fn find_usdt_probe(probe: &str, pid: Option<u32>) -> Option<UsdtProbe> {
+    let (path, provider, name) = parse_usdt(probe)?;
+    let binary = process_binary(&path, pid)?;
+    binary
+        .usdt
+        .iter()
+        .find(|p| p.name == name && provider.as_ref().is_none_or(|prov| *prov == p.provider))
+        .cloned()
+}
+
+// Argument spec in words, e.g. "signed 32-bit in %edi" for -4@%edi
+pub fn arg_spec_description(spec: &str) -> String {
+    let Some((size, location)) = spec.split_once('@') else {
+        return spec.to_string();
+    };
+    let (sign, size) = match size.strip_prefix('-') {
+        Some(size) => ("signed", size),
+        None => ("unsigned", size),
+    };
+    let bits = size.parse::<u32>().unwrap_or_default() * 8;
+
+    let location = if location.starts_with('%') {
+        format!("in {}", location)
+    } else if let Some(value) = location.strip_prefix('$') {
+        format!("constant {}", value)
+    } else {
+        format!("at {}", location)
+    };
+    format!("{} {}-bit {}", sign, bits, location)
+}
+
+pub fn usdt_args_str(usdt: &UsdtProbe) -> String {
+    let mut s = String::new();
+    for (i, spec) in usdt.args.iter().enumerate() {
+        s.push_str(&format!(
+            "arg{}: {:<12} /* {} */\n",
+            i,
+            spec,
+            arg_spec_description(spec)
+        ));
+    }
+    s
+}
+
+pub fn usdt_hover_str(probe: &str, usdt: &UsdtProbe) -> String {
+    if usdt.args.is_empty() {
+        return format!("{}:\nno arguments", probe);
+    }
+    format!(
+        "{} has {} arguments:\n```c\n{}```",
+        probe,
+        usdt.args.len(),
+        usdt_args_str(usdt)
+    )
+}
+
+fn check_node(
+    node: Node,
+    text: &str,
+    pid: Option<u32>,
+    probes: &[(String, UsdtProbe)],
+    diagnostics: &mut json::JsonValue,
+) {
+    // argN is parsed as plain identifier inside expressions, but field names are identifiers too
+    let is_field = node
+        .parent()
+        .is_some_and(|parent| ["field_expression", "offsetof_expression"].contains(&parent.kind()));
+    if node.kind() == "argn_identifier" || (node.kind() == "identifier" && !is_field) {
+        let arg = node.utf8_text(text.as_bytes()).unwrap_or_default();
+        let Some(n) = arg
+            .strip_prefix("arg")
+            .and_then(|n| n.parse::<usize>().ok())
+        else {
+            return;
+        };
+        if let Some((probe, usdt)) = probes.iter().find(|(_, usdt)| n >= usdt.args.len()) {
+            let message = format!(
+                "{} has {} arguments, {} is out of range",
+                probe,
+                usdt.args.len(),
+                arg
+            );
+            let _ = diagnostics.push(encode_diag(&node, text, SEVERITY_ERROR, message));
+        }
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == "action" {
+            let probes: Vec<(String, UsdtProbe)> = parser::find_probes_for_action(&child, text)
+                .into_iter()
+                .filter_map(|probe| find_usdt_probe(&probe, pid).map(|usdt| (probe, usdt)))
+                .collect();
+            check_node(child, text, pid, &probes, diagnostics);
+        } else {
+            check_node(child, text, pid, probes, diagnostics);
+        }
+    }
+}
+
+// Diagnostics for argN of USDT probes with less arguments
+pub fn check_tree(root: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+    let pid = proc_mod::target_pid(text);
+    check_node(root, text, pid, &[], &mut diagnostics);
+    diagnostics
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::check_mod;
+    use crate::elf_mod::tests::{build_elf, stapsdt_note};
+    use std::fs;
+    use std::path::PathBuf;
+
+    // ELF file with USDT probe server:request which has two arguments, removed when dropped
+    pub struct UsdtLibrary(PathBuf);
+
+    impl UsdtLibrary {
+        pub fn new(name: &str) -> UsdtLibrary {
+            let path = std::env::temp_dir().join(format!(
+                "bpftrace_ls_{}_{}.so",
+                name,
+                std::process::id()
+            ));
+            let note = stapsdt_note("server", "request", "8@%rdi 8@%rsi");
+            fs::write(&path, build_elf(&[(".note.stapsdt", &note)])).unwrap();
+            UsdtLibrary(path)
+        }
+
+        pub fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for UsdtLibrary {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_parse_usdt() {
+        assert_eq!(
+            parse_usdt("usdt:/bin/app:server:start"),
+            Some((
+                "/bin/app".to_string(),
+                Some("server".to_string()),
+                "start".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_usdt("U:/bin/app:start"),
+            Some(("/bin/app".to_string(), None, "start".to_string()))
+        );
+        assert_eq!(parse_usdt("usdt:/bin/app:*"), None);
+
+        assert_eq!(arg_spec_description("-4@%edi"), "signed 32-bit in %edi");
+        assert_eq!(
+            arg_spec_description("8@-80(%rbx)"),
+            "unsigned 64-bit at -80(%rbx)"
+        );
+        assert_eq!(arg_spec_description("1@$5"), "unsigned 8-bit constant 5");
+    }
+
+    #[test]
+    fn test_usdt_args_check() {
+        let lib = UsdtLibrary::new("args_check");
+
+        let probe = format!("usdt:{}:server:request", lib.path());
+        assert_eq!(find_usdt_probe(&probe, None).unwrap().args.len(), 2);
+
+        let text = format!("{} {{ print(arg1); print(arg2); }}", probe);
+        let tree = check_mod::parse(&text).unwrap();
+        let messages: Vec<String> = check_tree(tree.root_node(), &text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![format!("{} has 2 arguments, arg2 is out of range", probe)]
+        );
+
+        // Member named like argument
+        let text = format!(
+            "{} {{ $s = (struct foo *)arg0; print($s->arg5); print(offsetof(struct foo, arg5)); }}",
+            probe
+        );
+        let tree = check_mod::parse(&text).unwrap();
+        assert!(check_tree(tree.root_node(), &text).is_empty());
+    }
+}
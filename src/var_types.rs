@@ -0,0 +1,465 @@
+use std::collections::HashMap;
+
+use tree_sitter::{Node, Point};
+
+use crate::btf_mod::{
+    btf_find_struct_id, btf_iterate_over_type_chain, btf_setup_module, ResolvedVariable,
+};
+use crate::check_mod::{self, byte_column, encode_diag, node_text, SEVERITY_ERROR};
+use crate::completion::{find_kfunc_list_arguments, resolve_args_type};
+use crate::parser;
+
+// Type of scratch variable, inferred from its assignments
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarType {
+    // Same as type_vec of ResolvedBtfItem, e.g. ["struct", "sock", "*"]
+    pub type_vec: Vec<String>,
+    // BTF module the type belongs to
+    pub module: String,
+}
+
+impl VarType {
+    // From C type of cast or declaration, e.g. "struct sock *"
+    pub fn from_c_type(type_str: &str, module: &str) -> VarType {
+        let mut type_vec: Vec<String> = Vec::new();
+        let words = type_str.replace('*', " * ");
+        for word in words.split_whitespace() {
+            match type_vec.last_mut() {
+                Some(last) if word == "*" && last.ends_with('*') => last.push('*'),
+                _ => type_vec.push(word.to_string()),
+            }
+        }
+        VarType {
+            type_vec,
+            module: module.to_string(),
+        }
+    }
+
+    pub fn type_str(&self) -> String {
+        let type_vec: Vec<&str> = self
+            .type_vec
+            .iter()
+            .map(|t| t.as_str())
+            .filter(|t| !t.is_empty())
+            .collect();
+        type_vec.join(" ")
+    }
+
+    // Struct or union the variable is or points to and if it is a pointer
+    pub fn struct_target(&self) -> Option<(String, bool)> {
+        let pos = self
+            .type_vec
+            .iter()
+            .position(|t| t == "struct" || t == "union")?;
+        let name = self.type_vec.get(pos + 1)?;
+        let rest = &self.type_vec[pos + 2..];
+        if rest.iter().any(|t| t.contains('[')) {
+            return None;
+        }
+        let pointers: usize = rest.iter().map(|t| t.matches('*').count()).sum();
+        (pointers <= 1).then(|| (name.clone(), pointers == 1))
+    }
+}
+
+// Member accesses on the variable, e.g. "->sk_socket->"
+pub fn resolve_var_chain(var: &VarType, chain: &str) -> Option<ResolvedVariable> {
+    let (name, is_pointer) = var.struct_target()?;
+    let btf = btf_setup_module(&var.module)?;
+    let id = btf_find_struct_id(&btf, &name)?;
+    btf_iterate_over_type_chain(&btf, id, is_pointer, chain)
+}
+
+struct InferContext<'a> {
+    text: &'a str,
+    probes: &'a [String],
+    module: String,
+    vars: HashMap<String, VarType>,
+}
+
+// Innermost expression of member access chain, e.g. $s for $s->sk_socket->file
+pub fn field_expression_base<'t>(node: &Node<'t>) -> Node<'t> {
+    let mut base = *node;
+    while base.kind() == "field_expression" {
+        let mut cursor = base.walk();
+        let Some(argument) = base
+            .children_by_field_name("argument", &mut cursor)
+            .find(|n| n.is_named())
+        else {
+            break;
+        };
+        base = argument;
+    }
+    base
+}
+
+fn first_named_field<'t>(node: &Node<'t>, field: &str) -> Option<Node<'t>> {
+    let mut cursor = node.walk();
+    let first = node
+        .children_by_field_name(field, &mut cursor)
+        .find(|n| n.is_named());
+    first
+}
+
+fn infer_type(node: &Node, ctx: &InferContext) -> Option<VarType> {
+    match node.kind() {
+        "cast_expression" => {
+            let type_node = node.child_by_field_name("type")?;
+            Some(VarType::from_c_type(
+                node_text(&type_node, ctx.text),
+                &ctx.module,
+            ))
+        }
+        "identifier" if node_text(node, ctx.text) == "curtask" => {
+            Some(VarType::from_c_type("struct task_struct *", "vmlinux"))
+        }
+        // Integer literals are int64 in bpftrace
+        "integer_literal" => Some(VarType::from_c_type("int64", &ctx.module)),
+        "scratch_variable" => ctx.vars.get(node_text(node, ctx.text)).cloned(),
+        "parenthesized_expression" => infer_type(&node.named_child(0)?, ctx),
+        "args_keyword" | "retval_identifier" | "field_expression" => {
+            let expr = node_text(node, ctx.text);
+            if expr.starts_with("args") || expr.starts_with("retval") {
+                let type_vec = resolve_args_type(ctx.probes, expr)?;
+                return Some(VarType {
+                    type_vec,
+                    module: ctx.module.clone(),
+                });
+            }
+
+            let base = field_expression_base(node);
+            let base_type = infer_type(&base, ctx)?;
+            let chain: String = ctx.text[base.end_byte()..node.end_byte()]
+                .chars()
+                .filter(|c| !c.is_whitespace())
+                .collect();
+            let resolved = resolve_var_chain(&base_type, &chain)?;
+            Some(VarType {
+                type_vec: resolved.var.type_vec,
+                module: base_type.module,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn record_assignment(node: &Node, ctx: &mut InferContext) {
+    let (var, value, declared_type) = match node.kind() {
+        "assignment_statement" => (
+            node.child_by_field_name("left"),
+            first_named_field(node, "right"),
+            None,
+        ),
+        "declaration_statement" => (
+            node.child_by_field_name("name"),
+            first_named_field(node, "value"),
+            node.child_by_field_name("type"),
+        ),
+        _ => return,
+    };
+    let Some(var) = var.filter(|v| v.kind() == "scratch_variable") else {
+        return;
+    };
+    let name = node_text(&var, ctx.text);
+    if ctx.vars.contains_key(name) {
+        return;
+    }
+
+    let var_type = match declared_type {
+        Some(t) => Some(VarType::from_c_type(node_text(&t, ctx.text), &ctx.module)),
+        None => value.and_then(|value| infer_type(&value, ctx)),
+    };
+    if let Some(var_type) = var_type {
+        ctx.vars.insert(name.to_string(), var_type);
+    }
+}
+
+fn contains_point(node: &Node, point: Point) -> bool {
+    node.start_position() <= point && point <= node.end_position()
+}
+
+// Blocks which do not contain the position are out of scope, without position all are visible
+fn collect_assignments(node: &Node, point: Option<Point>, ctx: &mut InferContext) {
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        match child.kind() {
+            "block" if point.is_some_and(|point| !contains_point(&child, point)) => {}
+            "assignment_statement" | "declaration_statement" => record_assignment(&child, ctx),
+            _ => collect_assignments(&child, point, ctx),
+        }
+    }
+}
+
+fn probes_module(probes: &[String]) -> String {
+    find_kfunc_list_arguments(probes, false)
+        .map(|(module, _)| module)
+        .unwrap_or("vmlinux".to_string())
+}
+
+fn var_types_for_action(
+    action: &Node,
+    text: &str,
+    probes: &[String],
+    point: Option<Point>,
+) -> HashMap<String, VarType> {
+    let mut ctx = InferContext {
+        text,
+        probes,
+        module: probes_module(probes),
+        vars: HashMap::new(),
+    };
+
+    // Types are flow-insensitive, variable can be typed from a variable assigned later in text
+    loop {
+        let typed = ctx.vars.len();
+        collect_assignments(action, point, &mut ctx);
+        if ctx.vars.len() == typed {
+            break;
+        }
+    }
+    ctx.vars
+}
+
+// Types of scratch variables visible at the position in action block, char_nr is in UTF-16
+// code units as sent by client
+pub fn find_var_types(
+    action: &Node,
+    text: &str,
+    line_nr: usize,
+    char_nr: usize,
+) -> HashMap<String, VarType> {
+    let probes = parser::find_probes_for_action(action, text);
+    let line = text.split('\n').nth(line_nr).unwrap_or_default();
+    let point = Point::new(line_nr, byte_column(line, char_nr));
+    var_types_for_action(action, text, &probes, Some(point))
+}
+
+// Type of scratch variable at its use, from assignments in the action block
+pub fn var_type_at(var: &Node, text: &str) -> Option<VarType> {
+    let mut action = var.parent();
+    while let Some(node) = action.filter(|node| node.kind() != "action") {
+        action = node.parent();
+    }
+    let action = action?;
+    let probes = parser::find_probes_for_action(&action, text);
+    let mut vars = var_types_for_action(&action, text, &probes, Some(var.start_position()));
+    vars.remove(node_text(var, text))
+}
+
+fn find_node_of_kind<'t>(node: Node<'t>, kind: &str) -> Option<Node<'t>> {
+    if node.kind() == kind {
+        return Some(node);
+    }
+    let mut cursor = node.walk();
+    let found = node
+        .named_children(&mut cursor)
+        .find_map(|child| find_node_of_kind(child, kind));
+    found
+}
+
+// Type of expression which is not in the syntax tree yet, e.g. "((struct sock *)arg0)" in a
+// line which is being typed
+pub fn infer_expr_type(
+    expr: &str,
+    probes: &[String],
+    vars: &HashMap<String, VarType>,
+) -> Option<VarType> {
+    let script = format!("begin {{ $__expr = {}; }}", expr);
+    let tree = check_mod::parse(&script)?;
+    let assignment = find_node_of_kind(tree.root_node(), "assignment_statement")?;
+    let value = first_named_field(&assignment, "right")?;
+
+    let ctx = InferContext {
+        text: &script,
+        probes,
+        module: probes_module(probes),
+        vars: vars.clone(),
+    };
+    infer_type(&value, &ctx)
+}
+
+// Check outermost member access chain on scratch variable, stop at the first wrong member
+fn check_field_expression(
+    node: &Node,
+    vars: &HashMap<String, VarType>,
+    text: &str,
+    diagnostics: &mut json::JsonValue,
+) {
+    let base = field_expression_base(node);
+    if base.kind() != "scratch_variable" {
+        return;
+    }
+    let Some(var_type) = vars.get(node_text(&base, text)) else {
+        return;
+    };
+    // Structs defined in the script or missing in BTF can't be checked
+    let Some((name, _)) = var_type.struct_target() else {
+        return;
+    };
+    let in_btf = btf_setup_module(&var_type.module)
+        .is_some_and(|btf| btf_find_struct_id(&btf, &name).is_some());
+    if !in_btf {
+        return;
+    }
+
+    // From innermost access to the outermost
+    let mut levels = Vec::new();
+    let mut level = base.parent();
+    while let Some(l) = level.filter(|l| l.kind() == "field_expression") {
+        levels.push(l);
+        if l == *node {
+            break;
+        }
+        level = l.parent();
+    }
+
+    for level in levels {
+        let chain: String = text[base.end_byte()..level.end_byte()]
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        if chain.contains('[') || resolve_var_chain(var_type, &chain).is_some() {
+            continue;
+        }
+
+        let Some(field) = level.child_by_field_name("field") else {
+            return;
+        };
+        let field_name = node_text(&field, text);
+        let parent_chain = &chain[..chain.len() - field_name.len()];
+        let (swapped, op) = if let Some(prefix) = parent_chain.strip_suffix("->") {
+            (format!("{}.{}", prefix, field_name), ".")
+        } else {
+            let prefix = parent_chain.strip_suffix('.').unwrap_or(parent_chain);
+            (format!("{}->{}", prefix, field_name), "->")
+        };
+
+        let expr = format!(
+            "{}{}",
+            node_text(&base, text),
+            &parent_chain[..parent_chain.len() - if op == "." { 2 } else { 1 }]
+        );
+        let message = if resolve_var_chain(var_type, &swapped).is_some() {
+            format!("Use {} to access {} of {}", op, field_name, expr)
+        } else {
+            let parent_type = resolve_var_chain(var_type, parent_chain)
+                .map(|parent| {
+                    parent
+                        .var
+                        .type_vec
+                        .join(" ")
+                        .replace(" *", "")
+                        .trim()
+                        .to_string()
+                })
+                .unwrap_or(var_type.type_str());
+            format!("{} has no member {}", parent_type, field_name)
+        };
+        let _ = diagnostics.push(encode_diag(&field, text, SEVERITY_ERROR, message));
+        return;
+    }
+}
+
+// Variables are typed once per action, from all its assignments
+fn check_node(
+    node: Node,
+    vars: Option<&HashMap<String, VarType>>,
+    text: &str,
+    diagnostics: &mut json::JsonValue,
+) {
+    let is_outermost = node.kind() == "field_expression"
+        && node
+            .parent()
+            .is_none_or(|parent| parent.kind() != "field_expression");
+    if let Some(vars) = vars.filter(|_| is_outermost) {
+        check_field_expression(&node, vars, text, diagnostics);
+        return;
+    }
+
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == "action" {
+            let probes = parser::find_probes_for_action(&child, text);
+            let vars = var_types_for_action(&child, text, &probes, None);
+            check_node(child, Some(&vars), text, diagnostics);
+        } else {
+            check_node(child, vars, text, diagnostics);
+        }
+    }
+}
+
+// Diagnostics for member accesses on typed scratch variables
+pub fn check_tree(root: Node, text: &str) -> json::JsonValue {
+    let mut diagnostics = json::JsonValue::new_array();
+    check_node(root, None, text, &mut diagnostics);
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::check_mod;
+
+    fn var_types(text: &str, line_nr: usize, char_nr: usize) -> HashMap<String, String> {
+        let tree = check_mod::parse(text).unwrap();
+        let action = tree.root_node().child(0).unwrap().child(1).unwrap();
+        assert_eq!(action.kind(), "action");
+        find_var_types(&action, text, line_nr, char_nr)
+            .into_iter()
+            .map(|(name, var)| (name, var.type_str()))
+            .collect()
+    }
+
+    fn check(text: &str) -> Vec<String> {
+        let tree = check_mod::parse(text).unwrap();
+        check_tree(tree.root_node(), text)
+            .members()
+            .map(|diag| diag["message"].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_var_types() {
+        let text = "fentry:tcp_sendmsg {\n  $t = curtask;\n  $p = $t->real_parent;\n  $s = (struct sock *)arg0;\n  $k = args.sk;\n  $f = $k->sk_socket->file;\n  let $u: struct mm_struct * = 0;\n  if (1) { $in = $s; }\n}";
+        let vars = var_types(text, 1, 2);
+        assert_eq!(vars["$t"], "struct task_struct *");
+        assert_eq!(vars["$p"], "struct task_struct *");
+        assert_eq!(vars["$s"], "struct sock *");
+        assert_eq!(vars["$k"], "struct sock *");
+        assert_eq!(vars["$f"], "struct file *");
+        assert_eq!(vars["$u"], "struct mm_struct *");
+        assert!(!vars.contains_key("$in"));
+
+        let vars = var_types(text, 7, 12);
+        assert_eq!(vars["$in"], "struct sock *");
+
+        // Variable typed by a variable assigned later
+        let text = "kprobe:do_nanosleep { $p = $t->real_parent; $t = curtask; }";
+        assert_eq!(var_types(text, 0, 0)["$p"], "struct task_struct *");
+
+        // Column is in UTF-16 code units, 'ž' in the comment takes two bytes
+        let text = "kprobe:do_nanosleep {\n  /* žžžžžžžžžž */ if (1) { $t = curtask; } }";
+        assert!(var_types(text, 1, 30).contains_key("$t"));
+        assert!(!var_types(text, 1, 45).contains_key("$t"));
+
+        let var = VarType::from_c_type("struct sock**", "vmlinux");
+        assert_eq!(var.type_str(), "struct sock **");
+        assert_eq!(var.struct_target(), None);
+    }
+
+    #[test]
+    fn test_member_diagnostics() {
+        let text = "fentry:tcp_sendmsg { $k = args.sk; print($k->sk_socket->file->f_mode); print($k->sk_socket.file); print($k->__sk_common->skc_family); print($k->no_such); }";
+        assert_eq!(
+            check(text),
+            vec![
+                "Use -> to access file of $k->sk_socket",
+                "Use . to access skc_family of $k->__sk_common",
+                "struct sock has no member no_such"
+            ]
+        );
+
+        // Struct defined in the script, not in BTF
+        let text = "struct foo { int bar; }\nkprobe:do_nanosleep { $f = (struct foo *)arg0; print($f->bar); }";
+        assert!(check(text).is_empty());
+    }
+}